@@ -0,0 +1,76 @@
+//! Handles the CLI command that steps through bytecode interactively
+
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+
+use clap;
+use fvm::debugger::{Breakpoint, Command, Debugger};
+use fvm::vm::VM;
+
+use disassembler::{decode_hex, read_bytecode};
+
+/// Loads the bytecode given to the debug command and runs the debugging prompt until the user
+/// quits or input ends
+pub fn handle_cli_command(matches: &clap::ArgMatches) {
+    let path = matches.value_of("INPUT").expect("INPUT is a required argument");
+    let code = read_bytecode(path, matches.is_present("binary"));
+    let mut vm = VM::new(code);
+    if let Some(calldata) = matches.value_of("calldata") {
+        match decode_hex(calldata.as_bytes()) {
+            Some(calldata) => vm = vm.with_calldata(calldata),
+            None => {
+                error!("Calldata must be given as hex");
+                exit(1);
+            }
+        }
+    }
+    if let Some(gas_limit) = matches.value_of("gas-limit") {
+        match gas_limit.parse() {
+            Ok(gas_limit) => vm = vm.with_gas_limit(gas_limit),
+            Err(_) => {
+                error!("Invalid gas limit: {}", gas_limit);
+                exit(1);
+            }
+        }
+    }
+
+    let mut debugger = Debugger::new(vm);
+    for breakpoint in matches.values_of("break").into_iter().flatten() {
+        match breakpoint.parse::<Breakpoint>() {
+            Ok(breakpoint) => {
+                debugger.add_breakpoint(breakpoint);
+            }
+            Err(e) => {
+                error!("{}", e);
+                exit(1);
+            }
+        }
+    }
+
+    print!("{}", debugger.info());
+    let stdin = io::stdin();
+    let mut last = Command::Step(1);
+    loop {
+        print!("(fvm) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        // An empty line repeats the last command, as in gdb
+        let command = if line.trim().is_empty() {
+            Ok(last.clone())
+        } else {
+            line.parse::<Command>()
+        };
+        match command {
+            Ok(Command::Quit) => break,
+            Ok(command) => {
+                print!("{}", debugger.run(&command));
+                last = command;
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Handles the CLI command that disassembles bytecode
+
+use std::fs;
+use std::process::exit;
+use std::str;
+
+use clap;
+use fvm::disassembler;
+use rustc_serialize::hex::FromHex;
+
+/// Prints a listing of the bytecode in the file given to the disassemble command
+pub fn handle_cli_command(matches: &clap::ArgMatches) {
+    let path = matches.value_of("INPUT").expect("INPUT is a required argument");
+    let code = read_bytecode(path, matches.is_present("binary"));
+    print!("{}", disassembler::listing(&code));
+}
+
+/// Reads bytecode from the file at `path`, exiting if it cannot be read. Unless `binary` is set,
+/// a file holding hex is decoded, so both solc .bin files and raw bytecode can be given.
+pub fn read_bytecode(path: &str, binary: bool) -> Vec<u8> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Unable to read {}: {:?}", path, e);
+            exit(1);
+        }
+    };
+    if binary {
+        return contents;
+    }
+    match decode_hex(&contents) {
+        Some(code) => code,
+        None => contents,
+    }
+}
+
+/// Decodes hex such as the contents of a solc .bin file, with or without a 0x prefix. Returns None
+/// if the input is not hex.
+pub fn decode_hex(contents: &[u8]) -> Option<Vec<u8>> {
+    let text = str::from_utf8(contents).ok()?.trim();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    text.from_hex().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_hex_input() {
+        assert_eq!(decode_hex(b"0x6080\n"), Some(vec![0x60, 0x80]));
+        assert_eq!(decode_hex(b"6080"), Some(vec![0x60, 0x80]));
+        assert_eq!(decode_hex(&[0x60, 0x80]), None);
+    }
+}
@@ -42,8 +42,11 @@ use hmac::Hmac;
 use sha2::Sha256;
 
 pub mod accounts;
+pub mod debugger;
+pub mod disassembler;
 pub mod keys;
 pub mod servers;
+pub mod transaction_test;
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -54,6 +57,21 @@ pub fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
 
+    // Commands that only run or inspect bytecode need none of the client's data, so they are handled
+    // first
+    if let Some(disassemble_matches) = matches.subcommand_matches("disassemble") {
+        disassembler::handle_cli_command(disassemble_matches);
+        exit(0);
+    }
+    if let Some(debug_matches) = matches.subcommand_matches("debug") {
+        debugger::handle_cli_command(debug_matches);
+        exit(0);
+    }
+    if let Some(transaction_matches) = matches.subcommand_matches("transaction-test") {
+        transaction_test::handle_cli_command(transaction_matches);
+        exit(0);
+    }
+
     // Setup data directories if not present
     let base_dir = matches
         .value_of("data-directory")
@@ -0,0 +1,195 @@
+//! Handles the CLI command that runs bytecode as a transaction and reports what it did
+
+use std::collections::BTreeMap;
+use std::process::exit;
+use std::str::FromStr;
+
+use bigint::{Address, H256, U256};
+use clap;
+use fvm::environment::{BlockContext, Environment, TransactionContext};
+use fvm::fork::Fork;
+use fvm::outcome::{ExecutionResult, Outcome};
+use fvm::state::State;
+use fvm::vm::VM;
+use rustc_serialize::hex::ToHex;
+use serde_json;
+
+use disassembler::{decode_hex, read_bytecode};
+
+/// What running the transaction did, as printed with --json
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report {
+    outcome: String,
+    return_data: String,
+    gas_used: u64,
+    gas_refunded: u64,
+    logs: Vec<LogReport>,
+    /// Non-zero storage slots by account, once execution has finished
+    storage: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize)]
+struct LogReport {
+    address: String,
+    topics: Vec<String>,
+    data: String,
+}
+
+/// Runs the bytecode given to the transaction-test command and prints the result
+pub fn handle_cli_command(matches: &clap::ArgMatches) {
+    let path = matches.value_of("INPUT").expect("INPUT is a required argument");
+    let code = read_bytecode(path, matches.is_present("binary"));
+    let address =
+        parse_or_exit(matches, "address", |value| Address::from_str(strip_0x(value)).ok()).unwrap_or_default();
+    let sender = parse_or_exit(matches, "sender", |value| Address::from_str(strip_0x(value)).ok()).unwrap_or_default();
+    let value = parse_or_exit(matches, "value", parse_u256).unwrap_or_default();
+    let calldata = parse_or_exit(matches, "calldata", |value| decode_hex(value.as_bytes())).unwrap_or_default();
+
+    // The contract starts out holding the value sent to it, as if the transfer had already happened
+    let state = State::new().with_account(address, value, code.clone());
+    let transaction = TransactionContext {
+        origin: sender,
+        caller: sender,
+        value,
+        gas_price: U256::zero(),
+    };
+    let mut vm = VM::new(code)
+        .with_address(address)
+        .with_host(state)
+        .with_calldata(calldata)
+        .with_environment(Environment::new(transaction, BlockContext::default()));
+    if let Some(gas_limit) = parse_or_exit(matches, "gas-limit", |value| value.parse().ok()) {
+        vm = vm.with_gas_limit(gas_limit);
+    }
+    if let Some(fork) = parse_or_exit(matches, "fork", |value| Fork::from_str(value).ok()) {
+        vm = vm.with_fork(fork);
+    }
+
+    let result = vm.execute();
+    let report = report(&result, vm.host());
+    if matches.is_present("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("a report only holds strings and numbers")
+        );
+    } else {
+        print_report(&report);
+    }
+}
+
+// Parses the value of the option `name` with `parse`, exiting with an error if it is invalid
+fn parse_or_exit<T, F>(matches: &clap::ArgMatches, name: &str, parse: F) -> Option<T>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    let value = matches.value_of(name)?;
+    match parse(value) {
+        Some(parsed) => Some(parsed),
+        None => {
+            error!("Invalid {}: {}", name, value);
+            exit(1);
+        }
+    }
+}
+
+fn parse_u256(value: &str) -> Option<U256> {
+    match value.strip_prefix("0x") {
+        Some(hex) => U256::from_str(hex).ok(),
+        None => U256::from_dec_str(value).ok(),
+    }
+}
+
+fn strip_0x(value: &str) -> &str {
+    value.strip_prefix("0x").unwrap_or(value)
+}
+
+fn report(result: &ExecutionResult, state: &State) -> Report {
+    let outcome = match result.outcome {
+        Outcome::Stop => "stop".to_string(),
+        Outcome::Return(_) => "return".to_string(),
+        Outcome::Revert(_) => "revert".to_string(),
+        Outcome::Halt(ref e) => format!("halt: {}", e),
+    };
+    let logs = result
+        .logs
+        .iter()
+        .map(|log| LogReport {
+            address: format!("0x{:x}", log.address),
+            topics: log.topics.iter().map(|topic| to_hex(topic)).collect(),
+            data: to_hex(&log.data),
+        })
+        .collect();
+    let mut storage = BTreeMap::new();
+    for address in state.addresses() {
+        let slots: BTreeMap<String, String> = state
+            .account(address)
+            .map(|account| account.storage())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(index, value)| (to_hex(&H256::from(index)), to_hex(&H256::from(value))))
+            .collect();
+        if !slots.is_empty() {
+            storage.insert(format!("0x{:x}", address), slots);
+        }
+    }
+    Report {
+        outcome,
+        return_data: to_hex(result.output()),
+        gas_used: result.gas_used,
+        gas_refunded: result.gas_refunded,
+        logs,
+        storage,
+    }
+}
+
+fn print_report(report: &Report) {
+    println!("Outcome: {}", report.outcome);
+    println!("Return data: {}", report.return_data);
+    println!("Gas used: {} ({} refunded)", report.gas_used, report.gas_refunded);
+    println!("Logs:");
+    for log in &report.logs {
+        println!("  {} topics [{}] data {}", log.address, log.topics.join(", "), log.data);
+    }
+    println!("Storage:");
+    for (address, slots) in &report.storage {
+        println!("  {}", address);
+        for (index, value) in slots {
+            println!("    {}: {}", index, value);
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", bytes.to_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigint::M256;
+
+    #[test]
+    fn reports_storage_and_output() {
+        let address = Address::from(0x100);
+        let state = State::new().with_storage(address, U256::from(1), M256::from(0x2a));
+        let result = ExecutionResult {
+            outcome: Outcome::Return(vec![0xab]),
+            gas_used: 21,
+            gas_refunded: 0,
+            logs: vec![],
+        };
+        let report = report(&result, &state);
+        assert_eq!(report.outcome, "return");
+        assert_eq!(report.return_data, "0xab");
+        let slots = &report.storage[&format!("0x{:x}", address)];
+        assert_eq!(slots[&format!("0x{:064x}", 1)], format!("0x{:064x}", 0x2a));
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(parse_u256("0x10"), Some(U256::from(16)));
+        assert_eq!(parse_u256("10"), Some(U256::from(10)));
+        assert_eq!(parse_u256("ten"), None);
+    }
+}
@@ -0,0 +1,36 @@
+//! Timing and reporting shared by the benchmarks
+
+// Not every benchmark uses all of it
+#![allow(dead_code)]
+
+use std::time::{Duration, Instant};
+
+/// Returns the average time `f` takes over `iterations` runs, taking the best of a few rounds to
+/// keep noise from other processes out
+pub fn time<F: FnMut()>(iterations: u32, mut f: F) -> Duration {
+    // One untimed run first, so that allocation and caches settle
+    f();
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                f();
+            }
+            start.elapsed() / iterations
+        })
+        .min()
+        .expect("there is at least one round")
+}
+
+/// Prints the time a workload took two ways, and how many times faster the second was
+pub fn report(name: &str, first: (&str, Duration), second: (&str, Duration)) {
+    println!(
+        "{:<48} {} {:>10.1?}  {} {:>10.1?}  speedup {:.2}x",
+        name,
+        first.0,
+        first.1,
+        second.0,
+        second.1,
+        first.1.as_secs_f64() / second.1.as_secs_f64()
+    );
+}
@@ -0,0 +1,125 @@
+//! Measures how fast the VM interprets code. Every workload is run twice, giving each VM a fresh
+//! analysis cache so that the code is analyzed on every run, and then sharing one cache between
+//! the VMs, as when every transaction of a block calls the same contract. Run with:
+//!
+//! ```text
+//! cargo bench -p fvm --bench interpreter
+//! ```
+
+extern crate bigint;
+extern crate fvm;
+
+mod common;
+
+use bigint::{Address, U256};
+use common::{report, time};
+use fvm::analysis::AnalysisCache;
+use fvm::assembler::assemble;
+use fvm::state::State;
+use fvm::vm::VM;
+use std::hint::black_box;
+
+fn main() {
+    arithmetic_loop();
+    push_heavy_code();
+    repeated_calls();
+}
+
+/// Runs `code` as that of an account in `state`, `iterations` times with a fresh analysis cache
+/// and with a shared one, and prints the average time each took
+fn compare(name: &str, iterations: u32, code: Vec<u8>, state: State) {
+    let address = Address::from(0xc0de);
+    let state = state.with_account(address, U256::zero(), code.clone());
+    let run = |cache: AnalysisCache| {
+        let result = VM::new(code.clone())
+            .with_address(address)
+            .with_host(state.clone())
+            .with_analysis_cache(cache)
+            .with_gas_limit(u64::MAX / 2)
+            .execute();
+        assert!(result.is_success(), "the benchmark contract runs to completion");
+        black_box(result);
+    };
+    let fresh = time(iterations, || run(AnalysisCache::new()));
+    let cache = AnalysisCache::new();
+    let shared = time(iterations, || run(cache.clone()));
+    report(name, ("fresh cache", fresh), ("shared cache", shared));
+}
+
+/// A tight loop of stack and arithmetic instructions, as in hashing or math libraries
+fn arithmetic_loop() {
+    let code = assemble(
+        "
+            PUSH2 10000
+            PUSH1 1
+        loop:
+            JUMPDEST
+            DUP1
+            PUSH1 3
+            MUL
+            PUSH1 7
+            ADD
+            PUSH1 0xff
+            AND
+            ADD
+            SWAP1
+            PUSH1 1
+            SWAP1
+            SUB
+            SWAP1
+            DUP2
+            PUSH loop
+            JUMPI
+            STOP
+        ",
+    )
+    .expect("the benchmark contract assembles");
+    compare("arithmetic loop, 10000 iterations", 20, code, State::new());
+}
+
+/// Straight-line code that pushes and pops 32 byte constants, as in code full of hashes and masks,
+/// close to the 24 KiB limit on the size of a contract
+fn push_heavy_code() {
+    let mut source = String::new();
+    for i in 0..700 {
+        let byte = format!("{:02x}", i % 255 + 1);
+        source.push_str(&format!("PUSH32 0x{}\nPOP\n", byte.repeat(32)));
+    }
+    let code = assemble(&source).expect("the benchmark contract assembles");
+    compare("700 PUSH32 and POP", 200, code, State::new());
+}
+
+/// A contract that calls a large contract 200 times, where every call does very little
+fn repeated_calls() {
+    let callee = Address::from(0xca11);
+    // Returns at once unless the calldata is non-empty, followed by 16 KiB of code that never runs
+    let mut callee_code = assemble("CALLDATASIZE\nPUSH end\nJUMPI\nSTOP\nend:\nJUMPDEST\n").unwrap();
+    callee_code.extend(vec![0x5b; 16 * 1024]);
+    let caller_code = assemble(&format!(
+        "
+            PUSH1 200
+        loop:
+            JUMPDEST
+            PUSH1 0
+            PUSH1 0
+            PUSH1 0
+            PUSH1 0
+            PUSH1 0
+            PUSH20 0x{:x}
+            GAS
+            CALL
+            POP
+            PUSH1 1
+            SWAP1
+            SUB
+            DUP1
+            PUSH loop
+            JUMPI
+            STOP
+        ",
+        callee
+    ))
+    .expect("the benchmark contract assembles");
+    let state = State::new().with_account(callee, U256::zero(), callee_code);
+    compare("200 calls to a 16 KiB contract", 20, caller_code, state);
+}
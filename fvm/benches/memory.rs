@@ -0,0 +1,124 @@
+//! Compares the implementations of memory on copy-heavy workloads, both through the `Memory` trait
+//! and through the VM. Run with:
+//!
+//! ```text
+//! cargo bench -p fvm --bench memory
+//! ```
+
+extern crate bigint;
+extern crate fvm;
+
+mod common;
+
+use bigint::{M256, U256};
+use common::{report, time};
+use fvm::assembler::assemble;
+use fvm::memory::{Memory, MemoryKind, DEFAULT_MEMORY_LIMIT};
+use fvm::vm::VM;
+use std::hint::black_box;
+use std::time::Duration;
+
+const KINDS: [MemoryKind; 2] = [MemoryKind::Simple, MemoryKind::Fast];
+
+fn main() {
+    let data: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+
+    compare("copy 4 KiB blocks into 1 MiB", 20, |memory| {
+        for block in 0..256u64 {
+            let start = U256::from(block * 4096);
+            memory
+                .copy_into_memory(&data, start, U256::zero(), U256::from(4096))
+                .unwrap();
+        }
+    });
+    compare("copy 4 KiB blocks out of 1 MiB", 20, |memory| {
+        memory.expand(U256::zero(), U256::from(1 << 20)).unwrap();
+        for block in 0..256u64 {
+            let copy = memory
+                .copy_from_memory(U256::from(block * 4096), U256::from(4096))
+                .unwrap();
+            black_box(copy);
+        }
+    });
+    compare("store and load 32k words", 20, |memory| {
+        for word in 0..32768u64 {
+            memory.write(M256::from(word * 32), M256::from(word)).unwrap();
+        }
+        for word in 0..32768u64 {
+            black_box(memory.read(M256::from(word * 32)).unwrap());
+        }
+    });
+    compare("store 64k bytes", 20, |memory| {
+        for byte in 0..65536u64 {
+            memory.write_byte(M256::from(byte), byte as u8).unwrap();
+        }
+    });
+    compare_vm();
+}
+
+/// Runs `workload` against a fresh memory of each kind, `iterations` times, and prints the average
+/// time each took
+fn compare<F>(name: &str, iterations: u32, mut workload: F)
+where
+    F: FnMut(&mut Memory),
+{
+    let times: Vec<Duration> = KINDS
+        .iter()
+        .map(|kind| {
+            time(iterations, || {
+                let mut memory = kind.create(DEFAULT_MEMORY_LIMIT);
+                workload(&mut *memory);
+                black_box(memory.size());
+            })
+        })
+        .collect();
+    report(name, ("simple", times[0]), ("fast", times[1]));
+}
+
+/// Runs a contract that copies 8 KiB of calldata into successive regions of memory and loads the
+/// first word of each region back, with each kind of memory
+fn compare_vm() {
+    let code = assemble(
+        "
+            PUSH2 0
+        loop:
+            JUMPDEST
+            PUSH2 0x2000
+            PUSH1 0
+            DUP3
+            CALLDATACOPY
+            DUP1
+            MLOAD
+            POP
+            PUSH2 0x2000
+            ADD
+            DUP1
+            PUSH3 0x100000
+            GT
+            PUSH loop
+            JUMPI
+            STOP
+        ",
+    )
+    .expect("the benchmark contract assembles");
+    let calldata: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+    let times: Vec<Duration> = KINDS
+        .iter()
+        .map(|kind| {
+            time(20, || {
+                let mut vm = VM::new(code.clone())
+                    .with_calldata(calldata.clone())
+                    .with_gas_limit(u64::MAX / 2)
+                    .with_memory_kind(*kind);
+                let result = vm.execute();
+                assert!(result.is_success(), "the benchmark contract runs to completion");
+                black_box(result);
+            })
+        })
+        .collect();
+    report(
+        "VM: copy 8 KiB of calldata 128 times",
+        ("simple", times[0]),
+        ("fast", times[1]),
+    );
+}
@@ -0,0 +1,69 @@
+//! Measures the precompiled contracts on the vectors under `tests/fixtures/precompiles`, and how
+//! much gas they charge for each second they run. A contract that charges far less than the rest,
+//! or than the interpreter does, is a denial of service risk. Run with:
+//!
+//! ```text
+//! cargo bench -p fvm --bench precompiles
+//! ```
+
+extern crate bigint;
+extern crate fvm;
+extern crate rustc_hex;
+extern crate serde_json;
+
+mod common;
+
+use bigint::Address;
+use common::time;
+use fvm::fork::Fork;
+use fvm::precompiles;
+use rustc_hex::FromHex;
+use serde_json::Value;
+use std::fs;
+use std::hint::black_box;
+use std::time::Duration;
+
+const FIXTURES: &str = "tests/fixtures/precompiles";
+
+fn main() {
+    println!("{:<48} {:>8} {:>12} {:>10}", "vector", "gas", "time", "Mgas/s");
+    run("ecRecover.json", 1, Fork::latest());
+    run("modexp_eip2565.json", 5, Fork::Berlin);
+    run("bn256Add.json", 6, Fork::Istanbul);
+    run("bn256ScalarMul.json", 7, Fork::Istanbul);
+    run("bn256Pairing.json", 8, Fork::Istanbul);
+    run("blake2F.json", 9, Fork::Istanbul);
+}
+
+/// Times the precompiled contract `number` on every vector in `file` that charges gas
+fn run(file: &str, number: u64, fork: Fork) {
+    let contents = fs::read_to_string(format!("{}/{}", FIXTURES, file)).expect("vector files are readable");
+    let vectors: Value = serde_json::from_str(&contents).expect("vector files are valid JSON");
+    for vector in vectors.as_array().expect("vector files hold a list") {
+        let input = bytes(&vector["Input"]);
+        let address = Address::from(number);
+        let (gas, _) = precompiles::execute(address, &input, u64::MAX, fork).expect("the vector succeeds");
+        if gas == 0 {
+            continue;
+        }
+        let elapsed = time(iterations(gas), || {
+            black_box(precompiles::execute(address, black_box(&input), u64::MAX, fork).unwrap());
+        });
+        report(vector["Name"].as_str().expect("vectors are named"), gas, elapsed);
+    }
+}
+
+/// Returns enough iterations to run about 100 million gas worth of calls
+fn iterations(gas: u64) -> u32 {
+    (100_000_000 / gas).clamp(1, 10_000) as u32
+}
+
+fn report(name: &str, gas: u64, time: Duration) {
+    let mgas_per_second = gas as f64 / time.as_secs_f64() / 1e6;
+    println!("{:<48} {:>8} {:>12.1?} {:>10.1}", name, gas, time, mgas_per_second);
+}
+
+fn bytes(value: &Value) -> Vec<u8> {
+    let hex = value.as_str().expect("bytes are given as hex strings");
+    hex.from_hex().expect("bytes are given as hex")
+}
@@ -0,0 +1,63 @@
+//! Module for the accounts and storage slots a transaction has accessed, which are cheaper to access
+//! again from Berlin on (EIP-2929)
+
+use bigint::{Address, U256};
+use std::collections::HashSet;
+
+/// The accounts and storage slots that are warm. Everything else is cold, and the first access to
+/// it costs extra.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    addresses: HashSet<Address>,
+    slots: HashSet<(Address, U256)>,
+}
+
+impl AccessList {
+    /// Creates and returns a new AccessList in which nothing is warm
+    pub fn new() -> AccessList {
+        AccessList {
+            addresses: HashSet::new(),
+            slots: HashSet::new(),
+        }
+    }
+
+    /// Marks `address` as warm. Returns true if it was cold until now.
+    pub fn access_address(&mut self, address: Address) -> bool {
+        self.addresses.insert(address)
+    }
+
+    /// Marks the storage slot `index` of the account at `address` as warm. Returns true if it was
+    /// cold until now.
+    pub fn access_slot(&mut self, address: Address, index: U256) -> bool {
+        self.slots.insert((address, index))
+    }
+
+    /// Marks `address` as cold again, undoing its first access
+    pub fn forget_address(&mut self, address: Address) {
+        self.addresses.remove(&address);
+    }
+
+    /// Marks the storage slot `index` of the account at `address` as cold again, undoing its first
+    /// access
+    pub fn forget_slot(&mut self, address: Address, index: U256) {
+        self.slots.remove(&(address, index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_access_is_cold() {
+        let mut access_list = AccessList::new();
+        let address = Address::random();
+        assert!(access_list.access_address(address));
+        assert!(!access_list.access_address(address));
+        assert!(access_list.access_slot(address, 1.into()));
+        assert!(!access_list.access_slot(address, 1.into()));
+        assert!(access_list.access_slot(address, 2.into()));
+        access_list.forget_address(address);
+        assert!(access_list.access_address(address));
+    }
+}
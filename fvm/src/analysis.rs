@@ -0,0 +1,316 @@
+//! Module for the analysis that is done once on code before it is executed
+
+use bigint::{H256, M256, U256};
+use fork::Fork;
+use gas_prices;
+use opcodes::Opcode;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Bitmap of the offsets in a piece of code that hold a JUMPDEST. A 0x5b byte that is part of the
+/// immediate data of a PUSH is not a valid destination, so the code has to be walked in order to
+/// tell the two apart, which `Analysis::analyze` does as it decodes it.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct JumpDestinations {
+    bitmap: Vec<u64>,
+}
+
+impl JumpDestinations {
+    /// Creates and returns a bitmap with no destinations for code of `len` bytes
+    fn new(len: usize) -> JumpDestinations {
+        JumpDestinations {
+            bitmap: vec![0u64; len.div_ceil(64)],
+        }
+    }
+
+    /// Records the offset `pc` as a JUMPDEST
+    fn insert(&mut self, pc: usize) {
+        self.bitmap[pc / 64] |= 1 << (pc % 64);
+    }
+
+    /// Returns true if `destination` is the offset of a JUMPDEST
+    fn is_valid(&self, destination: M256) -> bool {
+        let destination: U256 = destination.into();
+        if destination >= U256::from(self.bitmap.len() * 64) {
+            return false;
+        }
+        let destination = destination.as_usize();
+        self.bitmap[destination / 64] & (1 << (destination % 64)) != 0
+    }
+}
+
+/// An instruction decoded ahead of execution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    /// Static gas cost under the fork the code was analyzed for, or None if the opcode is not
+    /// defined in that fork
+    pub cost: Option<u32>,
+    /// For the first instruction of a basic block, the static gas cost of the whole block. Zero
+    /// for every other instruction.
+    pub block_gas: u64,
+    /// Index of the value pushed in the immediates of the analysis, for PUSH instructions
+    immediate: u32,
+}
+
+/// Code decoded once into a stream of instructions, with PUSH data parsed into words, the
+/// JUMPDEST table, and the static gas cost of every basic block. A basic block runs from a
+/// JUMPDEST or the instruction after a jump or halt, up to and including the next jump or halt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// Instructions in code order, ending with a STOP for running off the end of the code
+    instructions: Vec<Instruction>,
+    /// Index in `instructions` of the instruction starting at each offset of the code. Offsets
+    /// inside PUSH data are never executed, and hold `u32::MAX`.
+    offsets: Vec<u32>,
+    immediates: Vec<M256>,
+    jump_destinations: JumpDestinations,
+}
+
+impl Analysis {
+    /// Decodes `code`, pricing its instructions by the rules of `fork`
+    pub fn analyze(code: &[u8], fork: Fork) -> Analysis {
+        // Code averages a few bytes per instruction, so this avoids most of the growing
+        let mut instructions = Vec::with_capacity(code.len() / 4 + 1);
+        let mut offsets = vec![u32::MAX; code.len()];
+        let mut immediates = vec![];
+        let mut jump_destinations = JumpDestinations::new(code.len());
+        let mut pc = 0;
+        while pc < code.len() {
+            let opcode = Opcode::from(&code[pc]);
+            let mut immediate = 0;
+            offsets[pc] = instructions.len() as u32;
+            if opcode == Opcode::JUMPDEST {
+                jump_destinations.insert(pc);
+            }
+            pc += 1;
+            if let Opcode::PUSH(bytes) = opcode {
+                immediate = immediates.len() as u32;
+                immediates.push(read_push_data(code, pc, bytes));
+                pc += bytes;
+            }
+            instructions.push(Instruction {
+                opcode,
+                cost: gas_prices::get_cost(opcode, fork).map(|cost| cost as u32),
+                block_gas: 0,
+                immediate,
+            });
+        }
+        instructions.push(Instruction {
+            opcode: Opcode::STOP,
+            cost: Some(0),
+            block_gas: 0,
+            immediate: 0,
+        });
+        sum_block_gas(&mut instructions);
+        Analysis {
+            instructions,
+            offsets,
+            immediates,
+            jump_destinations,
+        }
+    }
+
+    /// Returns the instruction at offset `pc`. Past the end of the code or inside PUSH data, that
+    /// is a STOP.
+    #[inline]
+    pub fn instruction(&self, pc: usize) -> &Instruction {
+        let index = self.offsets.get(pc).map_or(u32::MAX, |&index| index);
+        match self.instructions.get(index as usize) {
+            Some(instruction) => instruction,
+            None => self.instructions.last().expect("analyzed code ends with a STOP"),
+        }
+    }
+
+    /// Returns the value `instruction` pushes, if it is a PUSH
+    #[inline]
+    pub fn immediate(&self, instruction: &Instruction) -> M256 {
+        self.immediates[instruction.immediate as usize]
+    }
+
+    /// Returns true if `destination` is the offset of a JUMPDEST
+    pub fn is_jump_destination(&self, destination: M256) -> bool {
+        self.jump_destinations.is_valid(destination)
+    }
+}
+
+// Records the static gas of each basic block on its first instruction
+fn sum_block_gas(instructions: &mut [Instruction]) {
+    let mut start = 0;
+    let mut gas = 0;
+    for i in 0..instructions.len() {
+        if instructions[i].opcode == Opcode::JUMPDEST && i > start {
+            instructions[start].block_gas = gas;
+            start = i;
+            gas = 0;
+        }
+        gas += u64::from(instructions[i].cost.unwrap_or(0));
+        if ends_block(&instructions[i]) {
+            instructions[start].block_gas = gas;
+            start = i + 1;
+            gas = 0;
+        }
+    }
+}
+
+// Returns true if no instruction runs after `instruction` in the same basic block
+fn ends_block(instruction: &Instruction) -> bool {
+    match instruction.opcode {
+        Opcode::STOP
+        | Opcode::JUMP
+        | Opcode::JUMPI
+        | Opcode::RETURN
+        | Opcode::REVERT
+        | Opcode::INVALID
+        | Opcode::SUICIDE => true,
+        _ => instruction.cost.is_none(),
+    }
+}
+
+/// Reads the `bytes` bytes of PUSH data starting at `start` as a word. Data cut off by the end of
+/// the code reads as zeros on the right.
+fn read_push_data(code: &[u8], start: usize, bytes: usize) -> M256 {
+    let start = cmp::min(start, code.len());
+    let end = cmp::min(start + bytes, code.len());
+    let mut data = [0u8; 32];
+    data[32 - bytes..32 - bytes + (end - start)].copy_from_slice(&code[start..end]);
+    // Reads the limbs directly, which is several times faster than converting the slice
+    let limb = |i: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&data[24 - 8 * i..32 - 8 * i]);
+        u64::from_be_bytes(bytes)
+    };
+    M256(U256([limb(0), limb(1), limb(2), limb(3)]))
+}
+
+/// Analyses of code that can be shared by calls and by VMs, keyed by the hash of the code and the
+/// fork it was analyzed for. Clones are handles to the same cache, so a single cache can be given
+/// to the VM of every transaction in a block, and a contract called many times is analyzed once.
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisCache {
+    analyses: Rc<RefCell<Analyses>>,
+}
+
+type Analyses = HashMap<(H256, Fork), Rc<Analysis>>;
+
+impl AnalysisCache {
+    /// Creates and returns a new, empty AnalysisCache
+    pub fn new() -> AnalysisCache {
+        AnalysisCache::default()
+    }
+
+    /// Returns the analysis of `code`, whose hash is `code_hash`, analyzing it only if it is not
+    /// in the cache yet
+    pub fn get(&self, code_hash: H256, code: &[u8], fork: Fork) -> Rc<Analysis> {
+        self.analyses
+            .borrow_mut()
+            .entry((code_hash, fork))
+            .or_insert_with(|| Rc::new(Analysis::analyze(code, fork)))
+            .clone()
+    }
+
+    /// Returns the number of analyses in the cache
+    pub fn len(&self) -> usize {
+        self.analyses.borrow().len()
+    }
+
+    /// Returns true if nothing has been analyzed yet
+    pub fn is_empty(&self) -> bool {
+        self.analyses.borrow().is_empty()
+    }
+
+    /// Removes every analysis from the cache
+    pub fn clear(&self) {
+        self.analyses.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_jumpdests() {
+        let code = vec![0x5b, 0x60, 0x00, 0x56, 0x5b];
+        let analysis = Analysis::analyze(&code, Fork::latest());
+        assert!(analysis.is_jump_destination(0.into()));
+        assert!(!analysis.is_jump_destination(1.into()));
+        assert!(analysis.is_jump_destination(4.into()));
+    }
+
+    #[test]
+    fn skips_push_data() {
+        let code = vec![0x61, 0x5b, 0x5b, 0x5b];
+        let analysis = Analysis::analyze(&code, Fork::latest());
+        assert!(!analysis.is_jump_destination(1.into()));
+        assert!(!analysis.is_jump_destination(2.into()));
+        assert!(analysis.is_jump_destination(3.into()));
+    }
+
+    #[test]
+    fn rejects_out_of_range_destinations() {
+        let code = vec![0x5b];
+        let analysis = Analysis::analyze(&code, Fork::latest());
+        assert!(!analysis.is_jump_destination(64.into()));
+        assert!(!analysis.is_jump_destination(M256::max_value()));
+    }
+
+    #[test]
+    fn decodes_instructions_and_push_data() {
+        // PUSH2 0x0102, PUSH1 0x5b, ADD
+        let analysis = Analysis::analyze(&[0x61, 0x01, 0x02, 0x60, 0x5b, 0x01], Fork::London);
+        let push = *analysis.instruction(0);
+        assert_eq!(push.opcode, Opcode::PUSH(2));
+        assert_eq!(analysis.immediate(&push), M256::from(0x0102));
+        assert_eq!(analysis.immediate(analysis.instruction(3)), M256::from(0x5b));
+        assert_eq!(analysis.instruction(5).opcode, Opcode::ADD);
+        assert!(!analysis.is_jump_destination(4.into()));
+    }
+
+    #[test]
+    fn push_data_and_the_end_of_code_read_as_stop() {
+        let analysis = Analysis::analyze(&[0x61, 0x01, 0x02], Fork::London);
+        assert_eq!(analysis.instruction(1).opcode, Opcode::STOP);
+        assert_eq!(analysis.instruction(3).opcode, Opcode::STOP);
+        assert_eq!(analysis.instruction(1000).opcode, Opcode::STOP);
+    }
+
+    #[test]
+    fn pads_truncated_push_data_with_zeros() {
+        let analysis = Analysis::analyze(&[0x62, 0x01, 0x02], Fork::London);
+        assert_eq!(analysis.immediate(analysis.instruction(0)), M256::from(0x010200));
+    }
+
+    #[test]
+    fn prices_by_fork() {
+        // BASEFEE
+        assert_eq!(Analysis::analyze(&[0x48], Fork::London).instruction(0).cost, Some(2));
+        assert_eq!(Analysis::analyze(&[0x48], Fork::Berlin).instruction(0).cost, None);
+    }
+
+    #[test]
+    fn sums_static_gas_of_basic_blocks() {
+        // PUSH1 4, JUMP, INVALID, JUMPDEST, PUSH1 1, PUSH1 2, ADD, STOP
+        let code = [0x60, 0x04, 0x56, 0xfe, 0x5b, 0x60, 0x01, 0x60, 0x02, 0x01, 0x00];
+        let analysis = Analysis::analyze(&code, Fork::London);
+        assert_eq!(analysis.instruction(0).block_gas, 3 + 8);
+        assert_eq!(analysis.instruction(2).block_gas, 0);
+        assert_eq!(analysis.instruction(4).block_gas, 1 + 3 + 3 + 3);
+        assert_eq!(analysis.instruction(5).block_gas, 0);
+    }
+
+    #[test]
+    fn cache_analyzes_code_once_per_fork() {
+        let cache = AnalysisCache::new();
+        let code = [0x60, 0x01, 0x00];
+        let hash = H256::from(1);
+        let first = cache.get(hash, &code, Fork::London);
+        assert!(Rc::ptr_eq(&first, &cache.clone().get(hash, &code, Fork::London)));
+        cache.get(hash, &code, Fork::Berlin);
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
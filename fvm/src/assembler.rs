@@ -0,0 +1,284 @@
+//! Module for turning assembly text into bytecode, so contracts for tests and fixtures can be
+//! written by name rather than by byte.
+//!
+//! Each line holds an instruction, a label definition, a directive or nothing at all, and
+//! anything after a `;` is a comment:
+//!
+//! ```text
+//! start:                 ; a label marks the offset of whatever follows it
+//!     PUSH1 0x02         ; PUSH operands are hex or decimal
+//!     PUSH 300           ; a PUSH without a size uses as few bytes as the value needs
+//!     PUSH end           ; a label pushes its offset, as two bytes unless a size is given
+//!     JUMP
+//! end:
+//!     JUMPDEST
+//!     STOP
+//! greeting:
+//!     .data 0x48656c6c6f ; raw bytes, copied into the code as they are
+//! ```
+//!
+//! Mnemonics are those the opcode table displays, in either case.
+
+use bigint::{H256, U256};
+use errors::AssemblerError;
+use opcodes::Opcode;
+use std::collections::HashMap;
+use std::result;
+use std::str::FromStr;
+
+/// Convenience wrapper around T and an AssemblerError
+pub type Result<T> = result::Result<T, AssemblerError>;
+
+// Size of a PUSH of a label when no size is given
+const LABEL_SIZE: usize = 2;
+
+enum Operand {
+    Value(U256),
+    Label(String),
+}
+
+enum Item {
+    Label(String),
+    Opcode(u8),
+    Push(usize, Operand),
+    Data(Vec<u8>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match *self {
+            Item::Label(_) => 0,
+            Item::Opcode(_) => 1,
+            Item::Push(size, _) => 1 + size,
+            Item::Data(ref data) => data.len(),
+        }
+    }
+}
+
+/// Assembles `source` into bytecode
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let mut items = vec![];
+    for (index, line) in source.lines().enumerate() {
+        parse_line(index + 1, line, &mut items)?;
+    }
+
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    for &(line, ref item) in &items {
+        if let Item::Label(ref name) = *item {
+            if labels.insert(name.clone(), offset).is_some() {
+                return Err(AssemblerError::DuplicateLabel(line, name.clone()));
+            }
+        }
+        offset += item.size();
+    }
+
+    let mut code = Vec::with_capacity(offset);
+    for &(line, ref item) in &items {
+        match *item {
+            Item::Label(_) => {}
+            Item::Opcode(byte) => code.push(byte),
+            Item::Push(size, ref operand) => {
+                let (value, word) = match *operand {
+                    Operand::Value(value) => (value, format!("0x{:x}", value)),
+                    Operand::Label(ref name) => match labels.get(name) {
+                        Some(&offset) => (U256::from(offset), name.clone()),
+                        None => return Err(AssemblerError::UndefinedLabel(line, name.clone())),
+                    },
+                };
+                if value_size(value) > size {
+                    return Err(AssemblerError::OperandTooLarge(line, word));
+                }
+                code.push(0x5f + size as u8);
+                code.extend_from_slice(&H256::from(value).0[32 - size..]);
+            }
+            Item::Data(ref data) => code.extend_from_slice(data),
+        }
+    }
+    Ok(code)
+}
+
+// Parses a single line into the items it holds, which may be a label followed by an instruction
+fn parse_line(line: usize, text: &str, items: &mut Vec<(usize, Item)>) -> Result<()> {
+    let text = text.split(';').next().unwrap_or("");
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        if word.ends_with(':') && is_label(&word[..word.len() - 1]) {
+            items.push((line, Item::Label(word[..word.len() - 1].to_string())));
+            continue;
+        }
+        let item = if word == ".data" {
+            let operand = words.next().unwrap_or("");
+            Item::Data(parse_data(operand).ok_or_else(|| AssemblerError::InvalidOperand(line, operand.to_string()))?)
+        } else if word.eq_ignore_ascii_case("PUSH") {
+            let operand = words.next().unwrap_or("");
+            match parse_operand(line, operand)? {
+                Operand::Value(value) => Item::Push(value_size(value).max(1), Operand::Value(value)),
+                label => Item::Push(LABEL_SIZE, label),
+            }
+        } else {
+            let byte = opcode_byte(&word.to_uppercase())
+                .ok_or_else(|| AssemblerError::UnknownMnemonic(line, word.to_string()))?;
+            match Opcode::from(&byte) {
+                Opcode::PUSH(size) => {
+                    let operand = words.next().unwrap_or("");
+                    Item::Push(size, parse_operand(line, operand)?)
+                }
+                _ => Item::Opcode(byte),
+            }
+        };
+        items.push((line, item));
+        // Anything left on the line after an instruction is a mistake
+        if let Some(word) = words.next() {
+            return Err(AssemblerError::InvalidOperand(line, word.to_string()));
+        }
+    }
+    Ok(())
+}
+
+// Returns the byte for an upper case mnemonic
+fn opcode_byte(name: &str) -> Option<u8> {
+    (0..=255u8).find(|byte| match Opcode::from(byte) {
+        Opcode::INVALID if *byte != 0xfe => false,
+        opcode => opcode.to_string() == name,
+    })
+}
+
+fn parse_operand(line: usize, word: &str) -> Result<Operand> {
+    if is_label(word) {
+        return Ok(Operand::Label(word.to_string()));
+    }
+    let value = if let Some(hex) = word.strip_prefix("0x") {
+        U256::from_str(hex).ok()
+    } else {
+        U256::from_dec_str(word).ok()
+    };
+    match value {
+        Some(value) if !word.is_empty() && word != "0x" => Ok(Operand::Value(value)),
+        _ => Err(AssemblerError::InvalidOperand(line, word.to_string())),
+    }
+}
+
+fn parse_data(word: &str) -> Option<Vec<u8>> {
+    let hex = word.strip_prefix("0x")?;
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+// Returns the number of bytes needed to hold `value`
+fn value_size(value: U256) -> usize {
+    value.bits().div_ceil(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disassembler;
+
+    #[test]
+    fn assembles_instructions() {
+        let code = assemble("PUSH1 0x02\n  push1 3 ; comment\n\nADD\nSTOP").unwrap();
+        assert_eq!(code, vec![0x60, 0x02, 0x60, 0x03, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn sizes_bare_pushes() {
+        assert_eq!(assemble("PUSH 0").unwrap(), vec![0x60, 0x00]);
+        assert_eq!(assemble("PUSH 300").unwrap(), vec![0x61, 0x01, 0x2c]);
+        assert_eq!(assemble("PUSH4 0x01").unwrap(), vec![0x63, 0x00, 0x00, 0x00, 0x01]);
+        let mut max = vec![0x7f];
+        max.extend_from_slice(&[0xff; 32]);
+        assert_eq!(assemble(&format!("PUSH 0x{}", "ff".repeat(32))).unwrap(), max);
+    }
+
+    #[test]
+    fn resolves_labels() {
+        let source = "
+            PUSH end
+            JUMP
+            PUSH1 data
+        end: JUMPDEST
+            STOP
+        data:
+            .data 0xdeadbeef
+        ";
+        let code = assemble(source).unwrap();
+        assert_eq!(
+            code,
+            vec![0x61, 0x00, 0x06, 0x56, 0x60, 0x08, 0x5b, 0x00, 0xde, 0xad, 0xbe, 0xef]
+        );
+    }
+
+    #[test]
+    fn reports_errors_by_line() {
+        assert_eq!(
+            assemble("STOP\nFOO"),
+            Err(AssemblerError::UnknownMnemonic(2, "FOO".to_string()))
+        );
+        assert_eq!(
+            assemble("PUSH1 0x100"),
+            Err(AssemblerError::OperandTooLarge(1, "0x100".to_string()))
+        );
+        assert_eq!(
+            assemble("PUSH1 0xzz"),
+            Err(AssemblerError::InvalidOperand(1, "0xzz".to_string()))
+        );
+        assert_eq!(
+            assemble("PUSH1"),
+            Err(AssemblerError::InvalidOperand(1, "".to_string()))
+        );
+        assert_eq!(
+            assemble("ADD 1"),
+            Err(AssemblerError::InvalidOperand(1, "1".to_string()))
+        );
+        assert_eq!(
+            assemble("JUMP nowhere"),
+            Err(AssemblerError::InvalidOperand(1, "nowhere".to_string()))
+        );
+        assert_eq!(
+            assemble("PUSH nowhere"),
+            Err(AssemblerError::UndefinedLabel(1, "nowhere".to_string()))
+        );
+        assert_eq!(
+            assemble("a:\na:"),
+            Err(AssemblerError::DuplicateLabel(2, "a".to_string()))
+        );
+        assert_eq!(
+            assemble(".data 0x123"),
+            Err(AssemblerError::InvalidOperand(1, "0x123".to_string()))
+        );
+    }
+
+    #[test]
+    fn round_trips_the_opcode_table() {
+        for byte in 0..=255u8 {
+            let instruction = &disassembler::disassemble(&[byte])[0];
+            if instruction.opcode == Opcode::INVALID && byte != 0xfe {
+                continue;
+            }
+            let source = format!(
+                "{} {}",
+                instruction.opcode,
+                if instruction.missing > 0 { "0" } else { "" }
+            );
+            let code = assemble(&source).unwrap();
+            assert_eq!(code[0], byte);
+            assert_eq!(code.len(), 1 + instruction.missing);
+        }
+    }
+}
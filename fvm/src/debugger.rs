@@ -0,0 +1,415 @@
+//! Module for stepping through execution interactively. The Debugger wraps a VM, runs it an
+//! instruction at a time and answers the commands typed at a debugging prompt.
+
+use bigint::{Address, H256, M256, U256};
+use host::Host;
+use opcodes::{self, Opcode};
+use outcome::Outcome;
+use rustc_hex::ToHex;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::str::FromStr;
+use tracer::{Step, Tracer};
+use vm::VM;
+
+const HELP: &str = "\
+step [n]          execute the next n instructions, 1 if n is not given
+continue          execute until a breakpoint is reached or execution halts
+break <pc|op>     stop before the instruction at pc, or before every instruction with mnemonic op
+delete <pc|op>    remove a breakpoint
+breakpoints       list the breakpoints
+info              show where execution is
+stack             show the stack, top first
+memory            show the memory of the running call
+storage [slot]    show a storage slot of the running contract, or every slot written so far
+logs              show the logs emitted so far
+help              show this message
+quit              leave the debugger
+";
+
+/// A place execution stops before reaching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Breakpoint {
+    /// The instruction at a position in the code, in whichever call is running
+    Pc(usize),
+    /// Every instruction with a given opcode byte
+    Opcode(u8),
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    /// Parses a pc in hex or decimal, or the mnemonic of an opcode
+    fn from_str(word: &str) -> Result<Breakpoint, String> {
+        let pc = match word.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => word.parse().ok(),
+        };
+        if let Some(pc) = pc {
+            return Ok(Breakpoint::Pc(pc));
+        }
+        let name = word.to_uppercase();
+        (0..=255u8)
+            .find(|byte| opcodes::mnemonic(*byte) == name)
+            .map(Breakpoint::Opcode)
+            .ok_or_else(|| format!("{} is neither a pc nor an opcode", word))
+    }
+}
+
+/// A command typed at the debugging prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Break(Breakpoint),
+    Delete(Breakpoint),
+    Breakpoints,
+    Info,
+    Stack,
+    Memory,
+    Storage(Option<U256>),
+    Logs,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next().unwrap_or("") {
+            "step" | "s" => match words.next() {
+                Some(count) => Command::Step(count.parse().map_err(|_| format!("{} is not a count", count))?),
+                None => Command::Step(1),
+            },
+            "continue" | "c" => Command::Continue,
+            "break" | "b" => Command::Break(words.next().unwrap_or("").parse()?),
+            "delete" | "d" => Command::Delete(words.next().unwrap_or("").parse()?),
+            "breakpoints" => Command::Breakpoints,
+            "info" | "i" => Command::Info,
+            "stack" => Command::Stack,
+            "memory" => Command::Memory,
+            "storage" => match words.next() {
+                Some(slot) => Command::Storage(Some(parse_slot(slot)?)),
+                None => Command::Storage(None),
+            },
+            "logs" => Command::Logs,
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            other => return Err(format!("unknown command {}, try help", other)),
+        };
+        match words.next() {
+            Some(extra) => Err(format!("unexpected {}", extra)),
+            None => Ok(command),
+        }
+    }
+}
+
+/// Runs a VM an instruction at a time, stopping at breakpoints
+pub struct Debugger<H: Host> {
+    vm: VM<H>,
+    breakpoints: BTreeSet<Breakpoint>,
+    /// Storage slots written so far, by contract
+    written: BTreeSet<(Address, U256)>,
+}
+
+impl<H: Host> Debugger<H> {
+    /// Creates and returns a Debugger that has not executed anything of `vm` yet
+    pub fn new(vm: VM<H>) -> Debugger<H> {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            written: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the VM being debugged
+    pub fn vm(&self) -> &VM<H> {
+        &self.vm
+    }
+
+    /// Adds a breakpoint. Returns false if it was already set.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.insert(breakpoint)
+    }
+
+    /// Removes a breakpoint. Returns false if it was not set.
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.remove(&breakpoint)
+    }
+
+    /// Executes the next instruction and returns what it did, or None if execution has halted
+    pub fn step(&mut self) -> Option<Step> {
+        if self.vm.is_halted() {
+            return None;
+        }
+        let mut recorder = LastStep(None);
+        let _ = self.vm.execute_one_with_tracer(&mut recorder);
+        let step = recorder.0.expect("a VM that has not halted executes an instruction");
+        if let (Some((index, _)), Opcode::SSTORE) = (step.storage, Opcode::from(&step.opcode)) {
+            self.written.insert((step.address, index));
+        }
+        Some(step)
+    }
+
+    /// Executes at least one instruction, then carries on until the next instruction is at a
+    /// breakpoint or execution halts. Returns the breakpoint reached, if any.
+    pub fn resume(&mut self) -> Option<Breakpoint> {
+        while self.step().is_some() {
+            if let Some(breakpoint) = self.breakpoint_reached() {
+                return Some(breakpoint);
+            }
+        }
+        None
+    }
+
+    /// Returns the breakpoint the next instruction is at, if any
+    pub fn breakpoint_reached(&self) -> Option<Breakpoint> {
+        if self.vm.is_halted() {
+            return None;
+        }
+        let byte = self.next_byte();
+        self.breakpoints
+            .iter()
+            .cloned()
+            .find(|breakpoint| *breakpoint == Breakpoint::Pc(self.vm.pc()) || *breakpoint == Breakpoint::Opcode(byte))
+    }
+
+    /// Runs a command from the debugging prompt and returns what to print
+    pub fn run(&mut self, command: &Command) -> String {
+        match *command {
+            Command::Step(count) => {
+                for _ in 0..count {
+                    if self.step().is_none() {
+                        break;
+                    }
+                }
+                self.info()
+            }
+            Command::Continue => match self.resume() {
+                Some(breakpoint) => format!("Reached {}\n{}", describe(breakpoint), self.info()),
+                None => self.info(),
+            },
+            Command::Break(breakpoint) => {
+                self.add_breakpoint(breakpoint);
+                format!("Breakpoint set at {}\n", describe(breakpoint))
+            }
+            Command::Delete(breakpoint) => {
+                if self.remove_breakpoint(breakpoint) {
+                    format!("Breakpoint at {} removed\n", describe(breakpoint))
+                } else {
+                    format!("No breakpoint at {}\n", describe(breakpoint))
+                }
+            }
+            Command::Breakpoints => self.breakpoints.iter().map(|b| format!("{}\n", describe(*b))).collect(),
+            Command::Info => self.info(),
+            Command::Stack => self.stack(),
+            Command::Memory => self.memory(),
+            Command::Storage(slot) => self.storage(slot),
+            Command::Logs => self.logs(),
+            Command::Help => HELP.to_string(),
+            Command::Quit => String::new(),
+        }
+    }
+
+    /// Describes where execution is: the next instruction, or how execution ended
+    pub fn info(&self) -> String {
+        if self.vm.is_halted() {
+            let result = self.vm.result();
+            return match result.outcome {
+                Outcome::Halt(e) => format!("Halted with {}, using {} gas\n", e, result.gas_used),
+                outcome => format!("Halted with {:?}, using {} gas\n", outcome, result.gas_used),
+            };
+        }
+        format!(
+            "{:05x}: {} (depth {}, gas {})\n",
+            self.vm.pc(),
+            opcodes::mnemonic(self.next_byte()),
+            // Counted from 1 for the outermost call, as traces count it
+            self.vm.depth() + 1,
+            self.vm.gas_remaining()
+        )
+    }
+
+    /// Lists the stack, top first
+    pub fn stack(&self) -> String {
+        let items = self.vm.stack().items();
+        items
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, item)| format!("{:4}: {}\n", depth, hex_word(*item)))
+            .collect()
+    }
+
+    /// Lists the memory of the running call a word at a time
+    pub fn memory(&self) -> String {
+        self.vm
+            .active_memory()
+            .chunks(32)
+            .enumerate()
+            .map(|(word, bytes)| format!("{:05x}: {}\n", word * 32, bytes.to_hex::<String>()))
+            .collect()
+    }
+
+    /// Shows a storage slot of the running contract, or every slot written so far
+    pub fn storage(&self, slot: Option<U256>) -> String {
+        match slot {
+            Some(slot) => {
+                let value = self.vm.host().storage(self.vm.address(), slot);
+                format!("{}: {}\n", hex_word(M256::from(slot)), hex_word(value))
+            }
+            None => self
+                .written
+                .iter()
+                .map(|&(address, slot)| {
+                    let value = self.vm.host().storage(address, slot);
+                    format!("{:x} {}: {}\n", address, hex_word(M256::from(slot)), hex_word(value))
+                })
+                .collect(),
+        }
+    }
+
+    /// Lists the logs emitted so far
+    pub fn logs(&self) -> String {
+        let mut output = String::new();
+        for log in self.vm.logs() {
+            let topics: Vec<String> = log.topics.iter().map(|topic| topic.to_hex()).collect();
+            let _ = writeln!(
+                output,
+                "{:x} topics [{}] data {}",
+                log.address,
+                topics.join(", "),
+                log.data.to_hex::<String>()
+            );
+        }
+        output
+    }
+
+    // Returns the byte of the next instruction, which is a STOP past the end of the code
+    fn next_byte(&self) -> u8 {
+        self.vm.code().get(self.vm.pc()).cloned().unwrap_or(0x00)
+    }
+}
+
+// Keeps the last step it is told about
+struct LastStep(Option<Step>);
+
+impl Tracer for LastStep {
+    fn step(&mut self, step: &Step) {
+        self.0 = Some(step.clone());
+    }
+}
+
+fn describe(breakpoint: Breakpoint) -> String {
+    match breakpoint {
+        Breakpoint::Pc(pc) => format!("pc 0x{:x}", pc),
+        Breakpoint::Opcode(byte) => opcodes::mnemonic(byte),
+    }
+}
+
+fn parse_slot(word: &str) -> Result<U256, String> {
+    let slot = match word.strip_prefix("0x") {
+        Some(hex) => U256::from_str(hex).ok(),
+        None => U256::from_dec_str(word).ok(),
+    };
+    slot.ok_or_else(|| format!("{} is not a storage slot", word))
+}
+
+fn hex_word(value: M256) -> String {
+    format!("0x{}", H256::from(value).to_hex::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::assemble;
+
+    fn debugger(source: &str) -> Debugger<::state::State> {
+        Debugger::new(VM::new(assemble(source).unwrap()))
+    }
+
+    const STORE_AND_LOG: &str = "
+        PUSH1 0x2a
+        PUSH1 1
+        SSTORE
+        PUSH1 0xff
+        PUSH1 0
+        MSTORE8
+        PUSH1 7
+        PUSH1 1
+        PUSH1 0
+        LOG1
+        STOP
+    ";
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!("step".parse(), Ok(Command::Step(1)));
+        assert_eq!("s 3".parse(), Ok(Command::Step(3)));
+        assert_eq!("break 0x1a".parse(), Ok(Command::Break(Breakpoint::Pc(26))));
+        assert_eq!("b sstore".parse(), Ok(Command::Break(Breakpoint::Opcode(0x55))));
+        assert_eq!("storage 0x10".parse(), Ok(Command::Storage(Some(U256::from(16)))));
+        assert!("break nothing".parse::<Command>().is_err());
+        assert!("jump".parse::<Command>().is_err());
+        assert!("stack 1".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn steps_one_instruction_at_a_time() {
+        let mut debugger = debugger(STORE_AND_LOG);
+        let step = debugger.step().unwrap();
+        assert_eq!(step.opcode, 0x60);
+        assert_eq!(
+            debugger.run(&Command::Step(1)),
+            format!("00004: SSTORE (depth 1, gas {})\n", 9_999_994)
+        );
+        assert_eq!(debugger.stack(), format!("   0: 0x{:064x}\n   1: 0x{:064x}\n", 1, 0x2a));
+    }
+
+    #[test]
+    fn counts_depth_of_nested_calls() {
+        let callee = Address::from(0xca);
+        let code = assemble("PUSH1 0\nPUSH1 0\nPUSH1 0\nPUSH1 0\nPUSH1 0\nPUSH1 0xca\nGAS\nCALL\nSTOP").unwrap();
+        let state = ::state::State::new().with_account(callee, U256::zero(), vec![0x00]);
+        let mut debugger = Debugger::new(VM::new(code).with_host(state));
+        debugger.run(&Command::Step(8));
+        assert!(debugger.info().starts_with("00000: STOP (depth 2, gas "));
+    }
+
+    #[test]
+    fn continues_to_breakpoints() {
+        let mut debugger = debugger(STORE_AND_LOG);
+        debugger.run(&Command::Break(Breakpoint::Opcode(0xa1)));
+        debugger.run(&Command::Break(Breakpoint::Pc(5)));
+        assert_eq!(debugger.resume(), Some(Breakpoint::Pc(5)));
+        assert_eq!(
+            debugger.storage(None),
+            format!("{:x} 0x{:064x}: 0x{:064x}\n", Address::default(), 1, 0x2a)
+        );
+        assert_eq!(debugger.resume(), Some(Breakpoint::Opcode(0xa1)));
+        assert_eq!(debugger.memory(), format!("00000: ff{}\n", "00".repeat(31)));
+        assert_eq!(debugger.logs(), "");
+        assert_eq!(debugger.resume(), None);
+        assert_eq!(
+            debugger.logs(),
+            format!("{:x} topics [{:064x}] data ff\n", Address::default(), 7)
+        );
+        assert!(debugger.info().starts_with("Halted with Stop"));
+        assert_eq!(debugger.step(), None);
+    }
+
+    #[test]
+    fn describes_where_execution_halted() {
+        let mut debugger = debugger("PUSH1 3\nJUMP");
+        assert_eq!(debugger.resume(), None);
+        assert_eq!(
+            debugger.info(),
+            format!(
+                "Halted with invalid jump destination at pc 2 (JUMP), using {} gas\n",
+                10_000_000
+            )
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Module for turning bytecode back into a readable listing of instructions
+
+use opcodes::{self, Opcode};
+use std::fmt;
+
+/// A single instruction found in bytecode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    /// Position of the instruction in the code
+    pub offset: usize,
+    /// The byte the instruction was decoded from
+    pub byte: u8,
+    pub opcode: Opcode,
+    /// Data following a PUSH, as much of it as the code holds
+    pub immediate: Vec<u8>,
+    /// How many bytes of PUSH data are cut off by the end of the code. When run, they read as
+    /// zeros.
+    pub missing: usize,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:05x}: {}", self.offset, opcodes::mnemonic(self.byte))?;
+        if !self.immediate.is_empty() {
+            write!(f, " 0x")?;
+            for byte in &self.immediate {
+                write!(f, "{:02x}", byte)?;
+            }
+        }
+        if self.missing > 0 {
+            write!(f, " (truncated, {} bytes missing)", self.missing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `code` into the instructions it holds
+pub fn disassemble(code: &[u8]) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        let byte = code[offset];
+        let opcode = Opcode::from(&byte);
+        let size = match opcode {
+            Opcode::PUSH(size) => size,
+            _ => 0,
+        };
+        let start = offset + 1;
+        let end = (start + size).min(code.len());
+        instructions.push(Instruction {
+            offset,
+            byte,
+            opcode,
+            immediate: code[start..end].to_vec(),
+            missing: start + size - end,
+        });
+        offset = start + size;
+    }
+    instructions
+}
+
+/// Returns a listing of `code` with one instruction per line
+pub fn listing(code: &[u8]) -> String {
+    disassemble(code)
+        .iter()
+        .map(|instruction| format!("{}\n", instruction))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_instructions_and_push_data() {
+        let instructions = disassemble(&[0x60, 0x80, 0x60, 0x40, 0x52, 0x00]);
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(instructions[1].offset, 2);
+        assert_eq!(instructions[1].opcode, Opcode::PUSH(1));
+        assert_eq!(instructions[1].immediate, vec![0x40]);
+        assert_eq!(instructions[2].offset, 4);
+        assert_eq!(instructions[2].opcode, Opcode::MSTORE);
+        assert!(instructions[2].immediate.is_empty());
+    }
+
+    #[test]
+    fn lists_one_instruction_per_line() {
+        assert_eq!(
+            listing(&[0x61, 0x01, 0x02, 0x56, 0xfe, 0x0c]),
+            "00000: PUSH2 0x0102\n00003: JUMP\n00004: INVALID\n00005: opcode 0x0c not defined\n"
+        );
+    }
+
+    #[test]
+    fn keeps_truncated_push_data() {
+        let instructions = disassemble(&[0x00, 0x63, 0xaa, 0xbb]);
+        assert_eq!(instructions[1].immediate, vec![0xaa, 0xbb]);
+        assert_eq!(instructions[1].missing, 2);
+        assert_eq!(
+            instructions[1].to_string(),
+            "00001: PUSH4 0xaabb (truncated, 2 bytes missing)"
+        );
+        assert_eq!(listing(&[0x7f]), "00000: PUSH32 (truncated, 32 bytes missing)\n");
+    }
+}
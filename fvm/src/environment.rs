@@ -0,0 +1,49 @@
+//! Module for the execution environment: the transaction and block a VM is running in
+
+use bigint::{Address, U256};
+
+/// Fields that are set by the transaction that started execution
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionContext {
+    /// Account that signed the transaction
+    pub origin: Address,
+    /// Account that sent the current message
+    pub caller: Address,
+    /// Wei sent along with the current message
+    pub value: U256,
+    /// Price per unit of gas the sender is paying
+    pub gas_price: U256,
+}
+
+/// Fields that are set by the block the transaction is included in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockContext {
+    /// Beneficiary of the block reward
+    pub coinbase: Address,
+    /// Unix timestamp of the block
+    pub timestamp: U256,
+    /// Height of the block
+    pub number: U256,
+    /// Difficulty of the block
+    pub difficulty: U256,
+    /// Total amount of gas the block may consume
+    pub gas_limit: U256,
+    /// Identifier of the chain the block belongs to, as read by CHAINID
+    pub chain_id: U256,
+    /// Base fee per unit of gas of the block, as read by BASEFEE
+    pub base_fee: U256,
+}
+
+/// Everything the VM can know about the world outside of the contract it is running
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Environment {
+    pub transaction: TransactionContext,
+    pub block: BlockContext,
+}
+
+impl Environment {
+    /// Creates and returns a new Environment
+    pub fn new(transaction: TransactionContext, block: BlockContext) -> Environment {
+        Environment { transaction, block }
+    }
+}
@@ -1,17 +1,54 @@
 //! This module contains errors related to the Fantom VM itself
+use opcodes::mnemonic;
 use std::error::Error;
 use std::fmt;
 
 /// Convenience wrapper around T and a VMError
 pub type Result<T> = std::result::Result<T, VMError>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Errors related to the VM
 pub enum VMError {
-    // VM has encountered an unknown opcode
-    UnknownOpcodeError,
+    // VM has encountered an opcode that is not defined in the fork being run, or the designated
+    // INVALID opcode
+    InvalidOpcode(u8),
     // VM has run out of memory
     MemoryError,
+    // Execution needed more gas than was left
+    OutOfGas,
+    // An instruction needed more items than were on the stack
+    StackUnderflow,
+    // An instruction pushed past the stack limit
+    StackOverflow,
+    // A JUMP or JUMPI targeted something other than a JUMPDEST
+    InvalidJump,
+    // An instruction tried to modify state inside a STATICCALL
+    StaticViolation,
+    // A call or create would have gone past the call depth limit
+    CallDepthExceeded,
+    // A call or create tried to send more value than the sender holds
+    InsufficientBalance,
+    // Execution hit a REVERT. Execution never halts with it: a REVERT ends in `Outcome::Revert`,
+    // which keeps the data it returns, and `Outcome::error` gives this for it.
+    Revert,
+    // The host failed to write a storage slot
+    StorageFailure,
+    // RETURNDATACOPY read past the end of the return data buffer
+    ReturnDataOutOfBounds,
+    // A precompiled contract was called with input it cannot handle
+    PrecompileFailure,
+}
+
+impl VMError {
+    /// Returns true if the error consumes all of the gas of the call it happens in. A revert only
+    /// uses the gas spent so far, and a call or create that fails before it runs uses none of the
+    /// gas it would have been given; the rest goes back to the caller.
+    pub fn consumes_all_gas(&self) -> bool {
+        !matches!(
+            self,
+            VMError::Revert | VMError::CallDepthExceeded | VMError::InsufficientBalance
+        )
+    }
 }
 
 impl Error for VMError {}
@@ -19,9 +56,41 @@ impl Error for VMError {}
 impl fmt::Display for VMError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            VMError::UnknownOpcodeError => write!(f, "an unrecognized opcode was found"),
+            VMError::InvalidOpcode(byte) => write!(f, "invalid opcode 0x{:02x}", byte),
             VMError::MemoryError => write!(f, "out of memory"),
-            _ => write!(f, "unknown error occurred"),
+            VMError::OutOfGas => write!(f, "out of gas"),
+            VMError::StackUnderflow => write!(f, "stack underflow"),
+            VMError::StackOverflow => write!(f, "stack overflow"),
+            VMError::InvalidJump => write!(f, "invalid jump destination"),
+            VMError::StaticViolation => write!(f, "state modification in a static call"),
+            VMError::CallDepthExceeded => write!(f, "max call depth exceeded"),
+            VMError::InsufficientBalance => write!(f, "insufficient balance for transfer"),
+            VMError::Revert => write!(f, "execution reverted"),
+            VMError::StorageFailure => write!(f, "failed to write storage"),
+            VMError::ReturnDataOutOfBounds => write!(f, "return data out of bounds"),
+            VMError::PrecompileFailure => write!(f, "invalid input to a precompiled contract"),
+        }
+    }
+}
+
+/// A VMError together with the instruction it happened at
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionError {
+    pub error: VMError,
+    /// Offset of the instruction in the code of the call it happened in
+    pub pc: usize,
+    /// The instruction's byte, which is 0x00 past the end of the code
+    pub opcode: u8,
+}
+
+impl Error for ExecutionError {}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error {
+            // The error already names the opcode
+            VMError::InvalidOpcode(_) => write!(f, "{} at pc {}", self.error, self.pc),
+            _ => write!(f, "{} at pc {} ({})", self.error, self.pc, mnemonic(self.opcode)),
         }
     }
 }
@@ -46,3 +115,65 @@ impl fmt::Display for StorageError {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+/// Errors found while assembling source text, each with the line it was found on
+pub enum AssemblerError {
+    // A word that is neither an opcode nor a directive
+    UnknownMnemonic(usize, String),
+    // A PUSH or data operand that could not be parsed
+    InvalidOperand(usize, String),
+    // A value that does not fit in the PUSH it was given to
+    OperandTooLarge(usize, String),
+    // A label that is used but never defined
+    UndefinedLabel(usize, String),
+    // A label that is defined more than once
+    DuplicateLabel(usize, String),
+}
+
+impl Error for AssemblerError {}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerError::UnknownMnemonic(line, word) => write!(f, "line {}: unknown mnemonic {}", line, word),
+            AssemblerError::InvalidOperand(line, word) => write!(f, "line {}: invalid operand {}", line, word),
+            AssemblerError::OperandTooLarge(line, word) => write!(f, "line {}: {} is too large", line, word),
+            AssemblerError::UndefinedLabel(line, label) => write!(f, "line {}: undefined label {}", line, label),
+            AssemblerError::DuplicateLabel(line, label) => {
+                write!(f, "line {}: label {} is already defined", line, label)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reverts_and_calls_that_never_ran_keep_their_gas() {
+        assert!(VMError::OutOfGas.consumes_all_gas());
+        assert!(VMError::InvalidOpcode(0xfe).consumes_all_gas());
+        assert!(VMError::StaticViolation.consumes_all_gas());
+        assert!(!VMError::Revert.consumes_all_gas());
+        assert!(!VMError::CallDepthExceeded.consumes_all_gas());
+        assert!(!VMError::InsufficientBalance.consumes_all_gas());
+    }
+
+    #[test]
+    fn displays_where_an_error_happened() {
+        let error = ExecutionError {
+            error: VMError::InvalidOpcode(0x0c),
+            pc: 7,
+            opcode: 0x0c,
+        };
+        assert_eq!(error.to_string(), "invalid opcode 0x0c at pc 7");
+        let error = ExecutionError {
+            error: VMError::OutOfGas,
+            pc: 12,
+            opcode: 0x55,
+        };
+        assert_eq!(error.to_string(), "out of gas at pc 12 (SSTORE)");
+    }
+}
@@ -0,0 +1,195 @@
+//! Module for the hard forks of the Ethereum protocol, which change the rules the VM follows
+
+use bigint::U256;
+use std::fmt;
+use std::str::FromStr;
+
+/// A set of protocol rules. Forks are ordered, so a later fork compares greater than an earlier one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Fork {
+    Frontier,
+    /// Adds DELEGATECALL, and makes running out of gas for the code deposit of a CREATE fail it
+    Homestead,
+    /// EIP-150: reprices IO heavy opcodes, and passes at most 63/64 of the gas left on to calls
+    TangerineWhistle,
+    /// EIP-160, EIP-161 and EIP-170: reprices EXP, clears empty accounts, limits code size
+    SpuriousDragon,
+    /// Adds REVERT, RETURNDATASIZE, RETURNDATACOPY and STATICCALL
+    Byzantium,
+    /// Adds SHL, SHR, SAR, EXTCODEHASH and CREATE2. The SSTORE changes of EIP-1283 are left out,
+    /// as they were by Petersburg.
+    Constantinople,
+    /// EIP-1884 and EIP-2200: reprices state access and SSTORE, adds CHAINID and SELFBALANCE
+    Istanbul,
+    /// EIP-2929: charges more for the first access to an account or storage slot in a transaction
+    Berlin,
+    /// EIP-3529 and EIP-3541: reduces refunds, rejects new code starting with 0xef, adds BASEFEE
+    London,
+}
+
+/// Every fork, from the oldest to the newest
+pub const ALL_FORKS: [Fork; 9] = [
+    Fork::Frontier,
+    Fork::Homestead,
+    Fork::TangerineWhistle,
+    Fork::SpuriousDragon,
+    Fork::Byzantium,
+    Fork::Constantinople,
+    Fork::Istanbul,
+    Fork::Berlin,
+    Fork::London,
+];
+
+impl Fork {
+    /// Returns the most recent fork the VM supports
+    pub fn latest() -> Fork {
+        Fork::London
+    }
+
+    /// Returns the name of the fork, as used by the Ethereum tests
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fork::Frontier => "Frontier",
+            Fork::Homestead => "Homestead",
+            Fork::TangerineWhistle => "EIP150",
+            Fork::SpuriousDragon => "EIP158",
+            Fork::Byzantium => "Byzantium",
+            Fork::Constantinople => "Constantinople",
+            Fork::Istanbul => "Istanbul",
+            Fork::Berlin => "Berlin",
+            Fork::London => "London",
+        }
+    }
+}
+
+impl Default for Fork {
+    fn default() -> Fork {
+        Fork::latest()
+    }
+}
+
+impl fmt::Display for Fork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Fork {
+    type Err = String;
+
+    /// Parses either the name used by the Ethereum tests or the name of the upgrade
+    fn from_str(name: &str) -> Result<Fork, String> {
+        match name {
+            "Frontier" => Ok(Fork::Frontier),
+            "Homestead" => Ok(Fork::Homestead),
+            "EIP150" | "TangerineWhistle" => Ok(Fork::TangerineWhistle),
+            "EIP158" | "SpuriousDragon" => Ok(Fork::SpuriousDragon),
+            "Byzantium" => Ok(Fork::Byzantium),
+            "Constantinople" | "ConstantinopleFix" | "Petersburg" => Ok(Fork::Constantinople),
+            "Istanbul" => Ok(Fork::Istanbul),
+            "Berlin" => Ok(Fork::Berlin),
+            "London" => Ok(Fork::London),
+            _ => Err(format!("unknown fork: {}", name)),
+        }
+    }
+}
+
+/// The block numbers at which forks activate on a chain. Frontier is always active from the
+/// genesis block, and a fork that is never given a block never activates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForkSchedule {
+    activations: Vec<(Fork, U256)>,
+}
+
+impl ForkSchedule {
+    /// Creates and returns a schedule in which only Frontier is active
+    pub fn new() -> ForkSchedule {
+        ForkSchedule { activations: vec![] }
+    }
+
+    /// Creates and returns the schedule of the Ethereum main network
+    pub fn mainnet() -> ForkSchedule {
+        ForkSchedule::new()
+            .with_fork(Fork::Homestead, 1_150_000.into())
+            .with_fork(Fork::TangerineWhistle, 2_463_000.into())
+            .with_fork(Fork::SpuriousDragon, 2_675_000.into())
+            .with_fork(Fork::Byzantium, 4_370_000.into())
+            .with_fork(Fork::Constantinople, 7_280_000.into())
+            .with_fork(Fork::Istanbul, 9_069_000.into())
+            .with_fork(Fork::Berlin, 12_244_000.into())
+            .with_fork(Fork::London, 12_965_000.into())
+    }
+
+    /// Creates and returns a schedule in which every fork is active from the genesis block
+    pub fn latest() -> ForkSchedule {
+        ALL_FORKS.iter().fold(ForkSchedule::new(), |schedule, &fork| {
+            schedule.with_fork(fork, U256::zero())
+        })
+    }
+
+    /// Activates `fork` at block `number`, replacing any block it was given before
+    pub fn with_fork(mut self, fork: Fork, number: U256) -> ForkSchedule {
+        self.activations.retain(|&(f, _)| f != fork);
+        self.activations.push((fork, number));
+        self
+    }
+
+    /// Returns the fork whose rules apply to the block at height `number`
+    pub fn fork_at(&self, number: U256) -> Fork {
+        self.activations
+            .iter()
+            .filter(|&&(_, activation)| activation <= number)
+            .map(|&(fork, _)| fork)
+            .max()
+            .unwrap_or(Fork::Frontier)
+    }
+}
+
+impl Default for ForkSchedule {
+    fn default() -> ForkSchedule {
+        ForkSchedule::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forks_are_ordered() {
+        assert!(Fork::Frontier < Fork::Homestead);
+        assert!(Fork::Constantinople < Fork::London);
+        assert_eq!(Fork::latest(), ALL_FORKS[ALL_FORKS.len() - 1]);
+    }
+
+    #[test]
+    fn mainnet_schedule() {
+        let schedule = ForkSchedule::mainnet();
+        assert_eq!(schedule.fork_at(0.into()), Fork::Frontier);
+        assert_eq!(schedule.fork_at(1_149_999.into()), Fork::Frontier);
+        assert_eq!(schedule.fork_at(1_150_000.into()), Fork::Homestead);
+        assert_eq!(schedule.fork_at(4_370_000.into()), Fork::Byzantium);
+        assert_eq!(schedule.fork_at(15_000_000.into()), Fork::London);
+    }
+
+    #[test]
+    fn latest_schedule() {
+        assert_eq!(ForkSchedule::latest().fork_at(0.into()), Fork::latest());
+    }
+
+    #[test]
+    fn unscheduled_forks_never_activate() {
+        let schedule = ForkSchedule::new().with_fork(Fork::Byzantium, 10.into());
+        assert_eq!(schedule.fork_at(9.into()), Fork::Frontier);
+        assert_eq!(schedule.fork_at(1_000_000.into()), Fork::Byzantium);
+    }
+
+    #[test]
+    fn parses_fork_names() {
+        for fork in ALL_FORKS.iter() {
+            assert_eq!(fork.name().parse::<Fork>(), Ok(*fork));
+        }
+        assert_eq!("Petersburg".parse::<Fork>(), Ok(Fork::Constantinople));
+        assert!("Shanghai".parse::<Fork>().is_err());
+    }
+}
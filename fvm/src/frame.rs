@@ -0,0 +1,262 @@
+//! Module for call frames. Every message call runs in its own frame, with its own stack, memory and
+//! gas, while the state and environment are shared by all of them.
+
+use analysis::Analysis;
+use bigint::{Address, M256, U256};
+use errors::{Result, VMError};
+use gas_prices;
+use journal::Checkpoint;
+use memory::{Memory, SimpleMemory};
+use outcome::Outcome;
+use stack::Stack;
+use std::cmp;
+use std::rc::Rc;
+
+// Offsets and sizes past this many bytes can never be paid for, so they are treated as running out
+// of gas instead of being converted to machine integers
+const MAX_MEMORY_BYTES: u64 = 0xffff_ffff;
+
+/// What started a frame, and so what has to happen with its result once it finishes
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameKind {
+    /// The frame the VM was created with. Its result is the result of the whole execution.
+    Root,
+    /// A frame started by one of the CALL opcodes. Its output is copied to the memory of the
+    /// caller at `out_offset`, up to `out_len` bytes.
+    Call { out_offset: U256, out_len: U256 },
+    /// A frame running the init code of a contract created by CREATE or CREATE2 at `address`. The
+    /// output of the init code becomes the code of the contract.
+    Create { address: Address },
+}
+
+/// The execution context of a single message call
+pub struct Frame {
+    pub kind: FrameKind,
+    /// Account whose storage and balance the frame operates on
+    pub address: Address,
+    /// Account that sent the message
+    pub caller: Address,
+    /// Wei sent along with the message
+    pub value: U256,
+    pub code: Vec<u8>,
+    /// Decoded form of `code`. Frames that are created without one are analyzed before their
+    /// first instruction runs.
+    pub analysis: Option<Rc<Analysis>>,
+    pub calldata: Vec<u8>,
+    pub pc: usize,
+    pub stack: Stack,
+    pub memory: Box<Memory>,
+    pub memory_words: u64,
+    pub gas_limit: u64,
+    pub gas_used: u64,
+    /// Static gas of the instructions left in the current basic block, once it is known that it
+    /// can be paid for
+    pub block_gas: u64,
+    /// True if the frame, or one of its callers, was started by a STATICCALL
+    pub is_static: bool,
+    /// Output of the last call this frame made, as read by RETURNDATASIZE and RETURNDATACOPY
+    pub return_data: Vec<u8>,
+    pub outcome: Option<Outcome>,
+    /// Point in the journal to revert to if the frame fails
+    pub checkpoint: Checkpoint,
+}
+
+impl Frame {
+    /// Creates and returns a new frame that will run `code` with `gas_limit` gas
+    pub fn new(kind: FrameKind, code: Vec<u8>, gas_limit: u64) -> Frame {
+        Frame {
+            kind,
+            address: Address::default(),
+            caller: Address::default(),
+            value: U256::zero(),
+            code,
+            analysis: None,
+            calldata: vec![],
+            pc: 0,
+            stack: Stack::new(),
+            memory: Box::new(SimpleMemory::new()),
+            memory_words: 0,
+            gas_limit,
+            gas_used: 0,
+            block_gas: 0,
+            is_static: false,
+            return_data: vec![],
+            outcome: None,
+            checkpoint: Checkpoint::default(),
+        }
+    }
+
+    /// Returns the amount of gas still available to the frame
+    pub fn gas_remaining(&self) -> u64 {
+        self.gas_limit - self.gas_used
+    }
+
+    /// Charges `amount` of gas. If there is not enough left, all of the remaining gas is consumed
+    /// and execution halts.
+    pub fn consume_gas(&mut self, amount: u64) -> Result<()> {
+        if amount > self.gas_remaining() {
+            self.exhaust_gas();
+            return Err(VMError::OutOfGas);
+        }
+        self.gas_used += amount;
+        // What is left may no longer cover the rest of the basic block
+        self.block_gas = cmp::min(self.block_gas, self.gas_remaining());
+        Ok(())
+    }
+
+    /// Charges the static `cost` of the next instruction. The first instruction of a basic block
+    /// gives the static cost of the whole block as `block_gas`, which is checked against the gas
+    /// left once, so that the instructions after it can be charged without checking.
+    #[inline]
+    pub fn charge_static_gas(&mut self, cost: u64, block_gas: u64) -> Result<()> {
+        if block_gas > 0 {
+            self.block_gas = if block_gas <= self.gas_remaining() {
+                block_gas
+            } else {
+                0
+            };
+        }
+        if cost <= self.block_gas {
+            self.block_gas -= cost;
+            self.gas_used += cost;
+            Ok(())
+        } else {
+            self.consume_gas(cost)
+        }
+    }
+
+    /// Consumes all of the gas of the frame, as any error halting it does
+    pub fn exhaust_gas(&mut self) {
+        self.gas_used = self.gas_limit;
+        self.block_gas = 0;
+    }
+
+    /// Ends a call or create that failed with `error` before it ran, pushing 0 for it. The `gas` it
+    /// was given goes back to the frame, unless the error consumes all gas.
+    pub fn fail_early(&mut self, gas: u64, error: &VMError) -> Result<()> {
+        if !error.consumes_all_gas() {
+            self.gas_used -= gas;
+        }
+        self.stack.push(M256::zero())
+    }
+
+    /// Returns the analysis of the frame's code. The VM analyzes every frame before running it.
+    pub fn analysis(&self) -> &Analysis {
+        self.analysis.as_ref().expect("frames are analyzed before they run")
+    }
+
+    /// Charges for expanding the active memory so that it covers `len` bytes starting at
+    /// `offset`. Touching memory that is already active is free, and a zero length never expands.
+    pub fn expand_memory(&mut self, offset: U256, len: U256) -> Result<()> {
+        if len.is_zero() {
+            return Ok(());
+        }
+        let limit = U256::from(MAX_MEMORY_BYTES);
+        if offset > limit || len > limit {
+            let remaining = self.gas_remaining();
+            return self.consume_gas(remaining + 1);
+        }
+        let words = gas_prices::to_words(offset.as_u64() + len.as_u64());
+        if words > self.memory_words {
+            let cost = gas_prices::memory_cost(words) - gas_prices::memory_cost(self.memory_words);
+            self.consume_gas(cost)?;
+            self.memory.expand(offset, len)?;
+            self.memory_words = words;
+        }
+        Ok(())
+    }
+
+    /// Reads `len` bytes of memory starting at `offset`
+    pub fn read_memory(&mut self, offset: U256, len: U256) -> Result<Vec<u8>> {
+        self.memory.copy_from_memory(offset, len)
+    }
+
+    /// Checks that `destination` is a JUMPDEST and returns it as the new program counter
+    pub fn jump_target(&self, destination: M256) -> Result<usize> {
+        if self.analysis().is_jump_destination(destination) {
+            Ok(destination.as_usize())
+        } else {
+            Err(VMError::InvalidJump)
+        }
+    }
+
+    /// Pops one operand and pushes the result of `op` on it
+    pub fn unary_op<F>(&mut self, op: F) -> Result<()>
+    where
+        F: FnOnce(M256) -> M256,
+    {
+        let a = self.stack.pop()?;
+        self.stack.push(op(a))
+    }
+
+    /// Pops two operands, the top of the stack first, and pushes the result of `op` on them
+    pub fn binary_op<F>(&mut self, op: F) -> Result<()>
+    where
+        F: FnOnce(M256, M256) -> M256,
+    {
+        let a = self.stack.pop()?;
+        let b = self.stack.pop()?;
+        self.stack.push(op(a, b))
+    }
+
+    /// Pops three operands, the top of the stack first, and pushes the result of `op` on them
+    pub fn ternary_op<F>(&mut self, op: F) -> Result<()>
+    where
+        F: FnOnce(M256, M256, M256) -> M256,
+    {
+        let a = self.stack.pop()?;
+        let b = self.stack.pop()?;
+        let c = self.stack.pop()?;
+        self.stack.push(op(a, b, c))
+    }
+
+    /// Pops the memory offset, source offset and length of a *COPY instruction, and charges for the
+    /// copy and for any memory it expands into
+    pub fn pop_copy_operands(&mut self) -> Result<(U256, U256, U256)> {
+        let memory_offset: U256 = self.stack.pop()?.into();
+        let source_offset: U256 = self.stack.pop()?.into();
+        let len: U256 = self.stack.pop()?.into();
+        self.expand_memory(memory_offset, len)?;
+        self.consume_gas(gas_prices::copy_cost(len.as_u64()))?;
+        Ok((memory_offset, source_offset, len))
+    }
+
+    /// Pops an offset and a length, and charges for any memory the range expands into
+    pub fn pop_memory_range(&mut self) -> Result<(U256, U256)> {
+        let offset: U256 = self.stack.pop()?.into();
+        let len: U256 = self.stack.pop()?.into();
+        self.expand_memory(offset, len)?;
+        Ok((offset, len))
+    }
+
+    /// Pops the offset and length of the output of RETURN or REVERT, and reads it from memory
+    pub fn pop_output_data(&mut self) -> Result<Vec<u8>> {
+        let (offset, len) = self.pop_memory_range()?;
+        self.read_memory(offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consume_gas_exhausts_frame() {
+        let mut frame = Frame::new(FrameKind::Root, vec![], 10);
+        assert!(frame.consume_gas(4).is_ok());
+        assert_eq!(frame.gas_remaining(), 6);
+        assert_eq!(frame.consume_gas(7), Err(VMError::OutOfGas));
+        assert_eq!(frame.gas_remaining(), 0);
+    }
+
+    #[test]
+    fn expand_memory_charges_once_per_word() {
+        let mut frame = Frame::new(FrameKind::Root, vec![], 100);
+        assert!(frame.expand_memory(0.into(), 33.into()).is_ok());
+        assert_eq!(frame.memory_words, 2);
+        assert_eq!(frame.gas_used, 6);
+        assert!(frame.expand_memory(10.into(), 20.into()).is_ok());
+        assert_eq!(frame.gas_used, 6);
+        assert_eq!(frame.memory.size(), M256::from(64));
+    }
+}
@@ -1,11 +1,60 @@
 //! Contains the Gas cost for all the various Opcodes
 
+use bigint::M256;
+use fork::Fork;
 use opcodes::Opcode;
+use std::cmp;
 
-/// Gets the cost for a specific Opcode. They are grouped by cost.
-pub fn get_cost(op: Opcode) -> Option<usize> {
+/// Cost per word of active memory
+pub const MEMORY_WORD: u64 = 3;
+/// Divisor for the quadratic part of the memory expansion cost
+pub const QUAD_COEFF_DIV: u64 = 512;
+/// Additional cost per byte of the exponent for EXP, before Spurious Dragon
+pub const EXP_BYTE_FRONTIER: u64 = 10;
+/// Additional cost per byte of the exponent for EXP
+pub const EXP_BYTE: u64 = 50;
+/// Additional cost per word hashed by SHA3
+pub const SHA3_WORD: u64 = 6;
+/// Additional cost per word copied by the *COPY opcodes
+pub const COPY_WORD: u64 = 3;
+/// Additional cost per byte of data in a LOG
+pub const LOG_DATA: u64 = 8;
+/// Cost of an SSTORE that sets a zero slot to a non-zero value
+pub const SSTORE_SET: u64 = 20000;
+/// Cost of an SSTORE that changes an already non-zero slot, or clears it
+pub const SSTORE_RESET: u64 = 5000;
+/// Refund for clearing a storage slot
+pub const SSTORE_CLEARS: u64 = 15000;
+/// Refund for clearing a storage slot from London on (EIP-3529)
+pub const SSTORE_CLEARS_LONDON: u64 = 4800;
+/// Cost of reading an account or storage slot that was already accessed in the transaction
+pub const WARM_STORAGE_READ: u64 = 100;
+/// Cost of the first access to an account in a transaction, from Berlin on
+pub const COLD_ACCOUNT_ACCESS: u64 = 2600;
+/// Cost of the first access to a storage slot in a transaction, from Berlin on
+pub const COLD_SLOAD: u64 = 2100;
+/// Additional cost of a CALL or CALLCODE that transfers a non-zero value
+pub const CALL_VALUE: u64 = 9000;
+/// Gas given to the callee for free when a call transfers a non-zero value
+pub const CALL_STIPEND: u64 = 2300;
+/// Additional cost of a CALL that transfers value to an account that does not exist yet
+pub const NEW_ACCOUNT: u64 = 25000;
+/// Refund for destroying a contract with SELFDESTRUCT, until London removed it (EIP-3529)
+pub const SELFDESTRUCT_REFUND: u64 = 24000;
+/// Cost per byte of the code deployed by CREATE or CREATE2
+pub const CREATE_DATA: u64 = 200;
+/// Largest contract code CREATE or CREATE2 may deploy, in bytes
+pub const MAX_CODE_SIZE: usize = 24576;
+
+/// Gets the cost for a specific Opcode under the rules of `fork`. They are grouped by cost. This is
+/// only the static part of the cost; memory expansion and the other size-dependent costs are
+/// charged by the VM itself. Returns None for opcodes that are not valid in `fork`.
+pub fn get_cost(op: Opcode, fork: Fork) -> Option<usize> {
+    if fork < introduced_in(op) {
+        return None;
+    }
     match op {
-        Opcode::STOP => Some(0),
+        Opcode::STOP | Opcode::RETURN | Opcode::REVERT | Opcode::SSTORE => Some(0),
         Opcode::JUMPDEST => Some(1),
 
         Opcode::ADDRESS
@@ -21,6 +70,8 @@ pub fn get_cost(op: Opcode) -> Option<usize> {
         | Opcode::NUMBER
         | Opcode::DIFFICULTY
         | Opcode::GASLIMIT
+        | Opcode::CHAINID
+        | Opcode::BASEFEE
         | Opcode::POP
         | Opcode::PC
         | Opcode::MSIZE
@@ -37,8 +88,18 @@ pub fn get_cost(op: Opcode) -> Option<usize> {
         | Opcode::XOR
         | Opcode::NOT
         | Opcode::BYTE
+        | Opcode::SHL
+        | Opcode::SHR
+        | Opcode::SAR
         | Opcode::ADD
         | Opcode::SUB
+        | Opcode::CALLDATALOAD
+        | Opcode::MLOAD
+        | Opcode::MSTORE
+        | Opcode::MSTORE8
+        | Opcode::CALLDATACOPY
+        | Opcode::CODECOPY
+        | Opcode::RETURNDATACOPY
         | Opcode::PUSH(_)
         | Opcode::DUP(_)
         | Opcode::SWAP(_) => Some(3),
@@ -48,30 +109,270 @@ pub fn get_cost(op: Opcode) -> Option<usize> {
         | Opcode::SDIV
         | Opcode::MOD
         | Opcode::SMOD
-        | Opcode::SIGNEXTEND => Some(5),
+        | Opcode::SIGNEXTEND
+        | Opcode::SELFBALANCE => Some(5),
 
-        Opcode::ADDMOD | Opcode::MULMOD => Some(8),
-        Opcode::EXP => Some(10),
+        Opcode::ADDMOD | Opcode::MULMOD | Opcode::JUMP => Some(8),
+        Opcode::EXP | Opcode::JUMPI => Some(10),
         Opcode::BLOCKHASH => Some(20),
         Opcode::SHA3 => Some(30),
 
-        Opcode::BALANCE => Some(400),
         Opcode::LOG(0) => Some(375),
         Opcode::LOG(1) => Some(750),
         Opcode::LOG(2) => Some(1125),
         Opcode::LOG(3) => Some(1500),
         Opcode::LOG(4) => Some(1875),
+        Opcode::CREATE | Opcode::CREATE2 => Some(32000),
+
+        // From Berlin on, these only cost this much for accounts and slots that were already
+        // accessed, and the VM charges the rest of the cold access cost itself
+        _ if fork >= Fork::Berlin && accesses_state(op) => Some(WARM_STORAGE_READ as usize),
+
+        Opcode::BALANCE => match fork {
+            Fork::Frontier | Fork::Homestead => Some(20),
+            Fork::Istanbul => Some(700),
+            _ => Some(400),
+        },
+        Opcode::EXTCODEHASH => match fork {
+            Fork::Istanbul => Some(700),
+            _ => Some(400),
+        },
+        Opcode::SLOAD => match fork {
+            Fork::Frontier | Fork::Homestead => Some(50),
+            Fork::Istanbul => Some(800),
+            _ => Some(200),
+        },
+        Opcode::EXTCODESIZE | Opcode::EXTCODECOPY => match fork {
+            Fork::Frontier | Fork::Homestead => Some(20),
+            _ => Some(700),
+        },
+        Opcode::CALL | Opcode::CALLCODE | Opcode::DELEGATECALL | Opcode::STATICCALL => match fork {
+            Fork::Frontier | Fork::Homestead => Some(40),
+            _ => Some(700),
+        },
+        Opcode::SUICIDE => match fork {
+            Fork::Frontier | Fork::Homestead => Some(0),
+            _ => Some(5000),
+        },
 
         _ => None,
     }
 }
 
+/// Returns the first fork in which `op` is valid
+pub fn introduced_in(op: Opcode) -> Fork {
+    match op {
+        Opcode::DELEGATECALL => Fork::Homestead,
+        Opcode::REVERT | Opcode::RETURNDATASIZE | Opcode::RETURNDATACOPY | Opcode::STATICCALL => Fork::Byzantium,
+        Opcode::SHL | Opcode::SHR | Opcode::SAR | Opcode::EXTCODEHASH | Opcode::CREATE2 => Fork::Constantinople,
+        Opcode::CHAINID | Opcode::SELFBALANCE => Fork::Istanbul,
+        Opcode::BASEFEE => Fork::London,
+        _ => Fork::Frontier,
+    }
+}
+
+/// Returns true for the opcodes whose cost depends on whether the account or storage slot they
+/// access is warm, from Berlin on
+fn accesses_state(op: Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::BALANCE
+            | Opcode::EXTCODESIZE
+            | Opcode::EXTCODECOPY
+            | Opcode::EXTCODEHASH
+            | Opcode::SLOAD
+            | Opcode::CALL
+            | Opcode::CALLCODE
+            | Opcode::DELEGATECALL
+            | Opcode::STATICCALL
+    )
+}
+
+/// Returns the cost of an SSTORE that writes `new` to a slot holding `current`, which held
+/// `original` at the start of the transaction, and the change it makes to the refund counter. The
+/// extra cost of a cold slot is not included. From Istanbul on this follows the net gas metering of
+/// EIP-2200, and before it only the current value matters.
+pub fn sstore_cost(fork: Fork, original: M256, current: M256, new: M256) -> (u64, i64) {
+    let zero = M256::zero();
+    let clears = if fork >= Fork::London {
+        SSTORE_CLEARS_LONDON
+    } else {
+        SSTORE_CLEARS
+    } as i64;
+    if fork < Fork::Istanbul {
+        return match (current == zero, new == zero) {
+            (true, false) => (SSTORE_SET, 0),
+            (false, true) => (SSTORE_RESET, clears),
+            _ => (SSTORE_RESET, 0),
+        };
+    }
+
+    // From Berlin on, part of the reset cost is charged as the cold slot cost instead
+    let reset = if fork >= Fork::Berlin {
+        SSTORE_RESET - COLD_SLOAD
+    } else {
+        SSTORE_RESET
+    };
+    let sload = get_cost(Opcode::SLOAD, fork).unwrap_or(0) as u64;
+    if current == new {
+        return (sload, 0);
+    }
+    if original == current {
+        if original == zero {
+            return (SSTORE_SET, 0);
+        }
+        let refund = if new == zero { clears } else { 0 };
+        return (reset, refund);
+    }
+
+    // The slot was already written in this transaction
+    let mut refund = 0;
+    if original != zero {
+        if current == zero {
+            refund -= clears;
+        }
+        if new == zero {
+            refund += clears;
+        }
+    }
+    if original == new {
+        refund += if original == zero {
+            (SSTORE_SET - sload) as i64
+        } else {
+            (reset - sload) as i64
+        };
+    }
+    (sload, refund)
+}
+
+/// Returns how much of the refund counter is paid back at the end of execution that used
+/// `gas_used` gas. It is capped at half of the gas used, or a fifth from London on.
+pub fn capped_refund(refund: i64, gas_used: u64, fork: Fork) -> u64 {
+    let quotient = if fork >= Fork::London { 5 } else { 2 };
+    cmp::min(cmp::max(refund, 0) as u64, gas_used / quotient)
+}
+
+/// Returns the refund for the first SELFDESTRUCT of a contract in a transaction
+pub fn selfdestruct_refund(fork: Fork) -> i64 {
+    if fork >= Fork::London {
+        0
+    } else {
+        SELFDESTRUCT_REFUND as i64
+    }
+}
+
+/// Returns the number of 32 byte words needed to hold `len` bytes
+pub fn to_words(len: u64) -> u64 {
+    len.div_ceil(32)
+}
+
+/// Total cost of having `words` words of memory active. Expanding memory costs the difference
+/// between the new and the old total, so the cost grows quadratically.
+pub fn memory_cost(words: u64) -> u64 {
+    MEMORY_WORD * words + (words * words) / QUAD_COEFF_DIV
+}
+
+/// Cost of copying `len` bytes with one of the *COPY opcodes, excluding the static part
+pub fn copy_cost(len: u64) -> u64 {
+    COPY_WORD * to_words(len)
+}
+
+/// Cost of hashing `len` bytes with SHA3, excluding the static part
+pub fn sha3_cost(len: u64) -> u64 {
+    SHA3_WORD * to_words(len)
+}
+
+/// Cost of the data portion of a LOG of `len` bytes
+pub fn log_cost(len: u64) -> u64 {
+    LOG_DATA * len
+}
+
+/// Cost of an exponent of `exponent_bytes` significant bytes, excluding the static part
+pub fn exp_cost(exponent_bytes: u64, fork: Fork) -> u64 {
+    if fork >= Fork::SpuriousDragon {
+        EXP_BYTE * exponent_bytes
+    } else {
+        EXP_BYTE_FRONTIER * exponent_bytes
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
     fn get_add_cost() {
-        let cost = get_cost(Opcode::ADD);
+        let cost = get_cost(Opcode::ADD, Fork::latest());
         assert_eq!(cost.unwrap(), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn get_repriced_costs() {
+        assert_eq!(get_cost(Opcode::SLOAD, Fork::Frontier), Some(50));
+        assert_eq!(get_cost(Opcode::SLOAD, Fork::TangerineWhistle), Some(200));
+        assert_eq!(get_cost(Opcode::SLOAD, Fork::Istanbul), Some(800));
+        assert_eq!(get_cost(Opcode::SLOAD, Fork::Berlin), Some(100));
+        assert_eq!(get_cost(Opcode::CALL, Fork::Homestead), Some(40));
+        assert_eq!(get_cost(Opcode::CALL, Fork::Byzantium), Some(700));
+        assert_eq!(get_cost(Opcode::BALANCE, Fork::Istanbul), Some(700));
+        assert_eq!(get_cost(Opcode::SUICIDE, Fork::Frontier), Some(0));
+        assert_eq!(exp_cost(2, Fork::Homestead), 20);
+        assert_eq!(exp_cost(2, Fork::SpuriousDragon), 100);
+    }
+
+    #[test]
+    fn opcodes_are_invalid_before_their_fork() {
+        assert_eq!(get_cost(Opcode::DELEGATECALL, Fork::Frontier), None);
+        assert_eq!(get_cost(Opcode::REVERT, Fork::SpuriousDragon), None);
+        assert_eq!(get_cost(Opcode::REVERT, Fork::Byzantium), Some(0));
+        assert_eq!(get_cost(Opcode::CREATE2, Fork::Byzantium), None);
+        assert_eq!(get_cost(Opcode::SELFBALANCE, Fork::Constantinople), None);
+        assert_eq!(get_cost(Opcode::BASEFEE, Fork::Berlin), None);
+        assert_eq!(get_cost(Opcode::BASEFEE, Fork::London), Some(2));
+    }
+
+    #[test]
+    fn sstore_cost_before_istanbul() {
+        let (zero, one, two) = (M256::zero(), M256::one(), M256::from(2));
+        assert_eq!(sstore_cost(Fork::Byzantium, zero, zero, one), (20000, 0));
+        assert_eq!(sstore_cost(Fork::Byzantium, one, one, two), (5000, 0));
+        assert_eq!(sstore_cost(Fork::Byzantium, one, one, zero), (5000, 15000));
+        assert_eq!(sstore_cost(Fork::Byzantium, one, one, one), (5000, 0));
+    }
+
+    #[test]
+    fn sstore_net_gas_metering() {
+        let (zero, one, two) = (M256::zero(), M256::one(), M256::from(2));
+        assert_eq!(sstore_cost(Fork::Istanbul, one, one, one), (800, 0));
+        assert_eq!(sstore_cost(Fork::Istanbul, zero, zero, one), (20000, 0));
+        assert_eq!(sstore_cost(Fork::Istanbul, one, one, zero), (5000, 15000));
+        assert_eq!(sstore_cost(Fork::Istanbul, one, zero, one), (800, -15000 + 4200));
+        assert_eq!(sstore_cost(Fork::Istanbul, zero, one, zero), (800, 19200));
+        assert_eq!(sstore_cost(Fork::Istanbul, one, two, zero), (800, 15000));
+        assert_eq!(sstore_cost(Fork::Berlin, one, one, zero), (2900, 15000));
+        assert_eq!(sstore_cost(Fork::Berlin, zero, one, zero), (100, 19900));
+        assert_eq!(sstore_cost(Fork::London, one, one, zero), (2900, 4800));
+    }
+
+    #[test]
+    fn refund_is_capped() {
+        assert_eq!(capped_refund(15000, 5006, Fork::Istanbul), 2503);
+        assert_eq!(capped_refund(15000, 100_000, Fork::Istanbul), 15000);
+        assert_eq!(capped_refund(4800, 5006, Fork::London), 1001);
+        assert_eq!(capped_refund(-1, 5006, Fork::London), 0);
+    }
+
+    #[test]
+    fn get_memory_cost() {
+        assert_eq!(memory_cost(0), 0);
+        assert_eq!(memory_cost(1), 3);
+        assert_eq!(memory_cost(1024), 3 * 1024 + 2048);
+    }
+
+    #[test]
+    fn get_copy_cost() {
+        assert_eq!(copy_cost(0), 0);
+        assert_eq!(copy_cost(1), 3);
+        assert_eq!(copy_cost(33), 6);
+    }
+}
@@ -0,0 +1,72 @@
+//! Module for the interface between the VM and the world state it executes against
+
+use bigint::{Address, H256, M256, U256};
+use errors::StorageError;
+use eth_log::Log;
+use keccak_hash::keccak;
+
+/// Everything the VM needs from outside of the frames it is executing. The VM is generic over its
+/// Host, so the same code runs against an in-memory state in tests and the world state of a node.
+pub trait Host {
+    /// Returns true if there is an account at `address`
+    fn exists(&self, address: Address) -> bool;
+    /// Returns the balance of the account at `address`
+    fn balance(&self, address: Address) -> U256;
+    /// Returns the nonce of the account at `address`
+    fn nonce(&self, address: Address) -> U256;
+    /// Returns the code of the account at `address`
+    fn code(&self, address: Address) -> Vec<u8>;
+    /// Reads the storage slot `index` of the account at `address`
+    fn storage(&self, address: Address, index: U256) -> M256;
+    /// Reads the value the storage slot `index` of the account at `address` held at the start of
+    /// the current transaction
+    fn original_storage(&self, address: Address, index: U256) -> M256;
+    /// Writes `value` to the storage slot `index` of the account at `address`, replacing what was
+    /// there. Writing zero clears the slot.
+    fn set_storage(&mut self, address: Address, index: U256, value: M256) -> Result<(), StorageError>;
+    /// Ends the current transaction, so that the current value of every storage slot becomes its
+    /// original value
+    fn commit_storage(&mut self);
+    /// Sets the balance of the account at `address`, creating the account if needed
+    fn set_balance(&mut self, address: Address, balance: U256);
+    /// Sets the nonce of the account at `address`, creating the account if needed
+    fn set_nonce(&mut self, address: Address, nonce: U256);
+    /// Sets the code of the account at `address`, creating the account if needed
+    fn set_code(&mut self, address: Address, code: Vec<u8>);
+    /// Removes the account at `address` along with its storage
+    fn remove_account(&mut self, address: Address);
+    /// Returns the hash of the block at height `number`, or zero if it is not known
+    fn block_hash(&self, number: U256) -> H256;
+    /// Receives a log emitted by execution that completed successfully
+    fn log(&mut self, log: Log);
+
+    /// Returns the hash of the code of the account at `address`, or zero if there is no account
+    fn code_hash(&self, address: Address) -> H256 {
+        if !self.exists(address) {
+            return H256::zero();
+        }
+        H256::from(&*keccak(self.code(address)))
+    }
+
+    /// Returns true if the account at `address` has no code, a zero nonce and a zero balance, as
+    /// defined by EIP-161. Accounts that do not exist are empty too.
+    fn is_empty(&self, address: Address) -> bool {
+        self.nonce(address).is_zero() && self.balance(address).is_zero() && self.code(address).is_empty()
+    }
+
+    /// Moves `value` wei from `from` to `to`. Returns false, and changes nothing, if `from` cannot
+    /// afford it.
+    fn transfer(&mut self, from: Address, to: Address, value: U256) -> bool {
+        let from_balance = self.balance(from);
+        if from_balance < value {
+            return false;
+        }
+        if value.is_zero() || from == to {
+            return true;
+        }
+        self.set_balance(from, from_balance - value);
+        let to_balance = self.balance(to);
+        self.set_balance(to, to_balance + value);
+        true
+    }
+}
@@ -0,0 +1,409 @@
+//! Module for the journal, which records every change execution makes to the world state so that
+//! the changes of a call that fails can be undone
+
+use access_list::AccessList;
+use bigint::{Address, H256, M256, U256};
+use errors::StorageError;
+use eth_log::Log;
+use host::Host;
+
+/// A point in the journal that changes can be reverted to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checkpoint(usize);
+
+/// A single change, holding what is needed to undo it
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    /// An account that did not exist was created
+    Created(Address),
+    Balance(Address, U256),
+    Nonce(Address, U256),
+    Code(Address, Vec<u8>),
+    Storage(Address, U256, M256),
+    Log,
+    SelfDestruct,
+    Refund(i64),
+    AccessedAddress(Address),
+    AccessedSlot(Address, U256),
+    Touched,
+}
+
+/// A Host that passes every change on to the Host it wraps, while recording how to undo it. Logs,
+/// self-destructs, the refund counter, the access list and the accounts touched only live in the
+/// journal until the transaction is finalized, as they only matter to the transaction that made
+/// them.
+pub struct Journal<H: Host> {
+    host: H,
+    entries: Vec<Entry>,
+    logs: Vec<Log>,
+    selfdestructs: Vec<Address>,
+    refund: i64,
+    access_list: AccessList,
+    touched: Vec<Address>,
+}
+
+impl<H: Host> Journal<H> {
+    /// Creates and returns a new, empty Journal on top of `host`
+    pub fn new(host: H) -> Journal<H> {
+        Journal {
+            host,
+            entries: vec![],
+            logs: vec![],
+            selfdestructs: vec![],
+            refund: 0,
+            access_list: AccessList::new(),
+            touched: vec![],
+        }
+    }
+
+    /// Returns the host the journal writes to
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+
+    /// Returns a checkpoint that every change made from now on can be reverted to
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.entries.len())
+    }
+
+    /// Keeps the changes made since `checkpoint`. They can still be undone by reverting to an
+    /// earlier checkpoint.
+    pub fn commit(&mut self, checkpoint: Checkpoint) {
+        debug_assert!(checkpoint.0 <= self.entries.len());
+    }
+
+    /// Undoes every change made since `checkpoint`, most recent first
+    pub fn revert(&mut self, checkpoint: Checkpoint) {
+        while self.entries.len() > checkpoint.0 {
+            match self.entries.pop().expect("the journal is longer than the checkpoint") {
+                Entry::Created(address) => self.host.remove_account(address),
+                Entry::Balance(address, balance) => self.host.set_balance(address, balance),
+                Entry::Nonce(address, nonce) => self.host.set_nonce(address, nonce),
+                Entry::Code(address, code) => self.host.set_code(address, code),
+                Entry::Storage(address, index, value) => {
+                    // Writing back a value the slot already held cannot fail
+                    let _ = self.host.set_storage(address, index, value);
+                }
+                Entry::Log => {
+                    self.logs.pop();
+                }
+                Entry::SelfDestruct => {
+                    self.selfdestructs.pop();
+                }
+                Entry::Refund(change) => self.refund -= change,
+                Entry::AccessedAddress(address) => self.access_list.forget_address(address),
+                Entry::AccessedSlot(address, index) => self.access_list.forget_slot(address, index),
+                Entry::Touched => {
+                    self.touched.pop();
+                }
+            }
+        }
+    }
+
+    /// Ends the transaction: hands the logs to the host, removes the accounts that destroyed
+    /// themselves and makes the current storage values the original ones. Nothing done before can
+    /// be reverted any more.
+    pub fn finalize(&mut self) {
+        for log in self.logs.iter() {
+            self.host.log(log.clone());
+        }
+        for &address in self.selfdestructs.iter() {
+            self.host.remove_account(address);
+        }
+        self.host.commit_storage();
+        self.entries.clear();
+        self.touched.clear();
+    }
+
+    /// Removes every account touched so far that is empty, as EIP-161 has it done at the end of a
+    /// transaction
+    pub fn clear_empty_accounts(&mut self) {
+        for &address in self.touched.iter() {
+            if self.host.exists(address) && self.host.is_empty(address) {
+                self.host.remove_account(address);
+            }
+        }
+    }
+
+    /// Returns the logs emitted so far
+    pub fn logs(&self) -> &[Log] {
+        &self.logs
+    }
+
+    /// Marks the account at `address` to be destroyed at the end of the transaction. Returns true if
+    /// it was not marked already.
+    pub fn selfdestruct(&mut self, address: Address) -> bool {
+        if self.selfdestructs.contains(&address) {
+            return false;
+        }
+        self.selfdestructs.push(address);
+        self.entries.push(Entry::SelfDestruct);
+        true
+    }
+
+    /// Returns the accounts that will be destroyed at the end of the transaction
+    pub fn selfdestructs(&self) -> &[Address] {
+        &self.selfdestructs
+    }
+
+    /// Changes the refund counter by `change`, which may be negative
+    pub fn add_refund(&mut self, change: i64) {
+        self.refund += change;
+        self.entries.push(Entry::Refund(change));
+    }
+
+    /// Returns the refund counter
+    pub fn refund(&self) -> i64 {
+        self.refund
+    }
+
+    /// Marks `address` as warm. Returns true if it was cold until now.
+    pub fn access_address(&mut self, address: Address) -> bool {
+        let cold = self.access_list.access_address(address);
+        if cold {
+            self.entries.push(Entry::AccessedAddress(address));
+        }
+        cold
+    }
+
+    /// Marks the storage slot `index` of the account at `address` as warm. Returns true if it was
+    /// cold until now.
+    pub fn access_slot(&mut self, address: Address, index: U256) -> bool {
+        let cold = self.access_list.access_slot(address, index);
+        if cold {
+            self.entries.push(Entry::AccessedSlot(address, index));
+        }
+        cold
+    }
+
+    /// Marks the account at `address` as touched, which changing its balance, nonce or code also
+    /// does. A call touches its target even when it sends no value.
+    pub fn touch(&mut self, address: Address) {
+        if !self.touched.contains(&address) {
+            self.touched.push(address);
+            self.entries.push(Entry::Touched);
+        }
+    }
+
+    /// Returns the accounts touched so far
+    pub fn touched(&self) -> &[Address] {
+        &self.touched
+    }
+
+    fn record_creation(&mut self, address: Address) {
+        if !self.host.exists(address) {
+            self.entries.push(Entry::Created(address));
+        }
+    }
+}
+
+impl<H: Host> Host for Journal<H> {
+    fn exists(&self, address: Address) -> bool {
+        self.host.exists(address)
+    }
+
+    fn balance(&self, address: Address) -> U256 {
+        self.host.balance(address)
+    }
+
+    fn nonce(&self, address: Address) -> U256 {
+        self.host.nonce(address)
+    }
+
+    fn code(&self, address: Address) -> Vec<u8> {
+        self.host.code(address)
+    }
+
+    fn code_hash(&self, address: Address) -> H256 {
+        self.host.code_hash(address)
+    }
+
+    fn storage(&self, address: Address, index: U256) -> M256 {
+        self.host.storage(address, index)
+    }
+
+    fn original_storage(&self, address: Address, index: U256) -> M256 {
+        self.host.original_storage(address, index)
+    }
+
+    /// Storage is only written by the code of its account, so writing it does not touch the account:
+    /// an account with code is never empty.
+    fn set_storage(&mut self, address: Address, index: U256, value: M256) -> Result<(), StorageError> {
+        self.record_creation(address);
+        let previous = self.host.storage(address, index);
+        self.host.set_storage(address, index, value)?;
+        self.entries.push(Entry::Storage(address, index, previous));
+        Ok(())
+    }
+
+    fn commit_storage(&mut self) {
+        self.host.commit_storage();
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.record_creation(address);
+        self.touch(address);
+        self.entries.push(Entry::Balance(address, self.host.balance(address)));
+        self.host.set_balance(address, balance);
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: U256) {
+        self.record_creation(address);
+        self.touch(address);
+        self.entries.push(Entry::Nonce(address, self.host.nonce(address)));
+        self.host.set_nonce(address, nonce);
+    }
+
+    fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.record_creation(address);
+        self.touch(address);
+        self.entries.push(Entry::Code(address, self.host.code(address)));
+        self.host.set_code(address, code);
+    }
+
+    /// Removing an account is not journaled. Accounts are only removed once a transaction has
+    /// finished, when there is nothing left to revert.
+    fn remove_account(&mut self, address: Address) {
+        self.host.remove_account(address);
+    }
+
+    fn block_hash(&self, number: U256) -> H256 {
+        self.host.block_hash(number)
+    }
+
+    fn log(&mut self, log: Log) {
+        self.logs.push(log);
+        self.entries.push(Entry::Log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::State;
+
+    #[test]
+    fn revert_undoes_changes_since_checkpoint() {
+        let address = Address::random();
+        let mut journal = Journal::new(State::new().with_account(address, 10.into(), vec![]));
+        assert!(journal.set_storage(address, 1.into(), 5.into()).is_ok());
+        let checkpoint = journal.checkpoint();
+        journal.set_balance(address, 20.into());
+        journal.set_nonce(address, 1.into());
+        journal.set_code(address, vec![0x00]);
+        assert!(journal.set_storage(address, 1.into(), 6.into()).is_ok());
+        journal.revert(checkpoint);
+        assert_eq!(journal.balance(address), U256::from(10));
+        assert_eq!(journal.nonce(address), U256::zero());
+        assert!(journal.code(address).is_empty());
+        assert_eq!(journal.storage(address, 1.into()), M256::from(5));
+    }
+
+    #[test]
+    fn revert_removes_created_accounts() {
+        let from = Address::random();
+        let to = Address::random();
+        let mut journal = Journal::new(State::new().with_account(from, 10.into(), vec![]));
+        let checkpoint = journal.checkpoint();
+        assert!(journal.transfer(from, to, 4.into()));
+        assert!(journal.exists(to));
+        journal.revert(checkpoint);
+        assert!(!journal.exists(to));
+        assert_eq!(journal.balance(from), U256::from(10));
+    }
+
+    #[test]
+    fn nested_checkpoints() {
+        let address = Address::random();
+        let mut journal = Journal::new(State::new());
+        let outer = journal.checkpoint();
+        journal.set_balance(address, 1.into());
+        let inner = journal.checkpoint();
+        journal.set_balance(address, 2.into());
+        journal.commit(inner);
+        assert_eq!(journal.balance(address), U256::from(2));
+        journal.revert(outer);
+        assert!(!journal.exists(address));
+    }
+
+    #[test]
+    fn revert_undoes_substate() {
+        let address = Address::random();
+        let mut journal = Journal::new(State::new());
+        journal.log(Log {
+            address,
+            data: vec![],
+            topics: vec![],
+        });
+        let checkpoint = journal.checkpoint();
+        journal.log(Log {
+            address,
+            data: vec![1],
+            topics: vec![],
+        });
+        assert!(journal.selfdestruct(address));
+        assert!(!journal.selfdestruct(address));
+        journal.add_refund(15000);
+        assert!(journal.access_address(address));
+        journal.revert(checkpoint);
+        assert_eq!(journal.logs().len(), 1);
+        assert!(journal.selfdestructs().is_empty());
+        assert_eq!(journal.refund(), 0);
+        assert!(journal.access_address(address));
+    }
+
+    #[test]
+    fn revert_untouches_accounts() {
+        let (first, second) = (Address::random(), Address::random());
+        let mut journal = Journal::new(State::new());
+        journal.touch(first);
+        let checkpoint = journal.checkpoint();
+        journal.touch(second);
+        journal.set_balance(first, 1.into());
+        assert_eq!(journal.touched(), &[first, second]);
+        journal.revert(checkpoint);
+        assert_eq!(journal.touched(), &[first]);
+    }
+
+    #[test]
+    fn clears_touched_empty_accounts() {
+        let (empty, funded, untouched) = (Address::random(), Address::random(), Address::random());
+        let state = State::new()
+            .with_account(empty, U256::zero(), vec![])
+            .with_account(funded, U256::zero(), vec![])
+            .with_account(untouched, U256::zero(), vec![]);
+        let mut journal = Journal::new(state);
+        journal.touch(empty);
+        journal.set_balance(funded, 5.into());
+        journal.clear_empty_accounts();
+        assert!(!journal.exists(empty));
+        assert!(journal.exists(funded));
+        assert!(journal.exists(untouched));
+    }
+
+    #[test]
+    fn finalize_removes_destroyed_accounts() {
+        let address = Address::random();
+        let mut journal = Journal::new(State::new().with_account(address, 10.into(), vec![0x00]));
+        journal.selfdestruct(address);
+        assert!(journal.exists(address));
+        journal.finalize();
+        assert!(!journal.exists(address));
+    }
+
+    #[test]
+    fn finalize_hands_logs_to_host() {
+        let address = Address::random();
+        let mut journal = Journal::new(State::new());
+        journal.log(Log {
+            address,
+            data: vec![],
+            topics: vec![],
+        });
+        journal.finalize();
+        assert_eq!(journal.host().logs().len(), 1);
+        // Nothing before finalizing can be reverted
+        journal.revert(Checkpoint::default());
+        assert_eq!(journal.logs().len(), 1);
+    }
+}
@@ -1,14 +1,38 @@
+extern crate aurora_engine_modexp;
 extern crate bigint;
+extern crate bn;
 extern crate env_logger;
 extern crate keccak_hash;
 extern crate log;
 extern crate rlp;
+extern crate rustc_hex;
+extern crate secp256k1;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
 extern crate trie;
 
-mod errors;
+mod access_list;
+pub mod analysis;
+pub mod assembler;
+pub mod debugger;
+pub mod disassembler;
+pub mod environment;
+pub mod errors;
 pub mod eth_log;
+pub mod fork;
+mod frame;
 mod gas_prices;
-mod memory;
-mod opcodes;
+pub mod host;
+pub mod journal;
+pub mod memory;
+pub mod opcodes;
+pub mod outcome;
+pub mod precompiles;
+pub mod stack;
+pub mod state;
 mod storage;
+pub mod tracer;
 pub mod vm;
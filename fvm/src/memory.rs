@@ -2,43 +2,59 @@
 
 use bigint::{M256, U256};
 use errors::*;
+use std::cmp;
+
+/// Largest size in bytes memory may be expanded to if no other limit is set. Well beyond what any
+/// block gas limit can pay for, but small enough that allocating it is harmless.
+pub const DEFAULT_MEMORY_LIMIT: usize = 32 * 1024 * 1024;
 
 /// A volatile area of memory that is created per-transaction. The following constraints must be
 /// observed when interacting with it:
+///
 /// 1. A Read must be 256 bits
 /// 2. A Write can be 8 bits or 256 bits
 /// 3. Any expansion of the Memory area costs gas, and the cost scales quadratically
 /// 4. Expansion is done by the word, so 256-bits at a time
-
-/// Trait for all Memory systems
+///
+/// Trait for all Memory systems. Every access past the end of memory first expands it, by whole
+/// words, with zeros. Accesses that would expand it past its limit fail with
+/// `VMError::MemoryError` instead, however large the offset.
 pub trait Memory {
-    // Read a single u32 at index `index`
-    fn read(&self, index: M256) -> M256;
-    // Read a slice of u32s starting at init_off_u of size `init_size_u`
-    fn read_slice(&self, init_off_u: U256, init_size_u: U256) -> &[u8];
+    // Reads a word at `index`
+    fn read(&mut self, index: M256) -> Result<M256>;
+    // Reads a slice of `init_size_u` bytes starting at `init_off_u`
+    fn read_slice(&mut self, init_off_u: U256, init_size_u: U256) -> Result<&[u8]>;
     // Reads a single byte at index `index`
-    fn read_byte(&self, index: M256) -> u8;
-    // Writes a single u32 at `index`
+    fn read_byte(&mut self, index: M256) -> Result<u8>;
+    // Writes a word at `index`
     fn write(&mut self, index: M256, value: M256) -> Result<()>;
-    // Writes a single `u8` at `index
+    // Writes a single `u8` at `index`
     fn write_byte(&mut self, index: M256, value: u8) -> Result<()>;
-    // Returns the size of the memory
+    // Expands memory so that it covers `len` bytes starting at `offset`
+    fn expand(&mut self, offset: U256, len: U256) -> Result<()>;
+    // Returns the size of the memory in bytes
     fn size(&self) -> M256;
+    // Returns the whole of the memory, as far as it has been expanded
+    fn as_slice(&self) -> &[u8];
     // Prints the contents of the memory. Mainly useful for debugging.
     fn print(&self) -> String;
     // Copies a section of memory starting at `start` and of length `len`
-    fn copy_from_memory(&self, start: U256, len: U256) -> Vec<u8>;
-
-    fn copy_into_memory(&mut self, values: &[u8], start: U256, value_start: U256, len: U256);
+    fn copy_from_memory(&mut self, start: U256, len: U256) -> Result<Vec<u8>>;
+    // Copies `len` bytes of `values` starting at `value_start` into memory at `start`. Bytes past
+    // the end of `values` are copied as zeros.
+    fn copy_into_memory(&mut self, values: &[u8], start: U256, value_start: U256, len: U256) -> Result<()>;
 }
 
-/// Simple implementation of memory using Rust Vecs
+/// Simple implementation of memory using Rust Vecs. Words and copies go through it a byte at a
+/// time, which keeps it easy to check; FastMemory is the one to use where speed matters.
 #[derive(Debug, PartialEq)]
 pub struct SimpleMemory {
     /// Memory is represented as a simple Vector of `u8`s
     memory: Vec<u8>,
     /// Tracks how many times we've had to expand memory
     expansions: usize,
+    /// Largest size in bytes the memory may be expanded to
+    limit: usize,
 }
 
 impl SimpleMemory {
@@ -47,99 +63,291 @@ impl SimpleMemory {
         SimpleMemory {
             memory: Vec::new(),
             expansions: 0,
+            limit: DEFAULT_MEMORY_LIMIT,
         }
     }
 
-    // Resizes the memory vector if needed. This will be called automatically, and will increment
-    // the expansion count
-    fn resize_if_needed(&mut self, index: usize) -> Result<()> {
-        if index >= self.memory.len() {
-            self.memory.resize(index + 1, 0);
-            self.expansions += 1;
-        }
-        Ok(())
+    /// Sets the largest size in bytes the memory may be expanded to
+    pub fn with_limit(mut self, limit: usize) -> SimpleMemory {
+        self.limit = limit;
+        self
+    }
+}
+
+impl Default for SimpleMemory {
+    fn default() -> SimpleMemory {
+        SimpleMemory::new()
     }
 }
 
 impl Memory for SimpleMemory {
     /// Reads a `word` at the provided index
-    fn read(&self, index: M256) -> M256 {
+    fn read(&mut self, index: M256) -> Result<M256> {
+        let index = index.into();
+        self.expand(index, U256::from(32))?;
         let index = index.as_usize();
-        self.memory[index..index + 32]
-            .iter()
-            .map(|v| v.clone())
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .into()
+        Ok(M256::from(&self.memory[index..index + 32]))
     }
 
     /// Reads a single byte at the provided index
-    fn read_byte(&self, index: M256) -> u8 {
-        self.memory[index.as_usize()].clone()
+    fn read_byte(&mut self, index: M256) -> Result<u8> {
+        let index = index.into();
+        self.expand(index, U256::one())?;
+        Ok(self.memory[index.as_usize()])
     }
 
-    fn read_slice(&self, init_off_u: U256, init_size_u: U256) -> &[u8] {
-        let off = init_off_u.low_u64() as usize;
-        let size = init_size_u.low_u64() as usize;
-        &self.memory[off..off + size]
+    fn read_slice(&mut self, init_off_u: U256, init_size_u: U256) -> Result<&[u8]> {
+        if init_size_u.is_zero() {
+            return Ok(&[]);
+        }
+        self.expand(init_off_u, init_size_u)?;
+        let off = init_off_u.as_usize();
+        Ok(&self.memory[off..off + init_size_u.as_usize()])
     }
 
-    /// Writes a `word` at the specified index. This will resize the capacity
-    /// if needed, and will overwrite any existing bytes if there is overlap.
+    /// Writes a `word` at the specified index, overwriting any existing bytes if there is overlap
     fn write(&mut self, index: M256, value: M256) -> Result<()> {
-        let index = index.as_usize();
-        self.resize_if_needed(index)?;
+        let index = index.into();
+        self.expand(index, U256::from(32))?;
         for i in 0..32 {
-            let idx = M256::from(index + i);
+            let idx = M256::from(index + U256::from(i));
             self.write_byte(idx, value.index(i))?;
         }
         Ok(())
     }
 
-    /// Writes a single byte to the memory. This will resize the memory if
-    /// needed.
+    /// Writes a single byte to the memory
     fn write_byte(&mut self, index: M256, value: u8) -> Result<()> {
-        let index = index.as_usize();
-        self.resize_if_needed(index)?;
-        self.memory[index] = value;
+        let index = index.into();
+        self.expand(index, U256::one())?;
+        self.memory[index.as_usize()] = value;
         Ok(())
     }
 
-    /// Returns the current size of memory in words
+    /// Resizes the memory to the next whole word past the end of the range if it does not cover
+    /// it yet, incrementing the expansion count. A zero length never expands.
+    fn expand(&mut self, offset: U256, len: U256) -> Result<()> {
+        if len.is_zero() {
+            return Ok(());
+        }
+        let (end, overflow) = offset.overflowing_add(len);
+        if overflow || end > U256::from(self.limit) {
+            return Err(VMError::MemoryError);
+        }
+        let size = end.as_usize().div_ceil(32) * 32;
+        if size > self.limit {
+            return Err(VMError::MemoryError);
+        }
+        if size > self.memory.len() {
+            self.memory.resize(size, 0);
+            self.expansions += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the current size of memory in bytes
     fn size(&self) -> M256 {
         M256::from(self.memory.len())
     }
 
+    fn as_slice(&self) -> &[u8] {
+        &self.memory
+    }
+
     /// Prints the contents of memory
     fn print(&self) -> String {
-        String::from(format!("{:#?}", self.memory))
+        format!("{:#?}", self.memory)
     }
 
-    fn copy_from_memory(&self, start: U256, len: U256) -> Vec<u8> {
+    /// Copies `len` bytes starting at `start`. Bytes that have never been written read as zero.
+    fn copy_from_memory(&mut self, start: U256, len: U256) -> Result<Vec<u8>> {
+        self.expand(start, len)?;
         let mut result: Vec<u8> = Vec::new();
         let mut i = start;
         while i < start + len {
-            result.push(self.read_byte(i.into()));
+            result.push(self.read_byte(i.into())?);
             i = i + U256::from(1u64);
         }
-        result
+        Ok(result)
     }
 
     /// Copies a slice of values into memory with a start and end index
-    fn copy_into_memory(&mut self, values: &[u8], start: U256, value_start: U256, len: U256) {
+    fn copy_into_memory(&mut self, values: &[u8], start: U256, value_start: U256, len: U256) -> Result<()> {
+        self.expand(start, len)?;
         let value_len = U256::from(values.len());
         let mut i = start;
         let mut j = value_start;
         while i < start + len {
             if j < value_len {
                 let ju: usize = j.as_usize();
-                self.write_byte(i.into(), values[ju]).unwrap();
+                self.write_byte(i.into(), values[ju])?;
                 j = j + U256::from(1u64);
             } else {
-                self.write_byte(i.into(), 0u8).unwrap();
+                self.write_byte(i.into(), 0u8)?;
             }
             i = i + U256::from(1u64);
         }
+        Ok(())
+    }
+}
+
+/// Which implementation of `Memory` the frames of a VM are given
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryKind {
+    Simple,
+    Fast,
+}
+
+impl MemoryKind {
+    /// Creates and returns an empty memory of this kind that may grow to `limit` bytes
+    pub fn create(self, limit: usize) -> Box<Memory> {
+        match self {
+            MemoryKind::Simple => Box::new(SimpleMemory::new().with_limit(limit)),
+            MemoryKind::Fast => Box::new(FastMemory::new().with_limit(limit)),
+        }
+    }
+}
+
+/// Memory tuned for code that accesses it heavily. Offsets are checked and converted to machine
+/// integers once per access, without any 256-bit arithmetic, and every read, write and copy is a
+/// single slice operation.
+#[derive(Debug, PartialEq)]
+pub struct FastMemory {
+    /// Memory, always a whole number of words long
+    buffer: Vec<u8>,
+    /// Largest size in bytes the memory may be expanded to
+    limit: usize,
+}
+
+impl FastMemory {
+    /// Creates and returns a new FastMemory
+    pub fn new() -> FastMemory {
+        FastMemory {
+            buffer: Vec::new(),
+            limit: DEFAULT_MEMORY_LIMIT,
+        }
+    }
+
+    /// Sets the largest size in bytes the memory may be expanded to
+    pub fn with_limit(mut self, limit: usize) -> FastMemory {
+        self.limit = limit;
+        self
+    }
+
+    // Expands memory to cover `len` bytes starting at `offset`, and returns `offset` as a machine
+    // integer. `len` must not be zero.
+    #[inline]
+    fn reserve(&mut self, offset: U256, len: usize) -> Result<usize> {
+        if offset.bits() > 64 {
+            return Err(VMError::MemoryError);
+        }
+        let offset = offset.low_u64();
+        if offset > self.limit as u64 || len > self.limit - offset as usize {
+            return Err(VMError::MemoryError);
+        }
+        let offset = offset as usize;
+        let end = offset + len;
+        if end > self.buffer.len() {
+            let size = end.div_ceil(32) * 32;
+            if size > self.limit {
+                return Err(VMError::MemoryError);
+            }
+            self.buffer.resize(size, 0);
+        }
+        Ok(offset)
+    }
+
+    // Converts a length to a machine integer, failing if it could never fit in memory
+    #[inline]
+    fn length(&self, len: U256) -> Result<usize> {
+        if len > U256::from(self.limit) {
+            Err(VMError::MemoryError)
+        } else {
+            Ok(len.as_usize())
+        }
+    }
+}
+
+impl Default for FastMemory {
+    fn default() -> FastMemory {
+        FastMemory::new()
+    }
+}
+
+impl Memory for FastMemory {
+    fn read(&mut self, index: M256) -> Result<M256> {
+        let index = self.reserve(index.into(), 32)?;
+        Ok(M256::from(&self.buffer[index..index + 32]))
+    }
+
+    fn read_slice(&mut self, init_off_u: U256, init_size_u: U256) -> Result<&[u8]> {
+        if init_size_u.is_zero() {
+            return Ok(&[]);
+        }
+        let len = self.length(init_size_u)?;
+        let offset = self.reserve(init_off_u, len)?;
+        Ok(&self.buffer[offset..offset + len])
+    }
+
+    fn read_byte(&mut self, index: M256) -> Result<u8> {
+        let index = self.reserve(index.into(), 1)?;
+        Ok(self.buffer[index])
+    }
+
+    fn write(&mut self, index: M256, value: M256) -> Result<()> {
+        let index = self.reserve(index.into(), 32)?;
+        value.0.to_big_endian(&mut self.buffer[index..index + 32]);
+        Ok(())
+    }
+
+    fn write_byte(&mut self, index: M256, value: u8) -> Result<()> {
+        let index = self.reserve(index.into(), 1)?;
+        self.buffer[index] = value;
+        Ok(())
+    }
+
+    fn expand(&mut self, offset: U256, len: U256) -> Result<()> {
+        if len.is_zero() {
+            return Ok(());
+        }
+        let len = self.length(len)?;
+        self.reserve(offset, len).map(|_| ())
+    }
+
+    fn size(&self) -> M256 {
+        M256::from(self.buffer.len())
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn print(&self) -> String {
+        format!("{:#?}", self.buffer)
+    }
+
+    fn copy_from_memory(&mut self, start: U256, len: U256) -> Result<Vec<u8>> {
+        self.read_slice(start, len).map(|slice| slice.to_vec())
+    }
+
+    fn copy_into_memory(&mut self, values: &[u8], start: U256, value_start: U256, len: U256) -> Result<()> {
+        if len.is_zero() {
+            return Ok(());
+        }
+        let len = self.length(len)?;
+        let start = self.reserve(start, len)?;
+        let value_start = if value_start > U256::from(values.len()) {
+            values.len()
+        } else {
+            value_start.as_usize()
+        };
+        let copied = cmp::min(len, values.len() - value_start);
+        let (copy, zeros) = self.buffer[start..start + len].split_at_mut(copied);
+        copy.copy_from_slice(&values[value_start..value_start + copied]);
+        for byte in zeros {
+            *byte = 0;
+        }
+        Ok(())
     }
 }
 
@@ -149,10 +357,8 @@ mod tests {
 
     fn gen_simple_mem_with_data() -> SimpleMemory {
         let test_value = U256::from(5000);
-        let mut mem = SimpleMemory {
-            memory: vec![0; 32],
-            expansions: 0,
-        };
+        let mut mem = SimpleMemory::new();
+        mem.memory = vec![0; 32];
         test_value.to_big_endian(&mut mem.memory);
         mem
     }
@@ -166,22 +372,117 @@ mod tests {
     #[test]
     fn resize_memory() {
         let mut mem = SimpleMemory::new();
-        let result = mem.resize_if_needed(1024);
+        let result = mem.expand(1024.into(), 1.into());
         assert!(result.is_ok());
-        assert_eq!(mem.memory.len(), 1025);
+        // Memory grows by whole words
+        assert_eq!(mem.memory.len(), 1056);
+        assert_eq!(mem.expansions, 1);
     }
 
     #[test]
     fn read_word() {
-        let mem = gen_simple_mem_with_data();
-        let read_data = mem.read(0.into());
+        let mut mem = gen_simple_mem_with_data();
+        let read_data = mem.read(0.into()).unwrap();
         assert_eq!(read_data.as_u32(), 5000 as u32);
     }
 
     #[test]
     fn read_byte() {
-        let mem = gen_simple_mem_with_data();
-        let read_data = mem.read_byte(31.into());
+        let mut mem = gen_simple_mem_with_data();
+        let read_data = mem.read_byte(31.into()).unwrap();
         assert_eq!(read_data, 136 as u8);
     }
+
+    const KINDS: [MemoryKind; 2] = [MemoryKind::Simple, MemoryKind::Fast];
+
+    #[test]
+    fn reads_past_the_end_expand_with_zeros() {
+        for kind in &KINDS {
+            let mut mem = kind.create(DEFAULT_MEMORY_LIMIT);
+            mem.write(0.into(), M256::from(5000)).unwrap();
+            assert_eq!(mem.read(20.into()), Ok(M256::from(U256::from(5000) << 160)));
+            assert_eq!(mem.size(), M256::from(64));
+            assert_eq!(mem.read_byte(100.into()), Ok(0));
+            assert_eq!(mem.size(), M256::from(128));
+            assert_eq!(mem.copy_from_memory(126.into(), 4.into()), Ok(vec![0; 4]));
+            assert_eq!(mem.size(), M256::from(160));
+            assert_eq!(mem.as_slice().len(), 160);
+        }
+    }
+
+    #[test]
+    fn writes_words_and_bytes() {
+        for kind in &KINDS {
+            let mut mem = kind.create(DEFAULT_MEMORY_LIMIT);
+            assert!(mem.write(1.into(), M256::from(0xabcd)).is_ok());
+            assert!(mem.write_byte(0.into(), 0xff).is_ok());
+            assert_eq!(mem.size(), M256::from(64));
+            assert_eq!(mem.as_slice()[..2], [0xff, 0]);
+            assert_eq!(mem.as_slice()[31..34], [0xab, 0xcd, 0]);
+        }
+    }
+
+    #[test]
+    fn copies_into_memory_padding_with_zeros() {
+        for kind in &KINDS {
+            let mut mem = kind.create(DEFAULT_MEMORY_LIMIT);
+            mem.write(0.into(), M256::from(U256::max_value())).unwrap();
+            assert!(mem.copy_into_memory(&[1, 2, 3], 0.into(), 1.into(), 4.into()).is_ok());
+            assert_eq!(mem.as_slice()[..5], [2, 3, 0, 0, 0xff]);
+            // A source offset past the end of the values copies only zeros
+            let huge = U256::one() << 255;
+            assert!(mem.copy_into_memory(&[1, 2, 3], 0.into(), huge, 2.into()).is_ok());
+            assert_eq!(mem.as_slice()[..3], [0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn zero_lengths_never_expand() {
+        for kind in &KINDS {
+            let mut mem = kind.create(DEFAULT_MEMORY_LIMIT);
+            let huge = U256::one() << 255;
+            assert_eq!(mem.copy_from_memory(huge, 0.into()), Ok(vec![]));
+            assert!(mem.copy_into_memory(&[1], huge, 0.into(), 0.into()).is_ok());
+            assert!(mem.expand(huge, 0.into()).is_ok());
+            assert_eq!(mem.size(), M256::zero());
+        }
+    }
+
+    #[test]
+    fn expanding_past_the_limit_fails() {
+        for kind in &KINDS {
+            let mut mem = kind.create(64);
+            assert!(mem.write(32.into(), M256::one()).is_ok());
+            assert_eq!(mem.write(33.into(), M256::one()), Err(VMError::MemoryError));
+            assert_eq!(mem.read_byte(64.into()), Err(VMError::MemoryError));
+            let huge = M256::from(U256::one() << 255);
+            assert_eq!(mem.read(huge), Err(VMError::MemoryError));
+            assert_eq!(
+                mem.write(M256::from(U256::max_value()), M256::one()),
+                Err(VMError::MemoryError)
+            );
+            assert_eq!(mem.expand(1.into(), U256::max_value()), Err(VMError::MemoryError));
+            assert_eq!(mem.read(M256::from(u64::MAX)), Err(VMError::MemoryError));
+            assert_eq!(mem.size(), M256::from(64));
+        }
+    }
+
+    #[test]
+    fn kinds_agree() {
+        let data: Vec<u8> = (0..100).collect();
+        let memories: Vec<Vec<u8>> = KINDS
+            .iter()
+            .map(|kind| {
+                let mut mem = kind.create(DEFAULT_MEMORY_LIMIT);
+                mem.write(7.into(), M256::from(U256::max_value() / U256::from(3))).unwrap();
+                mem.copy_into_memory(&data, 20.into(), 90.into(), 40.into()).unwrap();
+                mem.write_byte(70.into(), 0x5a).unwrap();
+                let copy = mem.copy_from_memory(5.into(), 80.into()).unwrap();
+                mem.copy_into_memory(&copy, 100.into(), 0.into(), 80.into()).unwrap();
+                mem.as_slice().to_vec()
+            })
+            .collect();
+        assert_eq!(memories[0].len(), 192);
+        assert_eq!(memories[0], memories[1]);
+    }
 }
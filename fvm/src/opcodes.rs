@@ -1,8 +1,10 @@
+use std::fmt;
+
 type Gas = u32;
 
 /// Opcodes supported by the Ethereum VM. https://github.com/trailofbits/evm-opcodes is a good
 /// reference for them.
-#[derive(PartialEq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Opcode {
     STOP,
     ADD,
@@ -27,6 +29,9 @@ pub enum Opcode {
     XOR,
     NOT,
     BYTE,
+    SHL,
+    SHR,
+    SAR,
     SHA3,
     ADDRESS,
     BALANCE,
@@ -43,12 +48,16 @@ pub enum Opcode {
     EXTCODECOPY,
     RETURNDATASIZE,
     RETURNDATACOPY,
+    EXTCODEHASH,
     BLOCKHASH,
     COINBASE,
     TIMESTAMP,
     NUMBER,
     DIFFICULTY,
     GASLIMIT,
+    CHAINID,
+    SELFBALANCE,
+    BASEFEE,
     SLOAD,
     SSTORE,
     JUMP,
@@ -72,6 +81,9 @@ pub enum Opcode {
     CALLCODE,
     RETURN,
     DELEGATECALL,
+    CREATE2,
+    STATICCALL,
+    REVERT,
 }
 
 // Converts a byte into an Opcode for convenience
@@ -102,6 +114,9 @@ impl<'a> From<&'a u8> for Opcode {
             0x18 => Opcode::XOR,
             0x19 => Opcode::NOT,
             0x1a => Opcode::BYTE,
+            0x1b => Opcode::SHL,
+            0x1c => Opcode::SHR,
+            0x1d => Opcode::SAR,
 
             // Cryptographic Operations
             0x20 => Opcode::SHA3,
@@ -122,6 +137,7 @@ impl<'a> From<&'a u8> for Opcode {
             0x3c => Opcode::EXTCODECOPY,
             0x3d => Opcode::RETURNDATASIZE,
             0x3e => Opcode::RETURNDATACOPY,
+            0x3f => Opcode::EXTCODEHASH,
 
             // Block data functions
             0x40 => Opcode::BLOCKHASH,
@@ -130,6 +146,9 @@ impl<'a> From<&'a u8> for Opcode {
             0x43 => Opcode::NUMBER,
             0x44 => Opcode::DIFFICULTY,
             0x45 => Opcode::GASLIMIT,
+            0x46 => Opcode::CHAINID,
+            0x47 => Opcode::SELFBALANCE,
+            0x48 => Opcode::BASEFEE,
 
             // Stack, memory, storage, and flow operations
             0x50 => Opcode::POP,
@@ -228,9 +247,78 @@ impl<'a> From<&'a u8> for Opcode {
             0xf2 => Opcode::CALLCODE,
             0xf3 => Opcode::RETURN,
             0xf4 => Opcode::DELEGATECALL,
+            0xf5 => Opcode::CREATE2,
+            0xfa => Opcode::STATICCALL,
+            0xfd => Opcode::REVERT,
             0xfe => Opcode::INVALID,
             0xff => Opcode::SUICIDE,
             _ => Opcode::INVALID,
         }
     }
 }
+
+// Displays an Opcode by the mnemonic used in the yellow paper and other clients
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Opcode::PUSH(n) => write!(f, "PUSH{}", n),
+            Opcode::DUP(n) => write!(f, "DUP{}", n),
+            Opcode::SWAP(n) => write!(f, "SWAP{}", n),
+            Opcode::LOG(n) => write!(f, "LOG{}", n),
+            Opcode::SUICIDE => write!(f, "SELFDESTRUCT"),
+            ref opcode => write!(f, "{:?}", opcode),
+        }
+    }
+}
+
+/// Returns the mnemonic for `byte`, naming bytes that are not assigned to an opcode the way geth
+/// does
+pub fn mnemonic(byte: u8) -> String {
+    match Opcode::from(&byte) {
+        Opcode::INVALID if byte != 0xfe => format!("opcode 0x{:02x} not defined", byte),
+        opcode => opcode.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_byzantium_and_constantinople_opcodes() {
+        assert_eq!(Opcode::from(&0x1b), Opcode::SHL);
+        assert_eq!(Opcode::from(&0x1c), Opcode::SHR);
+        assert_eq!(Opcode::from(&0x1d), Opcode::SAR);
+        assert_eq!(Opcode::from(&0x3d), Opcode::RETURNDATASIZE);
+        assert_eq!(Opcode::from(&0x3e), Opcode::RETURNDATACOPY);
+        assert_eq!(Opcode::from(&0x3f), Opcode::EXTCODEHASH);
+        assert_eq!(Opcode::from(&0xf5), Opcode::CREATE2);
+        assert_eq!(Opcode::from(&0xfa), Opcode::STATICCALL);
+        assert_eq!(Opcode::from(&0xfd), Opcode::REVERT);
+    }
+
+    #[test]
+    fn decodes_istanbul_and_london_opcodes() {
+        assert_eq!(Opcode::from(&0x46), Opcode::CHAINID);
+        assert_eq!(Opcode::from(&0x47), Opcode::SELFBALANCE);
+        assert_eq!(Opcode::from(&0x48), Opcode::BASEFEE);
+    }
+
+    #[test]
+    fn decodes_unassigned_bytes_as_invalid() {
+        assert_eq!(Opcode::from(&0x1e), Opcode::INVALID);
+        assert_eq!(Opcode::from(&0xfe), Opcode::INVALID);
+    }
+
+    #[test]
+    fn displays_mnemonics() {
+        assert_eq!(Opcode::from(&0x01).to_string(), "ADD");
+        assert_eq!(Opcode::from(&0x7f).to_string(), "PUSH32");
+        assert_eq!(Opcode::from(&0x80).to_string(), "DUP1");
+        assert_eq!(Opcode::from(&0x9f).to_string(), "SWAP16");
+        assert_eq!(Opcode::from(&0xa2).to_string(), "LOG2");
+        assert_eq!(Opcode::from(&0xff).to_string(), "SELFDESTRUCT");
+        assert_eq!(mnemonic(0xfe), "INVALID");
+        assert_eq!(mnemonic(0x1e), "opcode 0x1e not defined");
+    }
+}
@@ -0,0 +1,89 @@
+//! Module for the result of running a VM to completion
+
+use errors::{ExecutionError, VMError};
+use eth_log::Log;
+
+/// The way in which execution came to an end
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Execution hit a STOP, or ran off the end of the code
+    Stop,
+    /// Execution hit a RETURN with the given output data
+    Return(Vec<u8>),
+    /// Execution hit a REVERT with the given output data. State changes are discarded, but unused
+    /// gas is not consumed.
+    Revert(Vec<u8>),
+    /// Execution halted exceptionally at the given instruction. State changes are discarded and
+    /// all gas is consumed.
+    Halt(ExecutionError),
+}
+
+impl Outcome {
+    /// Returns the reason execution failed, which is `VMError::Revert` for a REVERT, or None if it
+    /// succeeded
+    pub fn error(&self) -> Option<VMError> {
+        match self {
+            Outcome::Halt(e) => Some(e.error.clone()),
+            Outcome::Revert(_) => Some(VMError::Revert),
+            Outcome::Stop | Outcome::Return(_) => None,
+        }
+    }
+}
+
+/// Everything a caller needs to know once the VM has finished executing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionResult {
+    pub outcome: Outcome,
+    /// Gas consumed by execution, after the refund has been paid back
+    pub gas_used: u64,
+    /// Gas paid back for clearing storage. It is capped by the gas used, and is zero unless
+    /// execution succeeded.
+    pub gas_refunded: u64,
+    /// Logs emitted during execution. This is empty unless execution succeeded.
+    pub logs: Vec<Log>,
+}
+
+impl ExecutionResult {
+    /// Returns true if execution ended with STOP or RETURN
+    pub fn is_success(&self) -> bool {
+        matches!(self.outcome, Outcome::Stop | Outcome::Return(_))
+    }
+
+    /// Returns the output data of a RETURN or REVERT, or an empty slice otherwise
+    pub fn output(&self) -> &[u8] {
+        match self.outcome {
+            Outcome::Return(ref data) | Outcome::Revert(ref data) => data,
+            _ => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_of_stop_is_empty() {
+        let result = ExecutionResult {
+            outcome: Outcome::Stop,
+            gas_used: 0,
+            gas_refunded: 0,
+            logs: vec![],
+        };
+        assert!(result.is_success());
+        assert!(result.output().is_empty());
+    }
+
+    #[test]
+    fn revert_is_not_success() {
+        let result = ExecutionResult {
+            outcome: Outcome::Revert(vec![1, 2]),
+            gas_used: 0,
+            gas_refunded: 0,
+            logs: vec![],
+        };
+        assert!(!result.is_success());
+        assert_eq!(result.output(), &[1, 2]);
+        assert_eq!(result.outcome.error(), Some(VMError::Revert));
+    }
+}
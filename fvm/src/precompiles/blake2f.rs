@@ -0,0 +1,105 @@
+//! The BLAKE2b compression function F, as exposed by the precompile of EIP-152
+
+const IV: [u64; 8] = [
+    0x6a09_e667_f3bc_c908,
+    0xbb67_ae85_84ca_a73b,
+    0x3c6e_f372_fe94_f82b,
+    0xa54f_f53a_5f1d_36f1,
+    0x510e_527f_ade6_82d1,
+    0x9b05_688c_2b3e_6c1f,
+    0x1f83_d9ab_fb41_bd6b,
+    0x5be0_cd19_137e_2179,
+];
+
+// Order in which the message words are mixed in, for each round modulo 10
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// Length of the input the precompile accepts: the rounds, state, message, offset counters and
+/// final block flag
+pub const INPUT_LENGTH: usize = 213;
+
+/// Returns the number of rounds requested by `input`, which is also the gas it costs
+pub fn rounds(input: &[u8]) -> Option<u32> {
+    if input.len() != INPUT_LENGTH {
+        return None;
+    }
+    Some(u32::from_be_bytes([input[0], input[1], input[2], input[3]]))
+}
+
+/// Runs the compression function on `input`, returning the new state. Returns None if the input is
+/// not exactly 213 bytes long or the final block flag is neither 0 nor 1.
+pub fn run(input: &[u8]) -> Option<Vec<u8>> {
+    let rounds = rounds(input)?;
+    let last_block = match input[212] {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+    let read_words = |offset: usize, words: &mut [u64]| {
+        for (i, word) in words.iter_mut().enumerate() {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&input[offset + 8 * i..offset + 8 * i + 8]);
+            *word = u64::from_le_bytes(bytes);
+        }
+    };
+    let mut state = [0u64; 8];
+    let mut message = [0u64; 16];
+    let mut offsets = [0u64; 2];
+    read_words(4, &mut state);
+    read_words(68, &mut message);
+    read_words(196, &mut offsets);
+
+    compress(&mut state, &message, offsets, last_block, rounds);
+    let mut output = Vec::with_capacity(64);
+    for word in state.iter() {
+        output.extend_from_slice(&word.to_le_bytes());
+    }
+    Some(output)
+}
+
+fn g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+fn compress(state: &mut [u64; 8], message: &[u64; 16], offsets: [u64; 2], last_block: bool, rounds: u32) {
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(state);
+    v[8..].copy_from_slice(&IV);
+    v[12] ^= offsets[0];
+    v[13] ^= offsets[1];
+    if last_block {
+        v[14] = !v[14];
+    }
+    for round in 0..rounds as usize {
+        let s = &SIGMA[round % 10];
+        g(&mut v, 0, 4, 8, 12, message[s[0]], message[s[1]]);
+        g(&mut v, 1, 5, 9, 13, message[s[2]], message[s[3]]);
+        g(&mut v, 2, 6, 10, 14, message[s[4]], message[s[5]]);
+        g(&mut v, 3, 7, 11, 15, message[s[6]], message[s[7]]);
+        g(&mut v, 0, 5, 10, 15, message[s[8]], message[s[9]]);
+        g(&mut v, 1, 6, 11, 12, message[s[10]], message[s[11]]);
+        g(&mut v, 2, 7, 8, 13, message[s[12]], message[s[13]]);
+        g(&mut v, 3, 4, 9, 14, message[s[14]], message[s[15]]);
+    }
+    for i in 0..8 {
+        state[i] ^= v[i] ^ v[i + 8];
+    }
+}
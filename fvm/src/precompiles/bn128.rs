@@ -0,0 +1,117 @@
+//! The precompiles of EIP-196 and EIP-197: addition and scalar multiplication on the alt_bn128
+//! curve, and a check of its optimal ate pairing. The curve arithmetic is that of the bn crate.
+
+use bn::{pairing_batch, AffineG1, AffineG2, Fq, Fq2, Fr, Group, Gt, G1, G2};
+
+/// Reads an element of the base field from 32 big-endian bytes. Values of p and above are invalid.
+fn read_fq(bytes: &[u8]) -> Option<Fq> {
+    Fq::from_slice(&bytes[0..32]).ok()
+}
+
+/// Reads a point of G1 from 64 bytes. The point at infinity is encoded as zeros.
+fn read_g1(bytes: &[u8]) -> Option<G1> {
+    let x = read_fq(&bytes[0..32])?;
+    let y = read_fq(&bytes[32..64])?;
+    if x.is_zero() && y.is_zero() {
+        return Some(G1::zero());
+    }
+    AffineG1::new(x, y).ok().map(G1::from)
+}
+
+/// Reads a point of G2 from 128 bytes, with the imaginary part of each coordinate first. Points on
+/// the twist that are not in the subgroup of order r are rejected.
+fn read_g2(bytes: &[u8]) -> Option<G2> {
+    let x = Fq2::new(read_fq(&bytes[32..64])?, read_fq(&bytes[0..32])?);
+    let y = Fq2::new(read_fq(&bytes[96..128])?, read_fq(&bytes[64..96])?);
+    if x.is_zero() && y.is_zero() {
+        return Some(G2::zero());
+    }
+    AffineG2::new(x, y).ok().map(G2::from)
+}
+
+fn write_g1(point: G1) -> Vec<u8> {
+    let mut bytes = vec![0; 64];
+    if let Some(point) = AffineG1::from_jacobian(point) {
+        point
+            .x()
+            .to_big_endian(&mut bytes[0..32])
+            .expect("32 bytes hold an element of Fq");
+        point
+            .y()
+            .to_big_endian(&mut bytes[32..64])
+            .expect("32 bytes hold an element of Fq");
+    }
+    bytes
+}
+
+/// Returns `input` cut or padded with zeros to `len` bytes
+fn padded(input: &[u8], len: usize) -> Vec<u8> {
+    let mut data = input.to_vec();
+    data.resize(len, 0);
+    data
+}
+
+/// Adds the two points of G1 in `input`. Returns None if either is invalid.
+pub fn add(input: &[u8]) -> Option<Vec<u8>> {
+    let input = padded(input, 128);
+    let a = read_g1(&input[0..64])?;
+    let b = read_g1(&input[64..128])?;
+    Some(write_g1(a + b))
+}
+
+/// Multiplies the point of G1 in `input` by the scalar that follows it. Returns None if the point
+/// is invalid.
+pub fn mul(input: &[u8]) -> Option<Vec<u8>> {
+    let input = padded(input, 96);
+    let point = read_g1(&input[0..64])?;
+    // The scalar is taken modulo the order of the group, which leaves the product unchanged
+    let scalar = Fr::from_slice(&input[64..96]).expect("the scalar is 32 bytes long");
+    Some(write_g1(point * scalar))
+}
+
+/// Checks whether the product of the pairings of the (G1, G2) pairs in `input` is one, returning
+/// a word holding 1 if it is and 0 if not. Returns None if the input is not a whole number of pairs
+/// or holds an invalid point.
+pub fn pairing(input: &[u8]) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(192) {
+        return None;
+    }
+    let mut pairs = Vec::with_capacity(input.len() / 192);
+    for pair in input.chunks(192) {
+        let p = read_g1(&pair[0..64])?;
+        let q = read_g2(&pair[64..192])?;
+        // Pairs with the point at infinity contribute nothing to the product
+        if !p.is_zero() && !q.is_zero() {
+            pairs.push((p, q));
+        }
+    }
+    let is_one = pairs.is_empty() || pairing_batch(&pairs) == Gt::one();
+    let mut output = vec![0; 32];
+    output[31] = is_one as u8;
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::FromHex;
+
+    #[test]
+    fn coordinates_of_p_and_above_are_rejected() {
+        let p = "30644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd47";
+        let mut bytes: Vec<u8> = p.from_hex().unwrap();
+        assert!(read_fq(&bytes).is_none());
+        bytes[31] -= 1;
+        assert_eq!(read_fq(&bytes), Some(-Fq::one()));
+    }
+
+    #[test]
+    fn points_off_the_curve_are_rejected() {
+        let mut bytes = vec![0; 64];
+        bytes[31] = 1;
+        bytes[63] = 2;
+        assert!(read_g1(&bytes).is_some());
+        bytes[63] = 3;
+        assert!(read_g1(&bytes).is_none());
+    }
+}
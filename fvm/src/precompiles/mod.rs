@@ -0,0 +1,232 @@
+//! Module for the precompiled contracts: accounts at the addresses 1 to 9 whose code is built into
+//! the VM rather than stored as bytecode. A call to one of them runs the built-in function on the
+//! call data, and charges gas according to a formula of its own.
+
+mod blake2f;
+mod bn128;
+mod modexp;
+mod ripemd160;
+
+use bigint::Address;
+use errors::{Result, VMError};
+use fork::Fork;
+use gas_prices::to_words;
+use keccak_hash::keccak;
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, SECP256K1};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+
+/// Highest address that holds a precompiled contract on the latest fork
+pub const LAST_PRECOMPILE: u64 = 9;
+
+const ECRECOVER: u64 = 3000;
+const SHA256_BASE: u64 = 60;
+const SHA256_WORD: u64 = 12;
+const RIPEMD160_BASE: u64 = 600;
+const RIPEMD160_WORD: u64 = 120;
+const IDENTITY_BASE: u64 = 15;
+const IDENTITY_WORD: u64 = 3;
+
+/// Returns the number of the precompiled contract at `address`, if there is one under the rules of
+/// `fork`. Contracts 1 to 4 exist from Frontier, 5 to 8 from Byzantium and 9 from Istanbul.
+fn precompile_number(address: Address, fork: Fork) -> Option<u8> {
+    if address.iter().take(19).any(|&byte| byte != 0) {
+        return None;
+    }
+    let number = address[19];
+    let introduced_in = match number {
+        1..=4 => Fork::Frontier,
+        5..=8 => Fork::Byzantium,
+        9 => Fork::Istanbul,
+        _ => return None,
+    };
+    if fork >= introduced_in {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+/// Returns true if `address` holds a precompiled contract under the rules of `fork`
+pub fn is_precompile(address: Address, fork: Fork) -> bool {
+    precompile_number(address, fork).is_some()
+}
+
+/// Returns the gas it costs to run the precompiled contract `number` on `input`, or None if the
+/// input is malformed in a way that fails the call before it is priced
+fn gas_cost(number: u8, input: &[u8], fork: Fork) -> Option<u64> {
+    let words = to_words(input.len() as u64);
+    let istanbul = fork >= Fork::Istanbul;
+    let cost = match number {
+        1 => ECRECOVER,
+        2 => SHA256_BASE + SHA256_WORD * words,
+        3 => RIPEMD160_BASE + RIPEMD160_WORD * words,
+        4 => IDENTITY_BASE + IDENTITY_WORD * words,
+        5 => modexp::gas_cost(input, fork),
+        // EIP-1108 made the curve operations cheaper in Istanbul
+        6 if istanbul => 150,
+        6 => 500,
+        7 if istanbul => 6000,
+        7 => 40_000,
+        8 => {
+            let pairs = input.len() as u64 / 192;
+            if istanbul {
+                45_000 + 34_000 * pairs
+            } else {
+                100_000 + 80_000 * pairs
+            }
+        }
+        9 => u64::from(blake2f::rounds(input)?),
+        _ => unreachable!("not a precompiled contract"),
+    };
+    Some(cost)
+}
+
+/// Runs the precompiled contract at `address` on `input` with `gas` available, and returns the gas
+/// it used along with its output. Running out of gas and invalid input both fail the call and
+/// consume all of the gas given to it, the same as an exceptional halt of bytecode would.
+pub fn execute(address: Address, input: &[u8], gas: u64, fork: Fork) -> Result<(u64, Vec<u8>)> {
+    let number = precompile_number(address, fork).expect("only precompiled contracts are executed");
+    let cost = gas_cost(number, input, fork).ok_or(VMError::PrecompileFailure)?;
+    if cost > gas {
+        return Err(VMError::OutOfGas);
+    }
+    let output = match number {
+        1 => ecrecover(input),
+        2 => Sha256::digest(input).to_vec(),
+        3 => {
+            let mut output = vec![0; 12];
+            output.extend_from_slice(&ripemd160::hash(input));
+            output
+        }
+        4 => input.to_vec(),
+        5 => modexp::run(input),
+        6 => bn128::add(input).ok_or(VMError::PrecompileFailure)?,
+        7 => bn128::mul(input).ok_or(VMError::PrecompileFailure)?,
+        8 => bn128::pairing(input).ok_or(VMError::PrecompileFailure)?,
+        9 => blake2f::run(input).ok_or(VMError::PrecompileFailure)?,
+        _ => unreachable!("not a precompiled contract"),
+    };
+    Ok((cost, output))
+}
+
+/// Recovers the address that signed a message hash, from the hash, v, r and s in `input`. Invalid
+/// signatures do not fail the call, but return no output.
+fn ecrecover(input: &[u8]) -> Vec<u8> {
+    let mut data = input.to_vec();
+    data.resize(128, 0);
+    // v is a whole word, and only 27 and 28 are valid
+    if data[32..63].iter().any(|&byte| byte != 0) || (data[63] != 27 && data[63] != 28) {
+        return vec![];
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&data[0..32]);
+    let recovered = RecoveryId::try_from(i32::from(data[63] - 27))
+        .and_then(|id| RecoverableSignature::from_compact(&data[64..128], id))
+        .and_then(|signature| SECP256K1.recover_ecdsa(&Message::from_digest(hash), &signature));
+    match recovered {
+        Ok(key) => {
+            // The address is the last 20 bytes of the hash of the uncompressed key, without the
+            // leading 0x04
+            let key = key.serialize_uncompressed();
+            let mut output = vec![0; 12];
+            output.extend_from_slice(&keccak(&key[1..]).0[12..]);
+            output
+        }
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::{FromHex, ToHex};
+
+    fn run(number: u64, input: &str, fork: Fork) -> Result<(u64, Vec<u8>)> {
+        execute(
+            Address::from(number),
+            &input.from_hex::<Vec<u8>>().unwrap(),
+            10_000_000,
+            fork,
+        )
+    }
+
+    /// Runs the precompile and returns its output as hex
+    fn output(number: u64, input: &str) -> String {
+        let (_, output) = run(number, input, Fork::latest()).unwrap();
+        output.to_hex()
+    }
+
+    /// Runs the precompile and returns the gas it used
+    fn gas(number: u64, input: &str, fork: Fork) -> u64 {
+        run(number, input, fork).unwrap().0
+    }
+
+    #[test]
+    fn availability_depends_on_fork() {
+        assert!(is_precompile(Address::from(1), Fork::Frontier));
+        assert!(!is_precompile(Address::from(5), Fork::SpuriousDragon));
+        assert!(is_precompile(Address::from(8), Fork::Byzantium));
+        assert!(!is_precompile(Address::from(9), Fork::Constantinople));
+        assert!(is_precompile(Address::from(9), Fork::Istanbul));
+        assert!(!is_precompile(Address::from(0), Fork::latest()));
+        assert!(!is_precompile(Address::from(10), Fork::latest()));
+        assert!(!is_precompile(Address::from(0x0100), Fork::latest()));
+    }
+
+    #[test]
+    fn prices_depend_on_fork() {
+        assert_eq!(gas(1, "", Fork::Frontier), 3000);
+        assert_eq!(gas(2, "", Fork::Frontier), 60);
+        assert_eq!(gas(3, &"00".repeat(33), Fork::Frontier), 840);
+        assert_eq!(gas(4, &"00".repeat(32), Fork::Frontier), 18);
+        // The curve operations on points at infinity, which EIP-1108 made cheaper in Istanbul
+        assert_eq!(gas(6, "", Fork::Byzantium), 500);
+        assert_eq!(gas(6, "", Fork::Istanbul), 150);
+        assert_eq!(gas(7, "", Fork::Byzantium), 40_000);
+        assert_eq!(gas(7, "", Fork::Istanbul), 6000);
+        let pairs = "00".repeat(2 * 192);
+        assert_eq!(gas(8, &pairs, Fork::Byzantium), 260_000);
+        assert_eq!(gas(8, &pairs, Fork::Istanbul), 113_000);
+        // Blake2 F costs a gas per round
+        let blake2f = format!("0000000c{}01", "00".repeat(208));
+        assert_eq!(gas(9, &blake2f, Fork::Istanbul), 12);
+    }
+
+    #[test]
+    fn ecrecover() {
+        let input = concat!(
+            "38d18acb67d25c8bb9942764b62f18e17054f66a817bd4295423adf9ed98873e",
+            "000000000000000000000000000000000000000000000000000000000000001b",
+            "38d18acb67d25c8bb9942764b62f18e17054f66a817bd4295423adf9ed98873e",
+            "789d1dd423d25f0772d2748d60f7e4b81bb14d086eba8e8e8efb6dcff8a4ae02"
+        );
+        assert_eq!(
+            output(1, input),
+            "000000000000000000000000ceaccac640adf55b2028469bd36ba501f28b699d"
+        );
+        // An invalid v succeeds with no output
+        let input = input.replacen("1b", "1d", 1);
+        assert_eq!(output(1, &input), "");
+    }
+
+    #[test]
+    fn hashes_and_identity() {
+        assert_eq!(
+            output(2, ""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            output(3, "616263"),
+            "0000000000000000000000008eb208f7e05d987a9b044a8e98c6b087f15a0bfc"
+        );
+        assert_eq!(output(4, "0102030405"), "0102030405");
+    }
+
+    #[test]
+    fn out_of_gas() {
+        let result = execute(Address::from(2), &[], 59, Fork::latest());
+        assert_eq!(result, Err(VMError::OutOfGas));
+    }
+}
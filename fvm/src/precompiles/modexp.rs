@@ -0,0 +1,172 @@
+//! The modular exponentiation precompile of EIP-198. The arithmetic is that of the
+//! aurora-engine-modexp crate, which revm uses as well.
+
+use aurora_engine_modexp::modexp;
+use fork::Fork;
+use std::cmp;
+
+/// Reads `len` bytes of `input` starting at `offset`, padding with zeros past its end
+fn read_padded(input: &[u8], offset: u64, len: u64) -> Vec<u8> {
+    let mut data = vec![0u8; len as usize];
+    if offset < input.len() as u64 {
+        let start = offset as usize;
+        let end = cmp::min(input.len() as u64, offset.saturating_add(len)) as usize;
+        data[..end - start].copy_from_slice(&input[start..end]);
+    }
+    data
+}
+
+/// Reads one of the three 32 byte lengths at the start of the input. Lengths that do not fit in 64
+/// bits are returned as u64::MAX, which is more than could ever be paid for.
+fn read_length(input: &[u8], offset: u64) -> u64 {
+    let word = read_padded(input, offset, 32);
+    if word[..24].iter().any(|&byte| byte != 0) {
+        return u64::MAX;
+    }
+    word[24..].iter().fold(0, |len, &byte| (len << 8) | u64::from(byte))
+}
+
+/// Returns the lengths of the base, exponent and modulus given at the start of `input`
+fn lengths(input: &[u8]) -> (u64, u64, u64) {
+    (read_length(input, 0), read_length(input, 32), read_length(input, 64))
+}
+
+/// Returns the adjusted exponent length of EIP-198: roughly the number of bits in the exponent,
+/// counting only the highest of its first 32 bytes
+fn adjusted_exponent_length(input: &[u8], base_len: u64, exp_len: u64) -> u128 {
+    let head_len = cmp::min(exp_len, 32);
+    let head = read_padded(input, 96u64.saturating_add(base_len), head_len);
+    let head_bits = match head.iter().position(|&byte| byte != 0) {
+        Some(first) => 8 * (head.len() - first) as u128 - u128::from(head[first].leading_zeros()) - 1,
+        None => 0,
+    };
+    if exp_len <= 32 {
+        head_bits
+    } else {
+        8 * (u128::from(exp_len) - 32) + head_bits
+    }
+}
+
+/// Returns the gas cost of running modexp on `input`
+pub fn gas_cost(input: &[u8], fork: Fork) -> u64 {
+    let (base_len, exp_len, mod_len) = lengths(input);
+    let max_len = u128::from(cmp::max(base_len, mod_len));
+    let iterations = cmp::max(adjusted_exponent_length(input, base_len, exp_len), 1);
+    let gas = if fork >= Fork::Berlin {
+        // EIP-2565
+        let words = max_len.div_ceil(8);
+        cmp::max(200, words.saturating_mul(words).saturating_mul(iterations) / 3)
+    } else {
+        let complexity = if max_len <= 64 {
+            max_len * max_len
+        } else if max_len <= 1024 {
+            max_len * max_len / 4 + 96 * max_len - 3072
+        } else {
+            max_len.saturating_mul(max_len) / 16 + 480 * max_len - 199_680
+        };
+        complexity.saturating_mul(iterations) / 20
+    };
+    cmp::min(gas, u128::from(u64::MAX)) as u64
+}
+
+/// Returns the base raised to the exponent modulo the modulus, all read from `input`, as many bytes
+/// as the modulus is long. The caller must already have charged for the lengths given in the input.
+pub fn run(input: &[u8]) -> Vec<u8> {
+    let (base_len, exp_len, mod_len) = lengths(input);
+    if mod_len == 0 {
+        return vec![];
+    }
+    let base = read_padded(input, 96, base_len);
+    let exponent = read_padded(input, 96 + base_len, exp_len);
+    let modulus = read_padded(input, 96 + base_len + exp_len, mod_len);
+    // The result is less than the modulus, so it fits once padded. Anything modulo zero is zero,
+    // for which the crate returns no bytes at all.
+    let result = modexp(&base, &exponent, &modulus);
+    let mut output = vec![0; mod_len as usize];
+    let start = output.len() - result.len();
+    output[start..].copy_from_slice(&result);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::FromHex;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        hex.from_hex().unwrap()
+    }
+
+    #[test]
+    fn eip198_examples() {
+        // Fermat's little theorem: 3^(p-1) = 1 mod p for the secp256k1 field prime p
+        let input = from_hex(concat!(
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "03",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2e",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"
+        ));
+        assert_eq!(run(&input), from_hex(&format!("{:064x}", 1)));
+        assert_eq!(gas_cost(&input, Fork::Byzantium), 13056);
+
+        // A missing base is zero
+        let input = from_hex(concat!(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2e",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"
+        ));
+        assert_eq!(run(&input), vec![0; 32]);
+    }
+
+    #[test]
+    fn zero_modulus_gives_zeros() {
+        let input = from_hex(concat!(
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "03",
+            "05",
+            "0000"
+        ));
+        assert_eq!(run(&input), vec![0, 0]);
+    }
+
+    #[test]
+    fn lengths_beyond_64_bits_cannot_be_paid_for() {
+        let input = from_hex(concat!(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000010000000000000000"
+        ));
+        assert_eq!(gas_cost(&input, Fork::Byzantium), u64::MAX);
+        assert_eq!(gas_cost(&input, Fork::Berlin), u64::MAX);
+    }
+
+    #[test]
+    fn eip2565_reprices() {
+        // nagydani-1-square from the EIP-2565 test vectors
+        let input = from_hex(concat!(
+            "0000000000000000000000000000000000000000000000000000000000000040",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000040",
+            "e09ad9675465c53a109fac66a445c91b292d2bb2c5268addb30cd82f80fcb003",
+            "3ff97c80a5fc6f39193ae969c6ede6710a6b7ac27078a06d90ef1c72e5c85fb5",
+            "02",
+            "fc9e1f6beb81516545975218075ec2af118cd8798df6e08a147c60fd6095ac2b",
+            "b02c2908cf4dd7c81f11c289e4bce98f3553768f392a80ce22bf5c4f4a248c6b"
+        ));
+        assert_eq!(gas_cost(&input, Fork::Byzantium), 204);
+        assert_eq!(gas_cost(&input, Fork::Berlin), 200);
+        assert_eq!(
+            run(&input),
+            from_hex(concat!(
+                "60008f1614cc01dcfb6bfb09c625cf90b47d4468db81b5f8b7a39d42f332eab9",
+                "b2da8f2d95311648a8f243f4bb13cfb3d8f7f2a3c014122ebb3ed41b02783adc"
+            ))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Module for the stack the VM operates on
+
+use bigint::M256;
+use errors::{Result, VMError};
+
+/// The EVM stack can hold at most this many items
+pub const STACK_LIMIT: usize = 1024;
+
+/// A bounds-checked stack of 256-bit words. Positions passed to `peek`, `dup` and `swap` count
+/// from the top of the stack, as in the yellow paper.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stack {
+    items: Vec<M256>,
+}
+
+impl Stack {
+    /// Creates and returns a new, empty Stack
+    pub fn new() -> Stack {
+        Stack {
+            items: Vec::with_capacity(STACK_LIMIT),
+        }
+    }
+
+    /// Pushes a value onto the top of the stack
+    pub fn push(&mut self, value: M256) -> Result<()> {
+        if self.items.len() >= STACK_LIMIT {
+            return Err(VMError::StackOverflow);
+        }
+        self.items.push(value);
+        Ok(())
+    }
+
+    /// Removes and returns the value on the top of the stack
+    pub fn pop(&mut self) -> Result<M256> {
+        self.items.pop().ok_or(VMError::StackUnderflow)
+    }
+
+    /// Returns the value `depth` items below the top of the stack without removing it. A depth of
+    /// 0 is the top of the stack.
+    pub fn peek(&self, depth: usize) -> Result<M256> {
+        if depth >= self.items.len() {
+            return Err(VMError::StackUnderflow);
+        }
+        Ok(self.items[self.items.len() - 1 - depth])
+    }
+
+    /// Pushes a copy of the `n`th item, counting from 1 at the top of the stack. This is DUPn.
+    pub fn dup(&mut self, n: usize) -> Result<()> {
+        let value = self.peek(n - 1)?;
+        self.push(value)
+    }
+
+    /// Exchanges the top of the stack with the item `n` below it. This is SWAPn.
+    pub fn swap(&mut self, n: usize) -> Result<()> {
+        if n >= self.items.len() {
+            return Err(VMError::StackUnderflow);
+        }
+        let top = self.items.len() - 1;
+        self.items.swap(top, top - n);
+        Ok(())
+    }
+
+    /// Returns the number of items on the stack
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns true if the stack is empty, false otherwise
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns all of the items on the stack, from the bottom to the top
+    pub fn items(&self) -> &[M256] {
+        &self.items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_stack(values: &[u64]) -> Stack {
+        let mut stack = Stack::new();
+        for value in values {
+            stack.push(M256::from(*value)).unwrap();
+        }
+        stack
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut stack = gen_stack(&[1, 2]);
+        assert_eq!(stack.pop(), Ok(M256::from(2)));
+        assert_eq!(stack.pop(), Ok(M256::from(1)));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn pop_empty_stack_underflows() {
+        let mut stack = Stack::new();
+        assert_eq!(stack.pop(), Err(VMError::StackUnderflow));
+    }
+
+    #[test]
+    fn push_full_stack_overflows() {
+        let mut stack = Stack::new();
+        for i in 0..STACK_LIMIT {
+            assert!(stack.push(M256::from(i)).is_ok());
+        }
+        assert_eq!(stack.push(M256::zero()), Err(VMError::StackOverflow));
+        assert_eq!(stack.len(), STACK_LIMIT);
+    }
+
+    #[test]
+    fn peek_counts_from_top() {
+        let stack = gen_stack(&[1, 2, 3]);
+        assert_eq!(stack.peek(0), Ok(M256::from(3)));
+        assert_eq!(stack.peek(2), Ok(M256::from(1)));
+        assert_eq!(stack.peek(3), Err(VMError::StackUnderflow));
+    }
+
+    #[test]
+    fn dup_copies_from_top() {
+        let mut stack = gen_stack(&[1, 2, 3]);
+        assert!(stack.dup(1).is_ok());
+        assert_eq!(stack.items(), &[1.into(), 2.into(), 3.into(), 3.into()]);
+        assert!(stack.dup(4).is_ok());
+        assert_eq!(stack.peek(0), Ok(M256::from(1)));
+        assert_eq!(stack.dup(6), Err(VMError::StackUnderflow));
+    }
+
+    #[test]
+    fn swap_exchanges_with_top() {
+        let mut stack = gen_stack(&[1, 2, 3]);
+        assert!(stack.swap(2).is_ok());
+        assert_eq!(stack.items(), &[3.into(), 2.into(), 1.into()]);
+        assert_eq!(stack.swap(3), Err(VMError::StackUnderflow));
+    }
+}
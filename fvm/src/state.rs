@@ -0,0 +1,269 @@
+//! Module for an in-memory Host, mainly for testing purposes
+
+use bigint::{Address, H256, M256, U256};
+use errors::StorageError;
+use eth_log::Log;
+use host::Host;
+use keccak_hash::{keccak, KECCAK_EMPTY};
+use std::collections::HashMap;
+use storage::Storage;
+
+/// An account in the world state
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub balance: U256,
+    pub nonce: U256,
+    code: Vec<u8>,
+    code_hash: H256,
+    storage: Storage,
+}
+
+impl Account {
+    /// Creates and returns a new, empty Account living at `address`
+    pub fn new(address: Address) -> Account {
+        Account {
+            balance: U256::zero(),
+            nonce: U256::zero(),
+            code: vec![],
+            code_hash: H256::from(&*KECCAK_EMPTY),
+            storage: Storage::new(address),
+        }
+    }
+
+    /// Returns the code of the account
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Returns the hash of the code of the account, which is kept alongside the code so that it is
+    /// not hashed on every call
+    pub fn code_hash(&self) -> H256 {
+        self.code_hash
+    }
+
+    fn set_code(&mut self, code: Vec<u8>) {
+        self.code_hash = H256::from(&*keccak(&code));
+        self.code = code;
+    }
+
+    /// Returns every non-zero storage slot of the account along with its value, in slot order
+    pub fn storage(&self) -> Vec<(U256, M256)> {
+        self.storage.slots()
+    }
+}
+
+/// A Host that keeps accounts, block hashes and logs in memory. Reading an account that does not
+/// exist gives zero values, as if it were empty.
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    accounts: HashMap<Address, Account>,
+    block_hashes: HashMap<U256, H256>,
+    logs: Vec<Log>,
+}
+
+impl State {
+    /// Creates and returns a new, empty State
+    pub fn new() -> State {
+        State {
+            accounts: HashMap::new(),
+            block_hashes: HashMap::new(),
+            logs: vec![],
+        }
+    }
+
+    /// Adds an account with the given balance and code, replacing any account already at `address`
+    pub fn with_account(mut self, address: Address, balance: U256, code: Vec<u8>) -> State {
+        let mut account = Account::new(address);
+        account.balance = balance;
+        account.set_code(code);
+        self.accounts.insert(address, account);
+        self
+    }
+
+    /// Sets the storage slot `index` of the account at `address` to `value`, as it is at the start
+    /// of a transaction
+    pub fn with_storage(mut self, address: Address, index: U256, value: M256) -> State {
+        let storage = &mut self.account_mut(address).storage;
+        storage.write(index, value);
+        storage.commit();
+        self
+    }
+
+    /// Records `hash` as the hash of the block at height `number`
+    pub fn with_block_hash(mut self, number: U256, hash: H256) -> State {
+        self.block_hashes.insert(number, hash);
+        self
+    }
+
+    /// Returns the account at `address`, if it exists
+    pub fn account(&self, address: Address) -> Option<&Account> {
+        self.accounts.get(&address)
+    }
+
+    /// Returns the addresses of every account, in order
+    pub fn addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.accounts.keys().cloned().collect();
+        addresses.sort();
+        addresses
+    }
+
+    /// Returns the logs of every successful execution against this state, in order
+    pub fn logs(&self) -> &[Log] {
+        &self.logs
+    }
+
+    fn account_mut(&mut self, address: Address) -> &mut Account {
+        self.accounts.entry(address).or_insert_with(|| Account::new(address))
+    }
+}
+
+impl Host for State {
+    fn exists(&self, address: Address) -> bool {
+        self.accounts.contains_key(&address)
+    }
+
+    fn balance(&self, address: Address) -> U256 {
+        self.account(address).map(|a| a.balance).unwrap_or_default()
+    }
+
+    fn nonce(&self, address: Address) -> U256 {
+        self.account(address).map(|a| a.nonce).unwrap_or_default()
+    }
+
+    fn code(&self, address: Address) -> Vec<u8> {
+        self.account(address).map(|a| a.code.clone()).unwrap_or_default()
+    }
+
+    fn code_hash(&self, address: Address) -> H256 {
+        self.account(address).map(|a| a.code_hash).unwrap_or_default()
+    }
+
+    fn storage(&self, address: Address, index: U256) -> M256 {
+        match self.account(address) {
+            Some(account) => account.storage.read(index).unwrap_or_else(|_| M256::zero()),
+            None => M256::zero(),
+        }
+    }
+
+    fn original_storage(&self, address: Address, index: U256) -> M256 {
+        match self.account(address) {
+            Some(account) => account.storage.original(index),
+            None => M256::zero(),
+        }
+    }
+
+    fn set_storage(&mut self, address: Address, index: U256, value: M256) -> Result<(), StorageError> {
+        self.account_mut(address).storage.write(index, value);
+        Ok(())
+    }
+
+    fn commit_storage(&mut self) {
+        for account in self.accounts.values_mut() {
+            account.storage.commit();
+        }
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.account_mut(address).balance = balance;
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: U256) {
+        self.account_mut(address).nonce = nonce;
+    }
+
+    fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.account_mut(address).set_code(code);
+    }
+
+    fn remove_account(&mut self, address: Address) {
+        self.accounts.remove(&address);
+    }
+
+    fn block_hash(&self, number: U256) -> H256 {
+        self.block_hashes.get(&number).cloned().unwrap_or_default()
+    }
+
+    fn log(&mut self, log: Log) {
+        self.logs.push(log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_hash_of_missing_account_is_zero() {
+        let address = Address::random();
+        let state = State::new().with_account(address, U256::zero(), vec![]);
+        assert_eq!(state.code_hash(Address::random()), H256::zero());
+        // Hash of empty code
+        assert_eq!(
+            format!("{:x}", state.code_hash(address)),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+    }
+
+    #[test]
+    fn code_hash_follows_code() {
+        let address = Address::random();
+        let mut state = State::new().with_account(address, U256::zero(), vec![0x00]);
+        state.set_code(address, vec![0x60, 0x00]);
+        assert_eq!(state.code_hash(address), H256::from(&*keccak([0x60, 0x00])));
+        assert_eq!(state.account(address).unwrap().code(), &[0x60, 0x00]);
+    }
+
+    #[test]
+    fn missing_account_reads_as_empty() {
+        let state = State::new();
+        let address = Address::random();
+        assert!(!state.exists(address));
+        assert_eq!(state.balance(address), U256::zero());
+        assert!(state.code(address).is_empty());
+        assert_eq!(state.storage(address, 0.into()), M256::zero());
+    }
+
+    #[test]
+    fn original_storage_survives_until_commit() {
+        let address = Address::random();
+        let mut state = State::new().with_storage(address, 1.into(), 5.into());
+        assert!(state.set_storage(address, 1.into(), 6.into()).is_ok());
+        assert!(state.set_storage(address, 1.into(), 7.into()).is_ok());
+        assert_eq!(state.storage(address, 1.into()), M256::from(7));
+        assert_eq!(state.original_storage(address, 1.into()), M256::from(5));
+        state.commit_storage();
+        assert_eq!(state.original_storage(address, 1.into()), M256::from(7));
+    }
+
+    #[test]
+    fn lists_accounts_and_their_storage() {
+        let (first, second) = (Address::from(1), Address::from(2));
+        let state = State::new()
+            .with_storage(second, 4.into(), 8.into())
+            .with_account(first, U256::zero(), vec![]);
+        assert_eq!(state.addresses(), vec![first, second]);
+        assert_eq!(state.account(second).unwrap().storage(), vec![(4.into(), 8.into())]);
+        assert!(state.account(first).unwrap().storage().is_empty());
+    }
+
+    #[test]
+    fn transfer_moves_balance() {
+        let from = Address::random();
+        let to = Address::random();
+        let mut state = State::new().with_account(from, 100.into(), vec![]);
+        assert!(state.transfer(from, to, 40.into()));
+        assert_eq!(state.balance(from), U256::from(60));
+        assert_eq!(state.balance(to), U256::from(40));
+        assert!(state.exists(to));
+    }
+
+    #[test]
+    fn transfer_fails_without_funds() {
+        let from = Address::random();
+        let to = Address::random();
+        let mut state = State::new().with_account(from, 10.into(), vec![]);
+        assert!(!state.transfer(from, to, 11.into()));
+        assert_eq!(state.balance(from), U256::from(10));
+        assert!(!state.exists(to));
+    }
+}
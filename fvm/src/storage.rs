@@ -8,10 +8,12 @@ use std::collections::HashMap;
 type Map<U256, M256> = HashMap<U256, M256>;
 
 #[derive(Debug, Clone)]
-/// Represents durable storage for an Account
+/// Represents durable storage for an Account. Slots that hold zero are not stored at all.
 pub struct Storage {
     address: Address,
     storage: Map<U256, M256>,
+    /// Values that slots written since the last commit held before their first write
+    original: Map<U256, M256>,
 }
 
 impl Into<Map<U256, M256>> for Storage {
@@ -26,17 +28,14 @@ impl Storage {
         Storage {
             address,
             storage: Map::new(),
+            original: Map::new(),
         }
     }
 
-    /// Commit a value into the storage.
-    fn commit(&mut self, index: U256, value: M256) -> Result<(), StorageError> {
-        if self.storage.contains_key(&index) {
-            return Err(StorageError::AlreadyCommitted);
-        }
-
-        self.storage.insert(index, value);
-        Ok(())
+    /// Commit all writes made since the last commit, so that the current values become the
+    /// original values. This is done at the end of every transaction.
+    pub fn commit(&mut self) {
+        self.original.clear();
     }
 
     /// Read a value from the storage.
@@ -47,16 +46,36 @@ impl Storage {
         }
     }
 
-    /// Write a value into the storage.
-    pub fn write(&mut self, index: U256, value: M256) -> Result<(), StorageError> {
-        if self.storage.contains_key(&index) {
-            return Err(StorageError::RequireError);
+    /// Read the value a slot held at the last commit, before any of the writes since.
+    pub fn original(&self, index: U256) -> M256 {
+        match self.original.get(&index) {
+            Some(&v) => v,
+            None => self.read(index).unwrap_or_else(|_| M256::zero()),
         }
-        self.storage.insert(index, value);
-        Ok(())
     }
 
-    /// Return the number of changed/full items in storage.
+    /// Write a value into the storage, replacing the value already there. Writing zero deletes
+    /// the slot.
+    pub fn write(&mut self, index: U256, value: M256) {
+        if !self.original.contains_key(&index) {
+            let current = self.read(index).unwrap_or_else(|_| M256::zero());
+            self.original.insert(index, current);
+        }
+        if value == M256::zero() {
+            self.storage.remove(&index);
+        } else {
+            self.storage.insert(index, value);
+        }
+    }
+
+    /// Returns every non-zero slot along with its value, in slot order
+    pub fn slots(&self) -> Vec<(U256, M256)> {
+        let mut slots: Vec<(U256, M256)> = self.storage.iter().map(|(&index, &value)| (index, value)).collect();
+        slots.sort_by_key(|&(index, _)| index);
+        slots
+    }
+
+    /// Return the number of non-zero items in storage.
     pub fn len(&self) -> usize {
         self.storage.len()
     }
@@ -66,3 +85,48 @@ impl Storage {
         self.len() == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_overwrites() {
+        let mut storage = Storage::new(Address::default());
+        storage.write(1.into(), 5.into());
+        storage.write(1.into(), 6.into());
+        assert_eq!(storage.read(1.into()).unwrap(), M256::from(6));
+    }
+
+    #[test]
+    fn writing_zero_deletes() {
+        let mut storage = Storage::new(Address::default());
+        storage.write(1.into(), 5.into());
+        assert_eq!(storage.len(), 1);
+        storage.write(1.into(), M256::zero());
+        assert!(storage.is_empty());
+        assert_eq!(storage.read(1.into()).unwrap(), M256::zero());
+    }
+
+    #[test]
+    fn original_is_kept_until_commit() {
+        let mut storage = Storage::new(Address::default());
+        storage.write(1.into(), 5.into());
+        storage.commit();
+        storage.write(1.into(), 6.into());
+        storage.write(1.into(), 7.into());
+        assert_eq!(storage.original(1.into()), M256::from(5));
+        assert_eq!(storage.original(2.into()), M256::zero());
+        storage.commit();
+        assert_eq!(storage.original(1.into()), M256::from(7));
+    }
+
+    #[test]
+    fn slots_are_listed_in_order() {
+        let mut storage = Storage::new(Address::default());
+        storage.write(9.into(), 1.into());
+        storage.write(2.into(), 3.into());
+        storage.write(5.into(), 0.into());
+        assert_eq!(storage.slots(), vec![(2.into(), 3.into()), (9.into(), 1.into())]);
+    }
+}
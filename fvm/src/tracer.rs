@@ -0,0 +1,190 @@
+//! Module for tracing execution one instruction at a time. A Tracer handed to
+//! `VM::execute_with_tracer` is told about every instruction the VM runs, including those of
+//! nested calls.
+
+use bigint::{Address, H256, M256, U256};
+use errors::VMError;
+use opcodes;
+use outcome::ExecutionResult;
+use rustc_hex::ToHex;
+use serde_json;
+use std::collections::{BTreeMap, HashMap};
+
+/// A single executed instruction, along with the state of the VM from just before it ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub pc: usize,
+    /// The byte at `pc`, or STOP when execution ran off the end of the code
+    pub opcode: u8,
+    /// Gas remaining before the instruction
+    pub gas: u64,
+    /// Gas the instruction consumed, including memory expansion and any gas passed on to a call
+    pub gas_cost: u64,
+    /// Number of calls in progress, counting the one the VM was created with as 1
+    pub depth: usize,
+    /// Account whose code is running
+    pub address: Address,
+    /// The stack, with the top item last
+    pub stack: Vec<M256>,
+    /// The active memory
+    pub memory: Vec<u8>,
+    /// For SLOAD and SSTORE, the slot accessed and the value it holds after the instruction
+    pub storage: Option<(U256, M256)>,
+    /// The error the instruction halted with, if any
+    pub error: Option<VMError>,
+}
+
+/// Something that wants to be told about every instruction the VM executes
+pub trait Tracer {
+    /// Called once an instruction has executed
+    fn step(&mut self, step: &Step);
+}
+
+/// A single entry of geth's structLog trace
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StructLog {
+    pub pc: usize,
+    pub op: String,
+    pub gas: u64,
+    #[serde(rename = "gasCost")]
+    pub gas_cost: u64,
+    pub depth: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Stack items as 32 byte hex words, bottom first
+    pub stack: Vec<String>,
+    /// Memory as 32 byte hex words
+    pub memory: Vec<String>,
+    /// Every storage slot of the running contract read or written so far in the trace
+    pub storage: BTreeMap<String, String>,
+}
+
+/// The whole trace of a transaction, as returned by geth's debug_traceTransaction
+#[derive(Serialize)]
+struct Trace<'a> {
+    gas: u64,
+    failed: bool,
+    #[serde(rename = "returnValue")]
+    return_value: String,
+    #[serde(rename = "structLogs")]
+    struct_logs: &'a [StructLog],
+}
+
+/// A Tracer that records geth's structLog for every step, so traces can be compared with those of
+/// other clients
+#[derive(Debug, Default)]
+pub struct StructLogger {
+    logs: Vec<StructLog>,
+    /// Storage seen so far, per contract
+    storage: HashMap<Address, BTreeMap<String, String>>,
+}
+
+impl StructLogger {
+    /// Creates and returns a new StructLogger with no steps recorded
+    pub fn new() -> StructLogger {
+        StructLogger::default()
+    }
+
+    /// Returns the steps recorded so far
+    pub fn logs(&self) -> &[StructLog] {
+        &self.logs
+    }
+
+    /// Returns the trace as the JSON geth's debug_traceTransaction returns, given the result of
+    /// the execution that was traced
+    pub fn to_json(&self, result: &ExecutionResult) -> String {
+        let trace = Trace {
+            gas: result.gas_used,
+            failed: !result.is_success(),
+            return_value: result.output().to_hex(),
+            struct_logs: &self.logs,
+        };
+        serde_json::to_string(&trace).expect("a trace only holds strings and numbers")
+    }
+}
+
+impl Tracer for StructLogger {
+    fn step(&mut self, step: &Step) {
+        let storage = self.storage.entry(step.address).or_default();
+        if let Some((index, value)) = step.storage {
+            storage.insert(H256::from(index).to_hex(), H256::from(value).to_hex());
+        }
+        self.logs.push(StructLog {
+            pc: step.pc,
+            op: opcodes::mnemonic(step.opcode),
+            gas: step.gas,
+            gas_cost: step.gas_cost,
+            depth: step.depth,
+            error: step.error.as_ref().map(|e| e.to_string()),
+            stack: step
+                .stack
+                .iter()
+                .map(|&item| H256::from(item).to_hex::<String>())
+                .collect(),
+            memory: step.memory.chunks(32).map(|word| word.to_hex::<String>()).collect(),
+            storage: storage.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use errors::ExecutionError;
+
+    fn step(opcode: u8, storage: Option<(U256, M256)>) -> Step {
+        Step {
+            pc: 7,
+            opcode,
+            gas: 100,
+            gas_cost: 3,
+            depth: 1,
+            address: Address::default(),
+            stack: vec![M256::from(1)],
+            memory: vec![0; 64],
+            storage,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn records_struct_logs() {
+        let mut logger = StructLogger::new();
+        logger.step(&step(0x60, None));
+        logger.step(&step(0x55, Some((U256::from(1), M256::from(2)))));
+        logger.step(&step(0x0c, None));
+        let logs = logger.logs();
+        assert_eq!(logs[0].op, "PUSH1");
+        assert_eq!(logs[0].stack, vec![format!("{:064x}", 1)]);
+        assert_eq!(logs[0].memory.len(), 2);
+        assert!(logs[0].storage.is_empty());
+        assert_eq!(logs[1].op, "SSTORE");
+        // Storage seen once stays in the trace of the contract
+        assert_eq!(
+            logs[2].storage.get(&format!("{:064x}", 1)),
+            Some(&format!("{:064x}", 2))
+        );
+        assert_eq!(logs[2].op, "opcode 0x0c not defined");
+    }
+
+    #[test]
+    fn serializes_like_geth() {
+        let mut logger = StructLogger::new();
+        let mut failed = step(0x56, None);
+        failed.error = Some(VMError::InvalidJump);
+        logger.step(&failed);
+        let result = ExecutionResult {
+            outcome: ::outcome::Outcome::Halt(ExecutionError {
+                error: VMError::InvalidJump,
+                pc: 7,
+                opcode: 0x56,
+            }),
+            gas_used: 100,
+            gas_refunded: 0,
+            logs: vec![],
+        };
+        let json = logger.to_json(&result);
+        assert!(json.starts_with(r#"{"gas":100,"failed":true,"returnValue":"","structLogs":[{"pc":7,"#));
+        assert!(json.contains(r#""op":"JUMP","gas":100,"gasCost":3,"depth":1,"error":"invalid jump destination""#));
+    }
+}
@@ -1,489 +1,1158 @@
 //! Module that contains the VM that executes bytecode
 
-use bigint::{Address, H256, M256, MI256, U256};
-use errors::{Result, VMError};
+use analysis::{Analysis, AnalysisCache};
+use bigint::{Address, H256, M256, MI256, U256, U512};
+use environment::Environment;
+use errors::{ExecutionError, Result, VMError};
 use eth_log::Log;
+use fork::{Fork, ForkSchedule};
+use frame::{Frame, FrameKind};
+use gas_prices;
+use host::Host;
+use journal::Journal;
 use keccak_hash::keccak;
-use memory::{Memory, SimpleMemory};
+use memory::{Memory, MemoryKind, DEFAULT_MEMORY_LIMIT};
 use opcodes::Opcode;
-use storage::Storage;
-
-/// Core VM struct that executes bytecode
-pub struct VM {
-    address: Option<Address>,
-    registers: [M256; 1024],
-    memory: Option<Box<Memory>>,
-    storage: Option<Storage>,
-    code: Vec<u8>,
-    pc: usize,
-    stack_pointer: usize,
-    logs: Vec<Log>,
+use outcome::{ExecutionResult, Outcome};
+use precompiles;
+use rlp::RlpStream;
+use stack::Stack;
+use state::State;
+use std::cmp;
+use std::rc::Rc;
+use tracer::{Step, Tracer};
+
+/// Gas limit a VM is given if none is set with `with_gas_limit`
+pub const DEFAULT_GAS_LIMIT: u64 = 10_000_000;
+
+/// Calls nested deeper than this fail without running
+pub const CALL_DEPTH_LIMIT: usize = 1024;
+
+/// Core VM struct that executes bytecode against a Host
+pub struct VM<H: Host = State> {
+    /// Frames of the calls in progress. The first one is the frame the VM was created with, and
+    /// the last one is the one that is currently executing.
+    frames: Vec<Frame>,
+    /// Every change to the host goes through the journal, so that the changes made by a frame can
+    /// be undone if it fails. Logs, the refund counter and the access list are kept there too.
+    host: Journal<H>,
+    environment: Environment,
+    /// Forks that activate at each block height. The fork the VM follows is that of the block it
+    /// executes in, unless one is set with `with_fork`.
+    schedule: ForkSchedule,
+    /// Fork set with `with_fork`, which overrides the schedule
+    fork_override: Option<Fork>,
+    /// Rules the VM follows
+    fork: Fork,
+    /// Implementation of memory each call is given
+    memory_kind: MemoryKind,
+    /// Largest size in bytes the memory of each call may be expanded to
+    memory_limit: usize,
+    /// Decoded code of the contracts called so far
+    analyses: AnalysisCache,
 }
 
 impl VM {
     /// Creates and returns a new VM
     pub fn new(code: Vec<u8>) -> VM {
-        VM {
-            address: None,
-            registers: [0.into(); 1024],
-            memory: None,
-            storage: None,
-            stack_pointer: 0,
-            code: code,
-            pc: 0,
-            logs: vec![],
-        }
+        let mut vm = VM {
+            frames: vec![Frame::new(FrameKind::Root, code, DEFAULT_GAS_LIMIT)],
+            host: Journal::new(State::new()),
+            environment: Environment::default(),
+            schedule: ForkSchedule::latest(),
+            fork_override: None,
+            fork: Fork::latest(),
+            memory_kind: MemoryKind::Simple,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            analyses: AnalysisCache::new(),
+        };
+        vm.warm_transaction_accounts();
+        vm
     }
+}
 
-    /// Sets the volatile memory of the VM to the SimpleMemory type
-    pub fn with_simple_memory(mut self) -> VM {
-        self.memory = Some(Box::new(SimpleMemory::new()));
+impl<H: Host> VM<H> {
+    /// Sets the host the VM reads and modifies world state through
+    pub fn with_host<T: Host>(self, host: T) -> VM<T> {
+        let mut vm = VM {
+            frames: self.frames,
+            host: Journal::new(host),
+            environment: self.environment,
+            schedule: self.schedule,
+            fork_override: self.fork_override,
+            fork: self.fork,
+            memory_kind: self.memory_kind,
+            memory_limit: self.memory_limit,
+            analyses: self.analyses,
+        };
+        vm.warm_transaction_accounts();
+        vm
+    }
+
+    /// Sets the fork whose rules the VM follows, whatever block it executes in
+    pub fn with_fork(mut self, fork: Fork) -> VM<H> {
+        self.fork_override = Some(fork);
+        self.fork = fork;
+        self
+    }
+
+    /// Sets the schedule the fork the VM follows is looked up in, by the number of the block it
+    /// executes in. Unless one is set, every fork is active from the genesis block, so the latest
+    /// fork is followed.
+    pub fn with_fork_schedule(mut self, schedule: ForkSchedule) -> VM<H> {
+        self.schedule = schedule;
+        self.resolve_fork();
         self
     }
 
-    /// Sets the storage of the VM. This is unique to the Address.
-    pub fn with_storage(mut self, address: Address) -> VM {
-        self.storage = Some(Storage::new(address));
+    /// Sets the volatile memory of the VM to the SimpleMemory type
+    pub fn with_simple_memory(self) -> VM<H> {
+        self.with_memory_kind(MemoryKind::Simple)
+    }
+
+    /// Sets the volatile memory of the VM to the FastMemory type, which is quicker for code that
+    /// copies a lot of data in and out of memory
+    pub fn with_fast_memory(self) -> VM<H> {
+        self.with_memory_kind(MemoryKind::Fast)
+    }
+
+    /// Sets the implementation of memory the VM and every call it makes are given
+    pub fn with_memory_kind(mut self, kind: MemoryKind) -> VM<H> {
+        self.memory_kind = kind;
+        self.root_mut().memory = kind.create(self.memory_limit);
         self
     }
 
+    /// Sets the largest size in bytes the memory of each call may be expanded to. Expanding past it
+    /// halts the call with `VMError::MemoryError`, whatever gas is left.
+    pub fn with_memory_limit(mut self, limit: usize) -> VM<H> {
+        self.memory_limit = limit;
+        let kind = self.memory_kind;
+        self.with_memory_kind(kind)
+    }
+
     /// Sets the address for this VM
-    pub fn with_address(mut self, address: Address) -> VM {
-        self.address = Some(address);
+    pub fn with_address(mut self, address: Address) -> VM<H> {
+        self.root_mut().address = address;
+        self.warm_transaction_accounts();
         self
     }
 
     /// Creates a VM with a random address, mainly for testing purposes
-    pub fn with_random_address(mut self) -> VM {
-        self.address = Some(Address::random());
+    pub fn with_random_address(self) -> VM<H> {
+        self.with_address(Address::random())
+    }
+
+    /// Sets the cache the VM keeps the analyses of code in. Giving the VMs of many transactions the
+    /// same cache means a contract that all of them call is analyzed only once.
+    pub fn with_analysis_cache(mut self, cache: AnalysisCache) -> VM<H> {
+        self.analyses = cache;
         self
     }
 
-    /// Starts the execution loop for the VM
-    pub fn execute(&mut self) -> Result<()> {
-        loop {
-            match self.execute_one() {
-                Ok(_) => {
-                    continue;
-                }
-                Err(e) => {
-                    return Err(e);
-                }
-            };
+    /// Sets the maximum amount of gas execution may consume
+    pub fn with_gas_limit(mut self, gas_limit: u64) -> VM<H> {
+        self.root_mut().gas_limit = gas_limit;
+        self
+    }
+
+    /// Sets the input data of the message the VM is executing
+    pub fn with_calldata(mut self, calldata: Vec<u8>) -> VM<H> {
+        self.root_mut().calldata = calldata;
+        self
+    }
+
+    /// Sets the transaction and block context the VM executes in. The caller and value of the
+    /// transaction become those of the message the VM is executing, and both the caller and the
+    /// origin start out warm.
+    pub fn with_environment(mut self, environment: Environment) -> VM<H> {
+        self.root_mut().caller = environment.transaction.caller;
+        self.root_mut().value = environment.transaction.value;
+        self.environment = environment;
+        self.resolve_fork();
+        self.warm_transaction_accounts();
+        self
+    }
+
+    fn resolve_fork(&mut self) {
+        self.fork = match self.fork_override {
+            Some(fork) => fork,
+            None => self.schedule.fork_at(self.environment.block.number),
+        };
+    }
+
+    /// Marks the accounts taking part in the transaction and the precompiled contracts as warm, as
+    /// they are from the start
+    fn warm_transaction_accounts(&mut self) {
+        let address = self.root().address;
+        self.host.access_address(address);
+        self.host.access_address(self.environment.transaction.origin);
+        self.host.access_address(self.environment.transaction.caller);
+        for number in 1..=precompiles::LAST_PRECOMPILE {
+            self.host.access_address(Address::from(number));
+        }
+    }
+
+    /// Returns the amount of gas consumed so far
+    pub fn gas_used(&self) -> u64 {
+        self.root().gas_used
+    }
+
+    /// Returns the amount of gas still available
+    pub fn gas_remaining(&self) -> u64 {
+        self.root().gas_remaining()
+    }
+
+    /// Returns true once execution has come to an end, normally or exceptionally
+    pub fn is_halted(&self) -> bool {
+        self.frames.len() == 1 && self.root().outcome.is_some()
+    }
+
+    /// Starts the execution loop for the VM, and runs until execution comes to an end
+    pub fn execute(&mut self) -> ExecutionResult {
+        while !self.is_halted() {
+            // Errors are recorded as the outcome of the frame they happened in
+            let _ = self.execute_one();
+        }
+        self.result()
+    }
+
+    /// Runs until execution comes to an end like `execute`, telling `tracer` about every
+    /// instruction executed
+    pub fn execute_with_tracer(&mut self, tracer: &mut Tracer) -> ExecutionResult {
+        while !self.is_halted() {
+            let _ = self.execute_one_with_tracer(tracer);
+        }
+        self.result()
+    }
+
+    /// Returns the result of execution so far. Until the VM has halted, the outcome is reported as
+    /// a Stop. Once it has halted successfully, the refund is subtracted from the gas used.
+    pub fn result(&self) -> ExecutionResult {
+        let root = self.root();
+        let outcome = root.outcome.clone().unwrap_or(Outcome::Stop);
+        let (logs, gas_refunded) = match outcome {
+            Outcome::Stop | Outcome::Return(_) if self.is_halted() => (
+                self.host.logs().to_vec(),
+                gas_prices::capped_refund(self.host.refund(), root.gas_used, self.fork),
+            ),
+            Outcome::Stop | Outcome::Return(_) => (self.host.logs().to_vec(), 0),
+            _ => (vec![], 0),
+        };
+        ExecutionResult {
+            outcome,
+            gas_used: root.gas_used - gas_refunded,
+            gas_refunded,
+            logs,
         }
     }
 
-    /// Executes the next instruction only
+    /// Returns the refund counter, which may go down as well as up during execution
+    pub fn gas_refund(&self) -> i64 {
+        self.host.refund()
+    }
+
+    /// Executes the next instruction of the innermost call only. An error is returned if the
+    /// instruction halted execution as a whole; an error inside a nested call only ends that call.
     pub fn execute_one(&mut self) -> Result<()> {
-        let opcode = Opcode::from(&self.code[self.pc]);
+        self.execute_one_traced(None)
+    }
+
+    /// Executes the next instruction like `execute_one`, telling `tracer` about it
+    pub fn execute_one_with_tracer(&mut self, tracer: &mut Tracer) -> Result<()> {
+        self.execute_one_traced(Some(tracer))
+    }
+
+    fn execute_one_traced(&mut self, tracer: Option<&mut Tracer>) -> Result<()> {
+        if self.is_halted() {
+            return Ok(());
+        }
+        let mut trace = tracer.map(|tracer| (tracer, self.begin_step()));
+        let pc = self.frame().pc;
+        let result = self.step();
+        if let Some((ref mut tracer, ref mut step)) = trace {
+            self.end_step(step, &result);
+            tracer.step(step);
+        }
+        if let Err(ref e) = result {
+            let frame = self.frame_mut();
+            if e.consumes_all_gas() {
+                frame.exhaust_gas();
+            }
+            let opcode = frame.code.get(pc).cloned().unwrap_or(0x00);
+            frame.outcome = Some(Outcome::Halt(ExecutionError {
+                error: e.clone(),
+                pc,
+                opcode,
+            }));
+        }
+        if self.frames.len() > 1 && self.frame().outcome.is_some() {
+            self.finish_call();
+            return Ok(());
+        }
+        if self.is_halted() {
+            let checkpoint = self.root().checkpoint;
+            if self.result().is_success() {
+                self.host.commit(checkpoint);
+            } else {
+                self.host.revert(checkpoint);
+            }
+            if self.fork >= Fork::SpuriousDragon {
+                self.host.clear_empty_accounts();
+            }
+            self.host.finalize();
+        }
+        result
+    }
+
+    /// Captures the state of the VM before the next instruction runs
+    fn begin_step(&self) -> Step {
+        let frame = self.frame();
+        Step {
+            pc: frame.pc,
+            opcode: frame.code.get(frame.pc).cloned().unwrap_or(0x00),
+            gas: frame.gas_remaining(),
+            gas_cost: 0,
+            depth: self.frames.len(),
+            address: frame.address,
+            stack: frame.stack.items().to_vec(),
+            memory: self.active_memory(),
+            storage: None,
+            error: None,
+        }
+    }
+
+    /// Fills in what the instruction captured by `begin_step` did. Must be called before a
+    /// finished call is removed, so the frame it ran in is still there.
+    fn end_step(&self, step: &mut Step, result: &Result<()>) {
+        step.gas_cost = step.gas.saturating_sub(self.frames[step.depth - 1].gas_remaining());
+        match Opcode::from(&step.opcode) {
+            Opcode::SLOAD | Opcode::SSTORE if result.is_ok() => {
+                let index: U256 = (*step.stack.last().expect("the instruction succeeded")).into();
+                step.storage = Some((index, self.host.storage(step.address, index)));
+            }
+            _ => {}
+        }
+        step.error = result.as_ref().err().cloned();
+    }
+
+    fn step(&mut self) -> Result<()> {
+        let depth = self.depth();
+        let frame = self.frames.last_mut().expect("the root frame is never removed");
+        if frame.analysis.is_none() {
+            // Calls are analyzed before they start. A root frame running the code of its account
+            // is cached by the code hash the host keeps, and init code is analyzed as it runs.
+            let analysis = if depth == 0 && self.host.code(frame.address) == frame.code {
+                self.analyses
+                    .get(self.host.code_hash(frame.address), &frame.code, self.fork)
+            } else {
+                Rc::new(Analysis::analyze(&frame.code, self.fork))
+            };
+            frame.analysis = Some(analysis);
+        }
+        let instruction = *frame.analysis().instruction(frame.pc);
+        let opcode = instruction.opcode;
+        let cost = match instruction.cost {
+            Some(cost) => cost,
+            None => return Err(VMError::InvalidOpcode(frame.code[frame.pc])),
+        };
+        frame.charge_static_gas(u64::from(cost), instruction.block_gas)?;
+        let mut next_pc = frame.pc + 1;
         match opcode {
             Opcode::STOP => {
-                return Ok(());
-            }
-            Opcode::ADD => {
-                self.stack_pointer -= 1;
-                let result = self.registers[self.stack_pointer] + self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = result;
-                self.pc += 1;
-            }
-            Opcode::MUL => {
-                self.stack_pointer -= 1;
-                let result = self.registers[self.stack_pointer] * self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = result;
-                self.pc += 1;
-            }
-            Opcode::SUB => {
-                self.stack_pointer -= 1;
-                let result = self.registers[self.stack_pointer] - self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = result;
-                self.pc += 1;
-            }
-            Opcode::DIV => {
-                self.stack_pointer -= 1;
-                let result = self.registers[self.stack_pointer] / self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = result;
-                self.pc += 1;
-            }
-            Opcode::SDIV => {
-                self.stack_pointer -= 1;
-                let s1 = MI256::from(self.registers[self.stack_pointer]);
-                let s2 = MI256::from(self.registers[self.stack_pointer - 1]);
-                let result = s1 / s2;
-                let result: M256 = result.into();
-                self.registers[self.stack_pointer - 1] = result.into();
-                self.pc += 1;
-            }
-            Opcode::SMOD => {
-                self.stack_pointer -= 1;
-                let s1 = MI256::from(self.registers[self.stack_pointer]);
-                let s2 = MI256::from(self.registers[self.stack_pointer - 1]);
-                let result = s1 / s2;
-                self.registers[self.stack_pointer - 1] = result.into();
-                self.pc += 1;
-            }
-            Opcode::MOD => {
-                self.stack_pointer -= 1;
-                let result = self.registers[self.stack_pointer] % self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = result;
-                self.pc += 1;
-            }
-            Opcode::ADDMOD => {
-                self.stack_pointer -= 1;
-                let result = (self.registers[self.stack_pointer] + self.registers[self.stack_pointer - 1])
-                    % self.registers[self.stack_pointer - 2];
-                if result == self.registers[self.stack_pointer - 2] {
-                    self.registers[self.stack_pointer - 2] = result;
-                } else {
-                    self.registers[self.stack_pointer - 2] = 0.into();
-                }
-            }
-            Opcode::MULMOD => {
-                self.stack_pointer -= 1;
-                let result = (self.registers[self.stack_pointer] * self.registers[self.stack_pointer - 1])
-                    % self.registers[self.stack_pointer - 2];
-                if result == self.registers[self.stack_pointer - 2] {
-                    self.registers[self.stack_pointer - 2] = result;
-                } else {
-                    self.registers[self.stack_pointer - 2] = 0.into();
-                }
+                frame.outcome = Some(Outcome::Stop);
             }
-            Opcode::EXP => {
-                let s1 = self.registers[self.stack_pointer];
-                let s2 = self.registers[self.stack_pointer - 1];
-                if s1 > M256::from(32) {
-                    self.registers[self.stack_pointer - 1] = s2;
-                } else {
-                    let mut ret = M256::zero();
-                    let len: usize = s1.as_usize();
-                    let t: usize = 8 * (len + 1) - 1;
-                    let t_bit_mask = M256::one() << t;
-                    let t_value = (s2 & t_bit_mask) >> t;
-                    for i in 0..256 {
-                        let bit_mask = M256::one() << i;
-                        let i_value = (s2 & bit_mask) >> i;
-                        if i <= t {
-                            ret = ret + (i_value << i);
-                        } else {
-                            ret = ret + (t_value << i);
-                        }
-                    }
-                    self.registers[self.stack_pointer - 1] = s2;
+            Opcode::ADD => frame.binary_op(|a, b| a + b)?,
+            Opcode::MUL => frame.binary_op(|a, b| a * b)?,
+            Opcode::SUB => frame.binary_op(|a, b| a - b)?,
+            Opcode::DIV => frame.binary_op(|a, b| a / b)?,
+            Opcode::SDIV => frame.binary_op(sdiv)?,
+            Opcode::MOD => frame.binary_op(|a, b| a % b)?,
+            Opcode::SMOD => frame.binary_op(smod)?,
+            Opcode::ADDMOD => frame.ternary_op(|a, b, n| {
+                if n == M256::zero() {
+                    return M256::zero();
                 }
-            }
-            Opcode::SIGNEXTEND => {
-                let s1: U256 = self.registers[self.stack_pointer].into();
-                if s1 < U256::from(32) {
-                    let s2: U256 = self.registers[self.stack_pointer - 1].into();
-                    let bit_position = (s2.low_u64() * 8 + 7) as usize;
-
-                    let bit = s2.bit(bit_position);
-                    let mask = (U256::one() << bit_position) - U256::one();
-                    if bit {
-                        self.registers[self.stack_pointer - 1] = (s2 | !mask).into()
-                    } else {
-                        self.registers[self.stack_pointer - 1] = (s2 & mask).into()
-                    };
+                let sum = U512::from(a.0) + U512::from(b.0);
+                (sum % U512::from(n.0)).into()
+            })?,
+            Opcode::MULMOD => frame.ternary_op(|a, b, n| {
+                if n == M256::zero() {
+                    return M256::zero();
                 }
+                let product = U512::from(a.0) * U512::from(b.0);
+                (product % U512::from(n.0)).into()
+            })?,
+            Opcode::EXP => {
+                let base = frame.stack.pop()?;
+                let exponent = frame.stack.pop()?;
+                frame.consume_gas(gas_prices::exp_cost((exponent.bits() as u64).div_ceil(8), self.fork))?;
+                frame.stack.push(exp(base, exponent))?;
             }
-            Opcode::LT => {
-                self.stack_pointer -= 1;
-                if self.registers[self.stack_pointer] > self.registers[self.stack_pointer - 1] {
-                    self.registers[self.stack_pointer - 1] = 1.into();
+            Opcode::SIGNEXTEND => frame.binary_op(signextend)?,
+            Opcode::LT => frame.binary_op(|a, b| (a < b).into())?,
+            Opcode::GT => frame.binary_op(|a, b| (a > b).into())?,
+            Opcode::SLT => frame.binary_op(|a, b| (MI256::from(a) < MI256::from(b)).into())?,
+            Opcode::SGT => frame.binary_op(|a, b| (MI256::from(a) > MI256::from(b)).into())?,
+            Opcode::EQ => frame.binary_op(|a, b| (a == b).into())?,
+            Opcode::ISZERO => frame.unary_op(|a| (a == M256::zero()).into())?,
+            Opcode::AND => frame.binary_op(|a, b| a & b)?,
+            Opcode::OR => frame.binary_op(|a, b| a | b)?,
+            Opcode::XOR => frame.binary_op(|a, b| a ^ b)?,
+            Opcode::NOT => frame.unary_op(|a| !a)?,
+            Opcode::BYTE => frame.binary_op(|i, x| {
+                if i < M256::from(32) {
+                    M256::from(x.index(i.as_usize()) as u64)
                 } else {
-                    self.registers[self.stack_pointer - 1] = 0.into();
+                    M256::zero()
                 }
-                self.pc += 2;
-            }
-            Opcode::GT => {
-                self.stack_pointer -= 1;
-                if self.registers[self.stack_pointer] < self.registers[self.stack_pointer - 1] {
-                    self.registers[self.stack_pointer - 1] = 1.into();
+            })?,
+            Opcode::SHL => frame.binary_op(|shift, value| {
+                if shift >= M256::from(256) {
+                    M256::zero()
                 } else {
-                    self.registers[self.stack_pointer - 1] = 0.into();
+                    value << shift.as_usize()
                 }
-                self.pc += 1;
-            }
-            Opcode::SLT => {
-                self.stack_pointer -= 1;
-                let s1 = MI256::from(self.registers[self.stack_pointer]);
-                let s2 = MI256::from(self.registers[self.stack_pointer - 1]);
-                let result = s1 > s2;
-                self.registers[self.stack_pointer - 1] = result.into();
-                self.pc += 1;
-            }
-            Opcode::SGT => {
-                self.stack_pointer -= 1;
-                let s1 = MI256::from(self.registers[self.stack_pointer]);
-                let s2 = MI256::from(self.registers[self.stack_pointer - 1]);
-                let result = s1 < s2;
-                self.registers[self.stack_pointer - 1] = result.into();
-                self.pc += 1;
-            }
-            Opcode::EQ => {
-                self.stack_pointer -= 1;
-                if self.registers[self.stack_pointer] == self.registers[self.stack_pointer - 1] {
-                    self.registers[self.stack_pointer - 1] = 1.into();
+            })?,
+            Opcode::SHR => frame.binary_op(|shift, value| {
+                if shift >= M256::from(256) {
+                    M256::zero()
                 } else {
-                    self.registers[self.stack_pointer - 1] = 0.into();
+                    value >> shift.as_usize()
                 }
-                self.pc += 1;
+            })?,
+            Opcode::SAR => frame.binary_op(sar)?,
+            Opcode::SHA3 => {
+                let (offset, size) = frame.pop_memory_range()?;
+                frame.consume_gas(gas_prices::sha3_cost(size.as_u64()))?;
+                let data = frame.read_memory(offset, size)?;
+                frame.stack.push(M256::from(&*keccak(&data)))?;
             }
-            Opcode::ISZERO => {
-                self.stack_pointer -= 1;
-                if self.registers[self.stack_pointer] == 0.into() {
-                    self.registers[self.stack_pointer] = 1.into()
-                } else {
-                    self.registers[self.stack_pointer] = 0.into()
-                }
-                self.pc += 1;
-            }
-            Opcode::AND => {
-                self.stack_pointer -= 1;
-                let s1 = self.registers[self.stack_pointer];
-                let s2 = self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = s1 & s2;
-                self.pc += 1;
-            }
-            Opcode::OR => {
-                self.stack_pointer -= 1;
-                let s1 = self.registers[self.stack_pointer];
-                let s2 = self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = s1 | s2;
-                self.pc += 1;
-            }
-            Opcode::XOR => {
-                self.stack_pointer -= 1;
-                let s1 = self.registers[self.stack_pointer];
-                let s2 = self.registers[self.stack_pointer - 1];
-                self.registers[self.stack_pointer - 1] = s1 ^ s2;
-                self.pc += 1;
-            }
-            Opcode::NOT => {
-                self.stack_pointer -= 1;
-                let s1 = self.registers[self.stack_pointer];
-                self.registers[self.stack_pointer] = !s1;
-                self.pc += 1;
-            }
-            Opcode::BYTE => {
-                self.stack_pointer -= 1;
-                let s1 = self.registers[self.stack_pointer];
-                let s2 = self.registers[self.stack_pointer - 1];
-                let mut ret = M256::zero();
-                for i in 0..256 {
-                    if i < 8 && s1 < 32.into() {
-                        let o: usize = s1.as_usize();
-                        let t = 255 - (7 - i + 8 * o);
-                        let bit_mask = M256::one() << t;
-                        let value = (s2 & bit_mask) >> t;
-                        ret = ret + (value << i);
-                    }
-                }
-                self.registers[self.stack_pointer] = ret;
+            Opcode::ADDRESS => frame.stack.push(frame.address.into())?,
+            Opcode::BALANCE => {
+                let address = Address::from(frame.stack.pop()?);
+                frame.consume_gas(account_access_cost(&mut self.host, self.fork, address))?;
+                frame.stack.push(self.host.balance(address).into())?;
             }
-            Opcode::SHA3 => {
-                let offset = self.registers[self.stack_pointer];
-                let size = self.registers[self.stack_pointer - 1];
-                if let Some(ref mut mem) = self.memory {
-                    let k = keccak(mem.read_slice(offset.into(), size.into()));
-                    println!("k is: {:?}", k);
-                    self.registers[self.stack_pointer - 1] = M256::from(&*k);
-                    self.pc += 1;
+            Opcode::ORIGIN => frame.stack.push(self.environment.transaction.origin.into())?,
+            Opcode::CALLER => frame.stack.push(frame.caller.into())?,
+            Opcode::CALLVALUE => frame.stack.push(frame.value.into())?,
+            Opcode::CALLDATALOAD => {
+                let offset = frame.stack.pop()?;
+                let value = read_padded_word(&frame.calldata, offset.into());
+                frame.stack.push(value)?;
+            }
+            Opcode::CALLDATASIZE => frame.stack.push(frame.calldata.len().into())?,
+            Opcode::CALLDATACOPY => {
+                let (memory_offset, data_offset, len) = frame.pop_copy_operands()?;
+                frame
+                    .memory
+                    .copy_into_memory(&frame.calldata, memory_offset, data_offset, len)?;
+            }
+            Opcode::CODESIZE => frame.stack.push(frame.code.len().into())?,
+            Opcode::CODECOPY => {
+                let (memory_offset, code_offset, len) = frame.pop_copy_operands()?;
+                frame
+                    .memory
+                    .copy_into_memory(&frame.code, memory_offset, code_offset, len)?;
+            }
+            Opcode::GASPRICE => frame.stack.push(self.environment.transaction.gas_price.into())?,
+            Opcode::EXTCODESIZE => {
+                let address = Address::from(frame.stack.pop()?);
+                frame.consume_gas(account_access_cost(&mut self.host, self.fork, address))?;
+                frame.stack.push(self.host.code(address).len().into())?;
+            }
+            Opcode::EXTCODECOPY => {
+                let address = Address::from(frame.stack.pop()?);
+                frame.consume_gas(account_access_cost(&mut self.host, self.fork, address))?;
+                let (memory_offset, code_offset, len) = frame.pop_copy_operands()?;
+                let code = self.host.code(address);
+                frame.memory.copy_into_memory(&code, memory_offset, code_offset, len)?;
+            }
+            Opcode::EXTCODEHASH => {
+                let address = Address::from(frame.stack.pop()?);
+                frame.consume_gas(account_access_cost(&mut self.host, self.fork, address))?;
+                // Empty accounts count as not existing, so they have no code hash either
+                if self.host.is_empty(address) {
+                    frame.stack.push(M256::zero())?;
+                } else {
+                    frame.stack.push(self.host.code_hash(address).into())?;
                 }
             }
-            Opcode::ADDRESS => {
-                if let Some(addr) = self.address {
-                    self.registers[self.stack_pointer] = addr.clone().into();
+            Opcode::RETURNDATASIZE => frame.stack.push(frame.return_data.len().into())?,
+            Opcode::RETURNDATACOPY => {
+                let (memory_offset, data_offset, len) = frame.pop_copy_operands()?;
+                let available = U256::from(frame.return_data.len());
+                if len > available || data_offset > available - len {
+                    return Err(VMError::ReturnDataOutOfBounds);
                 }
+                frame
+                    .memory
+                    .copy_into_memory(&frame.return_data, memory_offset, data_offset, len)?;
             }
-            Opcode::BALANCE => unimplemented!(),
-            Opcode::ORIGIN => unimplemented!(),
-            Opcode::CALLER => unimplemented!(),
-            Opcode::CALLVALUE => unimplemented!(),
-            Opcode::CALLDATALOAD => unimplemented!(),
-            Opcode::CALLDATASIZE => unimplemented!(),
-            Opcode::CALLDATACOPY => unimplemented!(),
-            Opcode::CODESIZE => unimplemented!(),
-            Opcode::GASPRICE => unimplemented!(),
-            Opcode::EXTCODESIZE => unimplemented!(),
-            Opcode::EXTCODECOPY => unimplemented!(),
-            Opcode::RETURNDATACOPY => unimplemented!(),
-            Opcode::RETURNDATASIZE => unimplemented!(),
-            Opcode::BLOCKHASH => unimplemented!(),
-            Opcode::COINBASE => unimplemented!(),
-            Opcode::TIMESTAMP => unimplemented!(),
-            Opcode::NUMBER => unimplemented!(),
-            Opcode::DIFFICULTY => unimplemented!(),
-            Opcode::GASLIMIT => unimplemented!(),
-            Opcode::PC => {
-                self.registers[self.stack_pointer] = (self.pc - 1).into();
+            Opcode::BLOCKHASH => {
+                let number: U256 = frame.stack.pop()?.into();
+                let current = self.environment.block.number;
+                // Only the 256 most recent complete blocks are available
+                let hash = if number < current && current - number <= U256::from(256) {
+                    self.host.block_hash(number)
+                } else {
+                    H256::zero()
+                };
+                frame.stack.push(hash.into())?;
             }
+            Opcode::COINBASE => frame.stack.push(self.environment.block.coinbase.into())?,
+            Opcode::TIMESTAMP => frame.stack.push(self.environment.block.timestamp.into())?,
+            Opcode::NUMBER => frame.stack.push(self.environment.block.number.into())?,
+            Opcode::DIFFICULTY => frame.stack.push(self.environment.block.difficulty.into())?,
+            Opcode::GASLIMIT => frame.stack.push(self.environment.block.gas_limit.into())?,
+            Opcode::CHAINID => frame.stack.push(self.environment.block.chain_id.into())?,
+            Opcode::SELFBALANCE => frame.stack.push(self.host.balance(frame.address).into())?,
+            Opcode::BASEFEE => frame.stack.push(self.environment.block.base_fee.into())?,
+            Opcode::PC => frame.stack.push(frame.pc.into())?,
             Opcode::POP => {
-                self.stack_pointer -= 1;
+                frame.stack.pop()?;
             }
-            Opcode::GAS => unimplemented!(),
+            Opcode::GAS => frame.stack.push(frame.gas_remaining().into())?,
             Opcode::JUMP => {
-                let new_pc = self.registers[self.stack_pointer];
-                self.pc = new_pc.as_usize();
+                let destination = frame.stack.pop()?;
+                next_pc = frame.jump_target(destination)?;
             }
             Opcode::JUMPI => {
-                self.stack_pointer -= 1;
-                let destination = self.registers[self.stack_pointer];
-                let check = self.registers[self.stack_pointer - 1];
-                if check.as_usize() == 0 {
-                    self.pc = destination.as_usize();
+                let destination = frame.stack.pop()?;
+                let condition = frame.stack.pop()?;
+                if condition != M256::zero() {
+                    next_pc = frame.jump_target(destination)?;
                 }
             }
-            Opcode::JUMPDEST => unimplemented!(),
-            Opcode::CREATE => unimplemented!(),
-            Opcode::CALL => unimplemented!(),
-            Opcode::CALLCODE => unimplemented!(),
-            Opcode::RETURN => unimplemented!(),
-            Opcode::DELEGATECALL => unimplemented!(),
-            Opcode::INVALID => unimplemented!(),
-            Opcode::SUICIDE => unimplemented!(),
+            Opcode::JUMPDEST => {}
+            Opcode::CREATE | Opcode::CREATE2 => {
+                return self.create(opcode);
+            }
+            Opcode::CALL | Opcode::CALLCODE | Opcode::DELEGATECALL | Opcode::STATICCALL => {
+                return self.call(opcode);
+            }
+            Opcode::RETURN => {
+                let data = frame.pop_output_data()?;
+                frame.outcome = Some(Outcome::Return(data));
+            }
+            Opcode::REVERT => {
+                let data = frame.pop_output_data()?;
+                frame.outcome = Some(Outcome::Revert(data));
+            }
+            Opcode::INVALID => {
+                return Err(VMError::InvalidOpcode(frame.code[frame.pc]));
+            }
+            Opcode::SUICIDE => return self.selfdestruct(),
             Opcode::SLOAD => {
-                self.stack_pointer -= 1;
-                let s1 = self.registers[self.stack_pointer];
-                if let Some(ref mut store) = self.storage {
-                    self.registers[self.stack_pointer] = store.read(s1.into()).unwrap();
-                } else {
-                    return Err(VMError::MemoryError);
+                let index = frame.stack.pop()?;
+                if self.fork >= Fork::Berlin && self.host.access_slot(frame.address, index.into()) {
+                    frame.consume_gas(gas_prices::COLD_SLOAD - gas_prices::WARM_STORAGE_READ)?;
                 }
+                frame.stack.push(self.host.storage(frame.address, index.into()))?;
             }
             Opcode::SSTORE => {
-                self.stack_pointer -= 1;
-                let s1 = self.registers[self.stack_pointer];
-                let s2 = self.registers[self.stack_pointer - 1];
-                if let Some(ref mut store) = self.storage {
-                    match store.write(s1.into(), s2.into()) {
-                        Ok(_) => {}
-                        Err(_e) => {
-                            return Err(VMError::MemoryError);
-                        }
-                    }
-                } else {
-                    return Err(VMError::MemoryError);
+                if frame.is_static {
+                    return Err(VMError::StaticViolation);
+                }
+                let index = frame.stack.pop()?;
+                let value = frame.stack.pop()?;
+                // From Istanbul on, SSTORE cannot run on the stipend of a call that transferred value
+                if self.fork >= Fork::Istanbul && frame.gas_remaining() <= gas_prices::CALL_STIPEND {
+                    return Err(VMError::OutOfGas);
+                }
+                if self.fork >= Fork::Berlin && self.host.access_slot(frame.address, index.into()) {
+                    frame.consume_gas(gas_prices::COLD_SLOAD)?;
                 }
+                let original = self.host.original_storage(frame.address, index.into());
+                let current = self.host.storage(frame.address, index.into());
+                let (cost, refund) = gas_prices::sstore_cost(self.fork, original, current, value);
+                frame.consume_gas(cost)?;
+                self.host.add_refund(refund);
+                self.host
+                    .set_storage(frame.address, index.into(), value)
+                    .map_err(|_| VMError::StorageFailure)?;
             }
             Opcode::MLOAD => {
-                self.stack_pointer -= 1;
-                let offset = self.registers[self.stack_pointer];
-                if let Some(ref mut mem) = self.memory {
-                    self.registers[self.stack_pointer] = mem.read(offset);
-                } else {
-                    return Err(VMError::MemoryError);
-                }
+                let offset = frame.stack.pop()?;
+                frame.expand_memory(offset.into(), U256::from(32))?;
+                let value = frame.memory.read(offset)?;
+                frame.stack.push(value)?;
             }
             Opcode::MSTORE => {
-                self.stack_pointer -= 1;
-                let offset = self.registers[self.stack_pointer];
-                let value = self.registers[self.stack_pointer - 1];
-                if let Some(ref mut mem) = self.memory {
-                    mem.write(offset, value)?;
-                    self.pc += 1;
-                } else {
-                    return Err(VMError::MemoryError);
-                }
+                let offset = frame.stack.pop()?;
+                let value = frame.stack.pop()?;
+                frame.expand_memory(offset.into(), U256::from(32))?;
+                frame.memory.write(offset, value)?;
             }
             Opcode::MSTORE8 => {
-                self.stack_pointer -= 1;
-                let offset = self.registers[self.stack_pointer];
-                let value = self.registers[self.stack_pointer - 1] % 256.into();
-                if let Some(ref mut mem) = self.memory {
-                    mem.write_byte(offset, (value.0.low_u32() & 0xFF) as u8)?;
-                    self.pc += 1;
+                let offset = frame.stack.pop()?;
+                let value = frame.stack.pop()?;
+                frame.expand_memory(offset.into(), U256::one())?;
+                frame.memory.write_byte(offset, (value.0.low_u32() & 0xFF) as u8)?;
+            }
+            Opcode::MSIZE => frame.stack.push((frame.memory_words * 32).into())?,
+            Opcode::PUSH(bytes) => {
+                let value = frame.analysis().immediate(&instruction);
+                frame.stack.push(value)?;
+                next_pc = frame.pc + 1 + bytes;
+            }
+            Opcode::DUP(n) => frame.stack.dup(n)?,
+            Opcode::SWAP(n) => frame.stack.swap(n)?,
+            Opcode::LOG(topic_count) => {
+                if frame.is_static {
+                    return Err(VMError::StaticViolation);
+                }
+                let offset: U256 = frame.stack.pop()?.into();
+                let len: U256 = frame.stack.pop()?.into();
+                let mut topics: Vec<H256> = Vec::with_capacity(topic_count);
+                for _ in 0..topic_count {
+                    topics.push(H256::from(frame.stack.pop()?));
                 }
+                frame.expand_memory(offset, len)?;
+                frame.consume_gas(gas_prices::log_cost(len.as_u64()))?;
+                let data = frame.read_memory(offset, len)?;
+                self.host.log(Log {
+                    address: frame.address,
+                    data,
+                    topics,
+                });
             }
-            Opcode::MSIZE => {
-                if let Some(ref mut mem) = self.memory {
-                    self.registers[self.stack_pointer] = mem.size();
-                    self.pc += 1;
-                } else {
-                    return Err(VMError::MemoryError);
+        };
+
+        frame.pc = next_pc;
+        Ok(())
+    }
+
+    /// Starts a message call from the innermost frame. The callee runs in a frame of its own, and
+    /// its result is handed back by `finish_call` once it halts.
+    fn call(&mut self, opcode: Opcode) -> Result<()> {
+        let depth = self.frames.len();
+        let frame = self.frames.last_mut().expect("the root frame is never removed");
+        let requested_gas = frame.stack.pop()?;
+        let to = Address::from(frame.stack.pop()?);
+        let value: U256 = match opcode {
+            Opcode::CALL | Opcode::CALLCODE => frame.stack.pop()?.into(),
+            _ => U256::zero(),
+        };
+        let (in_offset, in_len) = frame.pop_memory_range()?;
+        let (out_offset, out_len) = frame.pop_memory_range()?;
+        if opcode == Opcode::CALL && frame.is_static && !value.is_zero() {
+            return Err(VMError::StaticViolation);
+        }
+
+        frame.consume_gas(account_access_cost(&mut self.host, self.fork, to))?;
+        if !value.is_zero() {
+            frame.consume_gas(gas_prices::CALL_VALUE)?;
+        }
+        // Before Spurious Dragon any call to an account that does not exist creates it, while
+        // after it only calls that transfer value to an empty account do
+        let creates_account = if self.fork >= Fork::SpuriousDragon {
+            !value.is_zero() && self.host.is_empty(to)
+        } else {
+            !self.host.exists(to)
+        };
+        if opcode == Opcode::CALL && creates_account {
+            frame.consume_gas(gas_prices::NEW_ACCOUNT)?;
+        }
+        let gas = if self.fork >= Fork::TangerineWhistle {
+            // At most all but one 64th of the remaining gas can be passed on to the callee
+            let available = frame.gas_remaining() - frame.gas_remaining() / 64;
+            if requested_gas > M256::from(available) {
+                available
+            } else {
+                requested_gas.as_u64()
+            }
+        } else if requested_gas > M256::from(frame.gas_remaining()) {
+            // Before Tangerine Whistle the callee gets exactly what was requested, or nothing runs
+            return frame.consume_gas(frame.gas_remaining() + 1);
+        } else {
+            requested_gas.as_u64()
+        };
+        frame.consume_gas(gas)?;
+        let stipend = if value.is_zero() { 0 } else { gas_prices::CALL_STIPEND };
+
+        let calldata = frame.read_memory(in_offset, in_len)?;
+        frame.pc += 1;
+        frame.return_data = vec![];
+        if let Some(error) = early_failure(depth, self.host.balance(frame.address), value) {
+            // The call fails without running, and the caller keeps the gas it would have passed on
+            // along with the stipend
+            return frame.fail_early(gas + stipend, &error);
+        }
+
+        let (address, caller, call_value) = match opcode {
+            Opcode::CALLCODE => (frame.address, frame.address, value),
+            Opcode::DELEGATECALL => (frame.address, frame.caller, frame.value),
+            _ => (to, frame.address, value),
+        };
+        let checkpoint = self.host.checkpoint();
+        if opcode == Opcode::CALL {
+            if creates_account && self.fork < Fork::SpuriousDragon {
+                self.host.set_balance(to, U256::zero());
+            }
+            self.host.transfer(frame.address, to, value);
+            self.host.touch(to);
+        }
+        let kind = FrameKind::Call { out_offset, out_len };
+        let precompile = precompiles::is_precompile(to, self.fork);
+        let code = if precompile { vec![] } else { self.host.code(to) };
+        let analysis = if precompile {
+            None
+        } else {
+            Some(self.analyses.get(self.host.code_hash(to), &code, self.fork))
+        };
+        let mut callee = Frame::new(kind, code, gas + stipend);
+        callee.analysis = analysis;
+        callee.memory = self.memory_kind.create(self.memory_limit);
+        callee.checkpoint = checkpoint;
+        callee.address = address;
+        callee.caller = caller;
+        callee.value = call_value;
+        callee.calldata = calldata;
+        callee.is_static = frame.is_static || opcode == Opcode::STATICCALL;
+        if precompile {
+            // Precompiled contracts run to completion at once, so their frame is finished as soon
+            // as it is started
+            let result = precompiles::execute(to, &callee.calldata, callee.gas_limit, self.fork);
+            callee.outcome = Some(match result {
+                Ok((gas_used, output)) => {
+                    callee.gas_used = gas_used;
+                    Outcome::Return(output)
+                }
+                // A precompiled contract has no code, so its errors happen at the start of it
+                Err(error) => {
+                    callee.exhaust_gas();
+                    Outcome::Halt(ExecutionError {
+                        error,
+                        pc: 0,
+                        opcode: 0x00,
+                    })
                 }
+            });
+            self.frames.push(callee);
+            self.finish_call();
+            return Ok(());
+        }
+        self.frames.push(callee);
+        Ok(())
+    }
+
+    /// Starts running init code from the innermost frame to create a new contract. Like a call, the
+    /// init code runs in a frame of its own, and `finish_call` deploys whatever it returns.
+    fn create(&mut self, opcode: Opcode) -> Result<()> {
+        let depth = self.frames.len();
+        let frame = self.frames.last_mut().expect("the root frame is never removed");
+        if frame.is_static {
+            return Err(VMError::StaticViolation);
+        }
+        let value: U256 = frame.stack.pop()?.into();
+        let (offset, len) = frame.pop_memory_range()?;
+        let salt = match opcode {
+            Opcode::CREATE2 => {
+                let salt = frame.stack.pop()?;
+                frame.consume_gas(gas_prices::sha3_cost(len.as_u64()))?;
+                Some(H256::from(salt))
             }
-            Opcode::PUSH(bytes) => {
-                let range = &self.code[self.pc + 1..self.pc + 1 + bytes];
-                self.registers[self.stack_pointer] = M256::from(range);
-                self.stack_pointer += 1;
-                self.pc += bytes + 1;
-            }
-            Opcode::DUP(bytes) => {
-                let val = self.registers[bytes - 1];
-                self.registers[self.stack_pointer] = val;
-            }
-            Opcode::SWAP(bytes) => {
-                let val1 = self.registers[self.stack_pointer - 1];
-                let val2 = self.registers[bytes - 1];
-                self.registers[self.stack_pointer - 1] = val2;
-                self.registers[bytes - 1] = val1;
-            }
-            Opcode::LOG(bytes) => {
-                self.stack_pointer -= 1;
-                let index = self.registers[self.stack_pointer];
-                let len = self.registers[self.stack_pointer - 1];
-                if let Some(ref mut mem) = self.memory {
-                    let data = mem.copy_from_memory(index.into(), len.into());
-                    let mut topics: Vec<H256> = Vec::new();
-                    for _ in 0..bytes {
-                        let pointer = self.stack_pointer + (bytes + 1);
-                        topics.push(H256::from(self.registers[pointer]));
-                    }
-                    println!("Pushing logs");
-                    self.logs.push(Log {
-                        address: self.address.unwrap(),
-                        data: data,
-                        topics: topics,
-                    });
+            _ => None,
+        };
+        let init_code = frame.read_memory(offset, len)?;
+        // Init code gets all but one 64th of the remaining gas, as with calls. Before Tangerine
+        // Whistle it gets all of it.
+        let gas = if self.fork >= Fork::TangerineWhistle {
+            frame.gas_remaining() - frame.gas_remaining() / 64
+        } else {
+            frame.gas_remaining()
+        };
+        frame.consume_gas(gas)?;
+        frame.pc += 1;
+        frame.return_data = vec![];
+        let creator = frame.address;
+        if let Some(error) = early_failure(depth, self.host.balance(creator), value) {
+            return frame.fail_early(gas, &error);
+        }
+
+        let nonce = self.host.nonce(creator);
+        self.host.set_nonce(creator, nonce + U256::one());
+        let address = match salt {
+            Some(salt) => create2_address(creator, salt, &init_code),
+            None => create_address(creator, nonce),
+        };
+        self.host.access_address(address);
+        if !self.host.nonce(address).is_zero() || !self.host.code(address).is_empty() {
+            // Creating over an existing contract fails, and the gas given to the init code is lost
+            return frame.stack.push(M256::zero());
+        }
+        let checkpoint = self.host.checkpoint();
+        self.host.transfer(creator, address, value);
+        if self.fork >= Fork::SpuriousDragon {
+            self.host.set_nonce(address, U256::one());
+        }
+        let mut init = Frame::new(FrameKind::Create { address }, init_code, gas);
+        init.memory = self.memory_kind.create(self.memory_limit);
+        init.checkpoint = checkpoint;
+        init.address = address;
+        init.caller = creator;
+        init.value = value;
+        self.frames.push(init);
+        Ok(())
+    }
+
+    /// Destroys the contract of the innermost frame at the end of the transaction, sending its whole
+    /// balance to the beneficiary on the stack, and halts the frame
+    fn selfdestruct(&mut self) -> Result<()> {
+        let frame = self.frames.last_mut().expect("the root frame is never removed");
+        if frame.is_static {
+            return Err(VMError::StaticViolation);
+        }
+        let beneficiary = Address::from(frame.stack.pop()?);
+        if self.fork >= Fork::Berlin && self.host.access_address(beneficiary) {
+            frame.consume_gas(gas_prices::COLD_ACCOUNT_ACCESS)?;
+        }
+        let balance = self.host.balance(frame.address);
+        // From Tangerine Whistle, bringing the beneficiary into existence costs extra. From Spurious
+        // Dragon, only if value is actually sent to an empty account.
+        let creates_account = if self.fork >= Fork::SpuriousDragon {
+            !balance.is_zero() && self.host.is_empty(beneficiary)
+        } else {
+            self.fork >= Fork::TangerineWhistle && !self.host.exists(beneficiary)
+        };
+        if creates_account {
+            frame.consume_gas(gas_prices::NEW_ACCOUNT)?;
+        }
+
+        if self.host.selfdestruct(frame.address) {
+            self.host.add_refund(gas_prices::selfdestruct_refund(self.fork));
+        }
+        self.host.touch(beneficiary);
+        // A contract that names itself as the beneficiary destroys its balance
+        if !balance.is_zero() {
+            let beneficiary_balance = self.host.balance(beneficiary);
+            self.host.set_balance(beneficiary, beneficiary_balance + balance);
+            self.host.set_balance(frame.address, U256::zero());
+        }
+        frame.outcome = Some(Outcome::Stop);
+        Ok(())
+    }
+
+    /// Removes the innermost frame once it has halted, and hands its result back to the caller.
+    /// Unused gas is returned and the output is copied into the caller's return data buffer. A call
+    /// also copies it into the caller's memory and pushes 1 for success or 0 for failure, while a
+    /// create deploys it as the code of the new contract and pushes its address, or 0 on failure.
+    /// Every change the frame made is undone if it failed.
+    fn finish_call(&mut self) {
+        let mut callee = self.frames.pop().expect("the root frame is never removed");
+        let (success, output) = match callee.outcome.take() {
+            Some(Outcome::Stop) => (true, vec![]),
+            Some(Outcome::Return(data)) => (true, data),
+            Some(Outcome::Revert(data)) => (false, data),
+            _ => (false, vec![]),
+        };
+        let (success, result, return_data) = match callee.kind {
+            FrameKind::Create { address } => {
+                if !success {
+                    (false, M256::zero(), output)
+                } else if self.deposit_code(&mut callee, address, output) {
+                    (true, M256::from(address), vec![])
                 } else {
-                    return Err(VMError::MemoryError);
+                    (false, M256::zero(), vec![])
                 }
             }
-            _ => {
-                return Err(VMError::UnknownOpcodeError);
-            }
+            _ => (success, M256::from(success), output),
         };
+        if success {
+            self.host.commit(callee.checkpoint);
+        } else {
+            self.host.revert(callee.checkpoint);
+        }
 
-        Ok(())
+        let caller = self.frame_mut();
+        caller.gas_used -= callee.gas_remaining();
+        if let FrameKind::Call { out_offset, out_len } = callee.kind {
+            let len = cmp::min(out_len, U256::from(return_data.len()));
+            caller
+                .memory
+                .copy_into_memory(&return_data, out_offset, U256::zero(), len)
+                .expect("the output range was expanded when the call was made");
+        }
+        caller.return_data = return_data;
+        caller
+            .stack
+            .push(result)
+            .expect("a call pops more items than it pushes");
+    }
+
+    /// Stores the `code` returned by init code as the code of the contract at `address`, charging
+    /// the init code's frame for it. Returns false, having consumed all of the frame's gas, if the
+    /// code is too large, starts with 0xef from London on, or cannot be paid for. On Frontier, code
+    /// that cannot be paid for is dropped instead, and the contract is created without code.
+    fn deposit_code(&mut self, frame: &mut Frame, address: Address, code: Vec<u8>) -> bool {
+        let too_large = self.fork >= Fork::SpuriousDragon && code.len() > gas_prices::MAX_CODE_SIZE;
+        let reserved = self.fork >= Fork::London && code.first() == Some(&0xef);
+        if too_large || reserved {
+            frame.exhaust_gas();
+            return false;
+        }
+        let cost = gas_prices::CREATE_DATA * code.len() as u64;
+        if cost > frame.gas_remaining() {
+            if self.fork == Fork::Frontier {
+                return true;
+            }
+            frame.exhaust_gas();
+            return false;
+        }
+        frame.gas_used += cost;
+        self.host.set_code(address, code);
+        true
+    }
+
+    /// Returns the stack of the call that is currently executing
+    pub fn stack(&self) -> &Stack {
+        &self.frame().stack
+    }
+
+    /// Returns the memory of the call that is currently executing
+    pub fn memory(&self) -> &Memory {
+        &*self.frame().memory
+    }
+
+    /// Returns the memory of the call that is currently executing, which can be read from and
+    /// written to
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut *self.frame_mut().memory
+    }
+
+    /// Returns the memory of the call that is currently executing, up to the size it has been
+    /// expanded to
+    pub fn active_memory(&self) -> Vec<u8> {
+        self.frame().memory.as_slice().to_vec()
+    }
+
+    /// Returns the code of the call that is currently executing
+    pub fn code(&self) -> &[u8] {
+        &self.frame().code
+    }
+
+    /// Returns the position of the next instruction in the code of the call that is currently
+    /// executing
+    pub fn pc(&self) -> usize {
+        self.frame().pc
+    }
+
+    /// Returns the address of the account whose code is currently executing
+    pub fn address(&self) -> Address {
+        self.frame().address
     }
 
-    /// Utility function to print the values of a range of registers
+    /// Returns the logs emitted so far. They are discarded if the call that emitted them fails.
+    pub fn logs(&self) -> &[Log] {
+        self.host.logs()
+    }
+
+    /// Returns the host the VM operates on
+    pub fn host(&self) -> &H {
+        self.host.host()
+    }
+
+    /// Returns the number of calls in progress below the one the VM was created with
+    pub fn depth(&self) -> usize {
+        self.frames.len() - 1
+    }
+
+    fn root(&self) -> &Frame {
+        &self.frames[0]
+    }
+
+    fn root_mut(&mut self) -> &mut Frame {
+        &mut self.frames[0]
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("the root frame is never removed")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("the root frame is never removed")
+    }
+
+    /// Utility function to print the values of a range of stack slots, counting from the bottom
     pub fn print_registers(&self, start: usize, end: usize) {
-        println!("Stack Pointer is: {:?}", self.stack_pointer);
+        let stack = self.stack();
+        println!("Stack size is: {:?}", stack.len());
         println!("Registers are: ");
-        for register in self.registers[start..end].iter() {
+        let end = cmp::min(end, stack.len());
+        let start = cmp::min(start, end);
+        for register in stack.items()[start..end].iter() {
             print!("{:?} ", register);
         }
         println!("\nEnd of Registers");
     }
 }
 
+/// Returns the extra cost of accessing the account at `address` if it is cold, and marks it as warm.
+/// Accounts are always warm before Berlin.
+fn account_access_cost<H: Host>(journal: &mut Journal<H>, fork: Fork, address: Address) -> u64 {
+    if fork >= Fork::Berlin && journal.access_address(address) {
+        gas_prices::COLD_ACCOUNT_ACCESS - gas_prices::WARM_STORAGE_READ
+    } else {
+        0
+    }
+}
+
+/// Returns the address of the contract created by CREATE from `sender` with the given `nonce`,
+/// which is the last 20 bytes of the hash of RLP([sender, nonce])
+pub fn create_address(sender: Address, nonce: U256) -> Address {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(&sender);
+    stream.append(&nonce);
+    Address::from(&keccak(stream.out())[..][12..])
+}
+
+/// Returns the address of the contract created by CREATE2 from `sender` with the given `salt` and
+/// `init_code`, which is the last 20 bytes of the hash of 0xff ++ sender ++ salt ++ hash(init_code)
+pub fn create2_address(sender: Address, salt: H256, init_code: &[u8]) -> Address {
+    let mut data = Vec::with_capacity(85);
+    data.push(0xff);
+    data.extend_from_slice(&sender);
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&keccak(init_code));
+    Address::from(&keccak(&data)[..][12..])
+}
+
+/// Returns the reason a call or create made at `depth` fails before it runs, if it does. It fails if
+/// it would go past the call depth limit, or if `value` is more than the `balance` of the sender.
+fn early_failure(depth: usize, balance: U256, value: U256) -> Option<VMError> {
+    if depth > CALL_DEPTH_LIMIT {
+        Some(VMError::CallDepthExceeded)
+    } else if balance < value {
+        Some(VMError::InsufficientBalance)
+    } else {
+        None
+    }
+}
+
+/// Returns true if `value` is negative when read as a two's complement signed integer
+fn is_negative(value: M256) -> bool {
+    value.0.bit(255)
+}
+
+/// Returns the two's complement negation of `value`
+fn negate(value: M256) -> M256 {
+    !value + M256::one()
+}
+
+/// Returns the absolute value of `value` read as a two's complement signed integer
+fn abs(value: M256) -> M256 {
+    if is_negative(value) {
+        negate(value)
+    } else {
+        value
+    }
+}
+
+/// Signed integer division, rounding towards zero. Dividing by zero gives zero.
+fn sdiv(a: M256, b: M256) -> M256 {
+    if b == M256::zero() {
+        return M256::zero();
+    }
+    let quotient = abs(a) / abs(b);
+    if is_negative(a) != is_negative(b) {
+        negate(quotient)
+    } else {
+        quotient
+    }
+}
+
+/// Signed modulo, where the result takes the sign of `a`. A modulus of zero gives zero.
+fn smod(a: M256, b: M256) -> M256 {
+    if b == M256::zero() {
+        return M256::zero();
+    }
+    let remainder = abs(a) % abs(b);
+    if is_negative(a) {
+        negate(remainder)
+    } else {
+        remainder
+    }
+}
+
+/// Arithmetic right shift of `value` by `shift` bits, filling with the sign bit
+fn sar(shift: M256, value: M256) -> M256 {
+    let fill = if is_negative(value) {
+        !M256::zero()
+    } else {
+        M256::zero()
+    };
+    if shift >= M256::from(256) {
+        return fill;
+    }
+    let shift = shift.as_usize();
+    if shift == 0 {
+        return value;
+    }
+    (value >> shift) | (fill << (256 - shift))
+}
+
+/// Raises `base` to the power of `exponent`, modulo 2^256
+fn exp(base: M256, exponent: M256) -> M256 {
+    let mut result = M256::one();
+    let mut base = base;
+    let mut exponent: U256 = exponent.into();
+    while !exponent.is_zero() {
+        if exponent.bit(0) {
+            result = result * base;
+        }
+        base = base * base;
+        exponent = exponent >> 1;
+    }
+    result
+}
+
+/// Extends the sign of the lowest `b + 1` bytes of `x` through the rest of the word
+fn signextend(b: M256, x: M256) -> M256 {
+    if b >= M256::from(31) {
+        return x;
+    }
+    let bit_position = b.as_usize() * 8 + 7;
+    let mask = (M256::one() << bit_position) - M256::one();
+    if x.0.bit(bit_position) {
+        x | !mask
+    } else {
+        x & mask
+    }
+}
+
+/// Reads the 32 bytes of `data` starting at `offset`. Bytes past the end of `data` read as zero.
+fn read_padded_word(data: &[u8], offset: U256) -> M256 {
+    let mut word = [0u8; 32];
+    if offset < U256::from(data.len()) {
+        let start = offset.as_usize();
+        let end = cmp::min(start + 32, data.len());
+        word[..end - start].copy_from_slice(&data[start..end]);
+    }
+    M256::from(&word[..])
+}
+
 impl Default for VM {
     fn default() -> VM {
-        VM {
-            // In stack-based EVM implementations, the stack has a limit of 1024 items. This is why
-            // there is a limit of 1024 registers.
-            registers: [0.into(); 1024],
-            memory: Some(Box::new(SimpleMemory::new())),
-            storage: None,
-            stack_pointer: 0,
-            code: vec![],
-            pc: 0,
-            logs: vec![],
-            address: None,
-        }
+        VM::new(vec![])
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assembler::assemble;
+    use environment::{BlockContext, TransactionContext};
+    use host::Host;
+    use std::str::FromStr;
+    use tracer::StructLogger;
 
     #[test]
     fn test_create_vm() {
         let default_code = vec![0];
         let vm = VM::new(default_code);
-        assert_eq!(vm.registers.len(), 1024);
+        assert!(vm.stack().is_empty());
     }
 
     #[test]
@@ -499,7 +1168,7 @@ mod tests {
         let mut vm = VM::new(default_code);
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 10.into());
+        assert_eq!(vm.stack().items()[0], 10.into());
     }
 
     #[test]
@@ -512,7 +1181,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 20.into());
+        assert_eq!(vm.stack().items()[0], 20.into());
     }
 
     #[test]
@@ -525,7 +1194,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 0.into());
+        assert_eq!(vm.stack().items()[0], 0.into());
     }
 
     #[test]
@@ -538,7 +1207,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 100.into());
+        assert_eq!(vm.stack().items()[0], 100.into());
     }
 
     #[test]
@@ -551,7 +1220,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 1.into());
+        assert_eq!(vm.stack().items()[0], 1.into());
     }
 
     #[test]
@@ -565,7 +1234,7 @@ mod tests {
         let result = vm.execute_one();
         assert!(result.is_ok());
         vm.print_registers(0, 10);
-        assert_eq!(vm.registers[0], 1.into());
+        assert_eq!(vm.stack().items()[0], 1.into());
     }
 
     #[test]
@@ -578,7 +1247,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 1.into());
+        assert_eq!(vm.stack().items()[0], 1.into());
     }
 
     #[test]
@@ -591,7 +1260,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 1.into());
+        assert_eq!(vm.stack().items()[0], 1.into());
     }
 
     #[test]
@@ -604,7 +1273,8 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 1.into());
+        // 0xb is on top of the stack, and 0xb < 0xa is false
+        assert_eq!(vm.stack().items()[0], 0.into());
     }
 
     #[test]
@@ -617,7 +1287,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 0.into());
+        assert_eq!(vm.stack().items()[0], 1.into());
     }
 
     #[test]
@@ -630,7 +1300,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 10.into());
+        assert_eq!(vm.stack().items()[0], 10.into());
     }
 
     #[test]
@@ -643,7 +1313,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 11.into());
+        assert_eq!(vm.stack().items()[0], 11.into());
     }
 
     #[test]
@@ -656,7 +1326,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 1.into());
+        assert_eq!(vm.stack().items()[0], 1.into());
     }
 
     #[test]
@@ -669,7 +1339,10 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 13.into());
+        let result = vm.execute_one();
+        assert!(result.is_ok());
+        // (5 + 3) % 13
+        assert_eq!(vm.stack().items()[0], 8.into());
     }
 
     #[test]
@@ -682,7 +1355,10 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], 16.into());
+        let result = vm.execute_one();
+        assert!(result.is_ok());
+        // (5 * 5) % 16
+        assert_eq!(vm.stack().items()[0], 9.into());
     }
 
     #[test]
@@ -695,7 +1371,7 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        let memory = vm.memory.unwrap();
+        let memory = vm.memory();
         assert!(memory.size() > 0.into());
     }
 
@@ -709,13 +1385,13 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        let memory = vm.memory.unwrap();
+        let memory = vm.memory();
         assert!(memory.size() > 0.into());
     }
 
     #[test]
     fn test_memload_opcode() {
-        let default_code = vec![0x60, 0x05, 0x60, 0x01, 0x52, 0x01, 0x51];
+        let default_code = vec![0x60, 0x05, 0x60, 0x01, 0x52, 0x60, 0x01, 0x51];
         let mut vm = VM::new(default_code).with_simple_memory();
         let result = vm.execute_one();
         assert!(result.is_ok());
@@ -723,7 +1399,11 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], M256::from(5));
+        let result = vm.execute_one();
+        assert!(result.is_ok());
+        let result = vm.execute_one();
+        assert!(result.is_ok());
+        assert_eq!(vm.stack().items()[0], M256::from(5));
     }
 
     #[test]
@@ -736,7 +1416,8 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[2], M256::from(5));
+        assert_eq!(vm.stack().items()[2], M256::from(1));
+        assert_eq!(vm.stack().len(), 3);
     }
 
     #[test]
@@ -749,8 +1430,8 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert_eq!(vm.registers[0], M256::from(1));
-        assert_eq!(vm.registers[1], M256::from(5));
+        assert_eq!(vm.stack().items()[0], M256::from(1));
+        assert_eq!(vm.stack().items()[1], M256::from(5));
     }
 
     #[test]
@@ -767,38 +1448,1386 @@ mod tests {
         assert!(result.is_ok());
         let result = vm.execute_one();
         assert!(result.is_ok());
-        assert!(vm.logs.len() > 0);
+        assert!(!vm.host.logs().is_empty());
     }
 
     #[test]
     fn test_sload_opcode() {
-        let default_code = vec![0x60, 0x05, 0x60, 0x01, 0x54];
+        let default_code = vec![0x60, 0x00, 0x54];
         let mut vm = VM::new(default_code).with_simple_memory().with_random_address();
-        vm.storage = Some(Storage::new(vm.address.unwrap()));
-        if let Some(ref mut store) = vm.storage {
-            assert!(store.write(0.into(), 100.into()).is_ok());
-        };
+        let address = vm.root().address;
+        assert!(vm.host.set_storage(address, 0.into(), 100.into()).is_ok());
         assert!(vm.execute_one().is_ok());
         assert!(vm.execute_one().is_ok());
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(100)));
     }
 
     #[test]
     fn test_store_opcode() {
-        let default_code = vec![0x60, 0x00, 0x60, 0x05, 0x55];
+        let default_code = vec![0x60, 0x2a, 0x60, 0x05, 0x55];
         let mut vm = VM::new(default_code).with_simple_memory().with_random_address();
-        vm.storage = Some(Storage::new(vm.address.unwrap()));
-        assert!(vm.execute_one().is_ok());
-        assert!(vm.execute_one().is_ok());
+        for _ in 0..3 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.host().storage(vm.root().address, 5.into()), M256::from(0x2a));
+    }
+
+    #[test]
+    fn test_store_opcode_overwrites() {
+        let default_code = vec![0x60, 0x2a, 0x60, 0x05, 0x55, 0x60, 0x2b, 0x60, 0x05, 0x55];
+        let mut vm = VM::new(default_code).with_random_address();
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.host().storage(vm.root().address, 5.into()), M256::from(0x2b));
+    }
+
+    /// Runs `code` with slot 0 holding `original` at the start of the transaction
+    fn run_sstore(code: Vec<u8>, original: u8, fork: Fork) -> ExecutionResult {
+        let address = Address::random();
+        let state = State::new().with_storage(address, 0.into(), M256::from(original as u64));
+        let mut vm = VM::new(code).with_address(address).with_host(state).with_fork(fork);
+        vm.execute()
+    }
+
+    #[test]
+    fn test_sstore_net_gas_metering() {
+        // Cases from EIP-2200: the code, the original value, the gas used and the refund
+        let cases: [(&[u8], u8, u64, u64); 6] = [
+            (
+                &[0x60, 0x00, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0x55],
+                0,
+                1612,
+                0,
+            ),
+            (
+                &[0x60, 0x00, 0x60, 0x00, 0x55, 0x60, 0x01, 0x60, 0x00, 0x55],
+                0,
+                20812,
+                0,
+            ),
+            (
+                &[0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0x55],
+                0,
+                20812,
+                19200,
+            ),
+            (
+                &[0x60, 0x00, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0x55],
+                1,
+                5812,
+                15000,
+            ),
+            (
+                &[0x60, 0x00, 0x60, 0x00, 0x55, 0x60, 0x01, 0x60, 0x00, 0x55],
+                1,
+                5812,
+                4200,
+            ),
+            (
+                &[0x60, 0x02, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0x55],
+                1,
+                5812,
+                15000,
+            ),
+        ];
+        for &(code, original, gas_used, refund) in cases.iter() {
+            let result = run_sstore(code.to_vec(), original, Fork::Istanbul);
+            assert!(result.is_success());
+            assert_eq!(result.gas_used + result.gas_refunded, gas_used);
+            assert_eq!(result.gas_refunded, cmp::min(refund, gas_used / 2));
+        }
+    }
+
+    #[test]
+    fn test_sstore_before_istanbul() {
+        let default_code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0x55];
+        let result = run_sstore(default_code, 0, Fork::Byzantium);
+        assert_eq!(result.gas_used + result.gas_refunded, 25012);
+        assert_eq!(result.gas_refunded, 12506);
+    }
+
+    #[test]
+    fn test_sstore_refund_is_capped() {
+        let default_code = vec![0x60, 0x00, 0x60, 0x00, 0x55];
+        let result = run_sstore(default_code.clone(), 1, Fork::Istanbul);
+        assert_eq!(result.gas_used, 2503);
+        assert_eq!(result.gas_refunded, 2503);
+        // From London the refund is smaller, and capped at a fifth of the gas used
+        let result = run_sstore(default_code, 1, Fork::London);
+        assert_eq!(result.gas_used, 5006 - 1001);
+        assert_eq!(result.gas_refunded, 1001);
+    }
+
+    #[test]
+    fn test_no_refund_on_revert() {
+        let default_code = vec![0x60, 0x00, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xfd];
+        let result = run_sstore(default_code, 1, Fork::Istanbul);
+        assert_eq!(result.gas_refunded, 0);
+        assert_eq!(result.gas_used, 5012);
+    }
+
+    #[test]
+    fn test_sstore_needs_more_than_stipend() {
+        let default_code = vec![0x60, 0x01, 0x60, 0x00, 0x55];
+        let mut vm = VM::new(default_code).with_gas_limit(2306);
+        assert_eq!(vm.execute().outcome, halt(VMError::OutOfGas, 4, 0x55));
     }
 
     #[test]
     fn test_sha3_opcode() {
-        let default_code = vec![0x60, 0x05, 0x60, 0x00, 0x52, 0x20];
+        let default_code = vec![0x60, 0x05, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0x20];
+        let mut vm = VM::new(default_code).with_simple_memory().with_random_address();
+        for _ in 0..6 {
+            assert!(vm.execute_one().is_ok());
+        }
+        let mut word = [0u8; 32];
+        word[31] = 5;
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(&*keccak(&word[..]))));
+    }
+
+    /// Runs `code` until it halts and returns the value left on top of the stack
+    fn run_and_peek(code: Vec<u8>) -> M256 {
+        let mut vm = VM::new(code).with_simple_memory();
+        let result = vm.execute();
+        assert!(result.is_success());
+        vm.stack().peek(0).unwrap()
+    }
+
+    #[test]
+    fn test_signed_arithmetic() {
+        let minus_ten = negate(M256::from(10));
+        let minus_three = negate(M256::from(3));
+        assert_eq!(sdiv(minus_ten, M256::from(3)), minus_three);
+        assert_eq!(sdiv(minus_ten, minus_three), M256::from(3));
+        assert_eq!(sdiv(minus_ten, M256::zero()), M256::zero());
+        assert_eq!(smod(minus_ten, M256::from(3)), negate(M256::one()));
+        assert_eq!(smod(M256::from(10), minus_three), M256::one());
+        // The most negative number divided by -1 overflows back to itself
+        let min = M256::one() << 255;
+        assert_eq!(sdiv(min, negate(M256::one())), min);
+    }
+
+    #[test]
+    fn test_exp_opcode() {
+        // 2 ** 10, with the exponent pushed first
+        assert_eq!(run_and_peek(vec![0x60, 0x0a, 0x60, 0x02, 0x0a]), M256::from(1024));
+        // 2 ** 256 wraps around to zero
+        assert_eq!(run_and_peek(vec![0x61, 0x01, 0x00, 0x60, 0x02, 0x0a]), M256::zero());
+    }
+
+    #[test]
+    fn test_exp_gas() {
+        let default_code = vec![0x61, 0x01, 0x00, 0x60, 0x02, 0x0a];
+        let mut vm = VM::new(default_code);
+        // Two pushes, then 10 plus 50 for each of the two bytes of the exponent
+        assert_eq!(vm.execute().gas_used, 6 + 10 + 100);
+    }
+
+    #[test]
+    fn test_signextend_opcode() {
+        // Extends 0xff from one byte
+        assert_eq!(run_and_peek(vec![0x60, 0xff, 0x60, 0x00, 0x0b]), M256::max_value());
+        assert_eq!(run_and_peek(vec![0x60, 0x7f, 0x60, 0x00, 0x0b]), M256::from(0x7f));
+    }
+
+    #[test]
+    fn test_signed_comparison_opcodes() {
+        // -1 < 1, with 1 pushed first so -1 is on top
+        let code = vec![0x60, 0x01, 0x60, 0x00, 0x19, 0x12];
+        assert_eq!(run_and_peek(code), M256::one());
+        let code = vec![0x60, 0x01, 0x60, 0x00, 0x19, 0x13];
+        assert_eq!(run_and_peek(code), M256::zero());
+    }
+
+    #[test]
+    fn test_byte_opcode() {
+        // Byte 31 is the least significant byte
+        assert_eq!(run_and_peek(vec![0x61, 0xab, 0xcd, 0x60, 0x1f, 0x1a]), M256::from(0xcd));
+        assert_eq!(run_and_peek(vec![0x61, 0xab, 0xcd, 0x60, 0x1e, 0x1a]), M256::from(0xab));
+        assert_eq!(run_and_peek(vec![0x61, 0xab, 0xcd, 0x60, 0x20, 0x1a]), M256::zero());
+    }
+
+    #[test]
+    fn test_iszero_and_not_opcodes() {
+        assert_eq!(run_and_peek(vec![0x60, 0x00, 0x15]), M256::one());
+        assert_eq!(run_and_peek(vec![0x60, 0x07, 0x15]), M256::zero());
+        assert_eq!(run_and_peek(vec![0x60, 0x00, 0x19]), M256::max_value());
+    }
+
+    #[test]
+    fn test_pc_msize_and_gas_opcodes() {
+        assert_eq!(run_and_peek(vec![0x60, 0x00, 0x58]), M256::from(2));
+        // A single MSTORE8 makes one whole word of memory active
+        assert_eq!(run_and_peek(vec![0x60, 0x01, 0x60, 0x00, 0x53, 0x59]), M256::from(32));
+        let mut vm = VM::new(vec![0x5a]).with_gas_limit(100);
+        vm.execute();
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(98)));
+    }
+
+    #[test]
+    fn test_truncated_push_is_zero_padded() {
+        assert_eq!(run_and_peek(vec![0x61, 0xab]), M256::from(0xab00));
+    }
+
+    #[test]
+    fn test_jump_opcode() {
+        // Jumps over the push of 0xaa to the JUMPDEST at 6
+        let default_code = vec![0x60, 0x06, 0x56, 0x60, 0xaa, 0x00, 0x5b, 0x60, 0xbb, 0x00];
+        let mut vm = VM::new(default_code);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().items(), &[M256::from(0xbb)]);
+    }
+
+    #[test]
+    fn test_jumpi_opcode() {
+        // A non-zero condition takes the jump
+        let default_code = vec![0x60, 0x01, 0x60, 0x08, 0x57, 0x60, 0xaa, 0x00, 0x5b, 0x60, 0xbb, 0x00];
+        let mut vm = VM::new(default_code.clone());
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().items(), &[M256::from(0xbb)]);
+
+        // A zero condition falls through
+        let mut code = default_code;
+        code[1] = 0x00;
+        let mut vm = VM::new(code);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().items(), &[M256::from(0xaa)]);
+    }
+
+    /// Returns the outcome of halting with `error` at the instruction `opcode` at `pc`
+    fn halt(error: VMError, pc: usize, opcode: u8) -> Outcome {
+        Outcome::Halt(ExecutionError { error, pc, opcode })
+    }
+
+    #[test]
+    fn test_jump_to_non_jumpdest_halts() {
+        let default_code = vec![0x60, 0x03, 0x56, 0x00];
+        let mut vm = VM::new(default_code);
+        assert_eq!(vm.execute().outcome, halt(VMError::InvalidJump, 2, 0x56));
+    }
+
+    #[test]
+    fn test_jump_into_push_data_halts() {
+        // Offset 4 holds 0x5b, but as the immediate of the PUSH1 at 3
+        let default_code = vec![0x60, 0x04, 0x56, 0x60, 0x5b];
+        let mut vm = VM::new(default_code);
+        assert_eq!(vm.execute().outcome, halt(VMError::InvalidJump, 2, 0x56));
+    }
+
+    #[test]
+    fn test_jumpi_not_taken_ignores_destination() {
+        let default_code = vec![0x60, 0x00, 0x60, 0xff, 0x57];
+        let mut vm = VM::new(default_code);
+        assert_eq!(vm.execute().outcome, Outcome::Stop);
+    }
+
+    #[test]
+    fn test_stack_underflow_halts() {
+        let default_code = vec![0x60, 0x01, 0x01];
+        let mut vm = VM::new(default_code).with_gas_limit(100);
+        let result = vm.execute();
+        assert_eq!(result.outcome, halt(VMError::StackUnderflow, 2, 0x01));
+        assert_eq!(result.gas_used, 100);
+    }
+
+    #[test]
+    fn test_stack_overflow_halts() {
+        let mut default_code = vec![];
+        for _ in 0..1025 {
+            default_code.push(0x60);
+            default_code.push(0x01);
+        }
+        let mut vm = VM::new(default_code);
+        let result = vm.execute();
+        assert_eq!(result.outcome, halt(VMError::StackOverflow, 2048, 0x60));
+        assert_eq!(vm.stack().len(), 1024);
+    }
+
+    #[test]
+    fn test_logs_are_returned_on_success() {
+        let default_code = vec![0x60, 0x05, 0x60, 0x01, 0x60, 0x00, 0x60, 0x01, 0xa1, 0x00];
         let mut vm = VM::new(default_code).with_simple_memory().with_random_address();
+        let result = vm.execute();
+        assert_eq!(result.logs.len(), 1);
+        assert_eq!(result.logs[0].topics, vec![H256::from(M256::one())]);
+    }
+
+    fn test_environment() -> Environment {
+        let transaction = TransactionContext {
+            origin: Address::from(M256::from(0xaa)),
+            caller: Address::from(M256::from(0xbb)),
+            value: U256::from(1000),
+            gas_price: U256::from(20),
+        };
+        let block = BlockContext {
+            coinbase: Address::from(M256::from(0xcc)),
+            timestamp: U256::from(1_545_000_000),
+            number: U256::from(42),
+            difficulty: U256::from(131_072),
+            gas_limit: U256::from(8_000_000),
+            chain_id: U256::from(61),
+            base_fee: U256::from(7),
+        };
+        Environment::new(transaction, block)
+    }
+
+    #[test]
+    fn test_transaction_context_opcodes() {
+        let default_code = vec![0x32, 0x33, 0x34, 0x3a];
+        let mut vm = VM::new(default_code).with_environment(test_environment());
+        for _ in 0..4 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.stack().items()[0], M256::from(0xaa));
+        assert_eq!(vm.stack().items()[1], M256::from(0xbb));
+        assert_eq!(vm.stack().items()[2], M256::from(1000));
+        assert_eq!(vm.stack().items()[3], M256::from(20));
+        assert_eq!(vm.gas_used(), 8);
+    }
+
+    #[test]
+    fn test_block_context_opcodes() {
+        let default_code = vec![0x41, 0x42, 0x43, 0x44, 0x45];
+        let mut vm = VM::new(default_code).with_environment(test_environment());
+        for _ in 0..5 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.stack().items()[0], M256::from(0xcc));
+        assert_eq!(vm.stack().items()[1], M256::from(1_545_000_000));
+        assert_eq!(vm.stack().items()[2], M256::from(42));
+        assert_eq!(vm.stack().items()[3], M256::from(131_072));
+        assert_eq!(vm.stack().items()[4], M256::from(8_000_000));
+    }
+
+    #[test]
+    fn test_chainid_selfbalance_and_basefee_opcodes() {
+        let address = Address::random();
+        let state = State::new().with_account(address, 500.into(), vec![]);
+        let default_code = vec![0x46, 0x47, 0x48];
+        let mut vm = VM::new(default_code)
+            .with_address(address)
+            .with_host(state)
+            .with_environment(test_environment());
+        for _ in 0..3 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.stack().items()[0], M256::from(61));
+        assert_eq!(vm.stack().items()[1], M256::from(500));
+        assert_eq!(vm.stack().items()[2], M256::from(7));
+        assert_eq!(vm.gas_used(), 9);
+    }
+
+    #[test]
+    fn test_default_environment_is_zeroed() {
+        let default_code = vec![0x33, 0x43];
+        let mut vm = VM::new(default_code);
+        assert!(vm.execute_one().is_ok());
+        assert!(vm.execute_one().is_ok());
+        assert_eq!(vm.stack().items()[0], M256::zero());
+        assert_eq!(vm.stack().items()[1], M256::zero());
+    }
+
+    #[test]
+    fn test_calldataload_opcode() {
+        let default_code = vec![0x60, 0x00, 0x35, 0x60, 0x1f, 0x35];
+        let mut calldata = vec![0u8; 31];
+        calldata.push(0x2a);
+        calldata.push(0x01);
+        let mut vm = VM::new(default_code).with_calldata(calldata);
+        for _ in 0..4 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.stack().items()[0], M256::from(0x2a));
+        // Reading past the end of the calldata pads with zeros
+        assert_eq!(vm.stack().items()[1], M256::from(0x2a01) << 240);
+    }
+
+    #[test]
+    fn test_calldatasize_opcode() {
+        let default_code = vec![0x36];
+        let mut vm = VM::new(default_code).with_calldata(vec![1, 2, 3, 4]);
+        assert!(vm.execute_one().is_ok());
+        assert_eq!(vm.stack().items()[0], M256::from(4));
+    }
+
+    #[test]
+    fn test_calldatacopy_opcode() {
+        // Copies 4 bytes starting at calldata offset 2 to memory offset 0
+        let default_code = vec![0x60, 0x04, 0x60, 0x02, 0x60, 0x00, 0x37];
+        let mut vm = VM::new(default_code)
+            .with_simple_memory()
+            .with_calldata(vec![0xaa, 0xbb, 0xcc, 0xdd]);
+        for _ in 0..4 {
+            assert!(vm.execute_one().is_ok());
+        }
+        // Three pushes, then the static cost plus one word of memory and one word copied
+        assert_eq!(vm.gas_used(), 9 + 3 + 3 + 3);
+        let memory = vm.memory_mut();
+        assert_eq!(
+            memory.copy_from_memory(0.into(), 4.into()),
+            Ok(vec![0xcc, 0xdd, 0x00, 0x00])
+        );
+    }
+
+    #[test]
+    fn test_codesize_opcode() {
+        let default_code = vec![0x38, 0x00, 0x00];
+        let mut vm = VM::new(default_code);
+        assert!(vm.execute_one().is_ok());
+        assert_eq!(vm.stack().items()[0], M256::from(3));
+    }
+
+    #[test]
+    fn test_codecopy_opcode() {
+        let default_code = vec![0x60, 0x08, 0x60, 0x00, 0x60, 0x00, 0x39];
+        let mut vm = VM::new(default_code.clone()).with_simple_memory();
+        for _ in 0..4 {
+            assert!(vm.execute_one().is_ok());
+        }
+        let memory = vm.memory_mut();
+        let mut expected = default_code;
+        expected.push(0x00);
+        assert_eq!(memory.copy_from_memory(0.into(), 8.into()), Ok(expected));
+    }
+
+    #[test]
+    fn test_execute_stops() {
+        let default_code = vec![0x60, 0x01, 0x00, 0x60, 0x02];
+        let mut vm = VM::new(default_code);
+        let result = vm.execute();
+        assert_eq!(result.outcome, Outcome::Stop);
+        assert!(result.is_success());
+        assert_eq!(result.gas_used, 3);
+    }
+
+    #[test]
+    fn test_execute_runs_off_end_of_code() {
+        let default_code = vec![0x60, 0x01];
+        let mut vm = VM::new(default_code);
+        assert_eq!(vm.execute().outcome, Outcome::Stop);
+    }
+
+    #[test]
+    fn test_return_opcode() {
+        // Stores 0x2a at memory 0 and returns the 32 byte word
+        let default_code = vec![0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3];
+        let mut vm = VM::new(default_code).with_simple_memory();
+        let result = vm.execute();
+        let mut expected = vec![0u8; 31];
+        expected.push(0x2a);
+        assert_eq!(result.outcome, Outcome::Return(expected.clone()));
+        assert_eq!(result.output(), &expected[..]);
+        assert_eq!(result.gas_used, 18);
+    }
+
+    #[test]
+    fn test_revert_opcode() {
+        // Reverts with two bytes of memory that were never written
+        let default_code = vec![0x60, 0x02, 0x60, 0x00, 0xfd];
+        let mut vm = VM::new(default_code).with_simple_memory().with_gas_limit(100);
+        let result = vm.execute();
+        assert_eq!(result.outcome, Outcome::Revert(vec![0, 0]));
+        assert!(!result.is_success());
+        // Unlike an exceptional halt, a revert does not consume the remaining gas
+        assert_eq!(result.gas_used, 9);
+    }
+
+    #[test]
+    fn test_invalid_opcode_halts() {
+        let default_code = vec![0xfe];
+        let mut vm = VM::new(default_code).with_gas_limit(100);
+        let result = vm.execute();
+        assert_eq!(result.outcome, halt(VMError::InvalidOpcode(0xfe), 0, 0xfe));
+        assert_eq!(result.gas_used, 100);
+        assert!(result.logs.is_empty());
+    }
+
+    #[test]
+    fn test_static_gas() {
+        let default_code = vec![0x60, 0xa, 0x60, 0xa, 0x01];
+        let mut vm = VM::new(default_code).with_gas_limit(100);
         assert!(vm.execute_one().is_ok());
         assert!(vm.execute_one().is_ok());
         assert!(vm.execute_one().is_ok());
+        assert_eq!(vm.gas_used(), 9);
+        assert_eq!(vm.gas_remaining(), 91);
+    }
+
+    #[test]
+    fn test_memory_expansion_gas() {
+        let default_code = vec![0x60, 0x05, 0x60, 0x00, 0x52, 0x60, 0x05, 0x60, 0x00, 0x52];
+        let mut vm = VM::new(default_code).with_simple_memory();
+        for _ in 0..3 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.gas_used(), 12);
+        // The second store touches the same word, so it only pays the static cost
+        for _ in 0..3 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.gas_used(), 21);
+    }
+
+    #[test]
+    fn test_huge_memory_offset_runs_out_of_gas() {
+        let mut default_code = vec![0x60, 0x05, 0x7f];
+        default_code.extend_from_slice(&[0xff; 32]);
+        default_code.push(0x52);
+        let mut vm = VM::new(default_code).with_simple_memory().with_gas_limit(1000);
         assert!(vm.execute_one().is_ok());
+        assert!(vm.execute_one().is_ok());
+        assert_eq!(vm.execute_one(), Err(VMError::OutOfGas));
+        assert_eq!(vm.gas_remaining(), 0);
+    }
+
+    #[test]
+    fn test_memory_limit_halts_execution() {
+        // MSTOREs at 0, which fits in the limit, then at 0x40, which does not
+        let default_code = vec![0x60, 0x01, 0x60, 0x00, 0x52, 0x60, 0x01, 0x60, 0x40, 0x52];
+        for kind in &[MemoryKind::Simple, MemoryKind::Fast] {
+            let mut vm = VM::new(default_code.clone())
+                .with_memory_kind(*kind)
+                .with_memory_limit(64);
+            let result = vm.execute();
+            assert_eq!(result.outcome, halt(VMError::MemoryError, 9, 0x52));
+            assert_eq!(vm.memory().size(), M256::from(32));
+        }
+    }
+
+    #[test]
+    fn test_infinite_loop_runs_out_of_gas() {
+        let default_code = vec![0x5b, 0x60, 0x00, 0x56];
+        let mut vm = VM::new(default_code).with_gas_limit(1000);
+        let result = vm.execute();
+        // 83 rounds of 12 gas leave 4, which runs out at the JUMP
+        assert_eq!(result.outcome, halt(VMError::OutOfGas, 3, 0x56));
+        assert_eq!(result.gas_used, 1000);
+        assert_eq!(vm.gas_remaining(), 0);
+    }
+
+    /// Builds code that makes a call with `opcode` to `to`, passing on all of its gas. The output
+    /// of the call is copied to the first word of memory, and the success flag is left on the stack.
+    fn call_code(opcode: u8, to: Address, value: u8) -> Vec<u8> {
+        let mut code = vec![0x60, 0x20, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00];
+        if opcode == 0xf1 || opcode == 0xf2 {
+            code.extend_from_slice(&[0x60, value]);
+        }
+        code.push(0x73);
+        code.extend_from_slice(&to);
+        code.extend_from_slice(&[0x5a, opcode, 0x00]);
+        code
+    }
+
+    /// Runs `code` at `address` against `state` and returns the halted VM
+    fn run_call(code: Vec<u8>, address: Address, state: State) -> VM {
+        let mut vm = VM::new(code).with_address(address).with_host(state);
+        assert!(vm.execute().is_success());
+        vm
+    }
+
+    // Stores 0x2a at memory 0 and returns or reverts with the 32 byte word
+    const RETURN_42: [u8; 10] = [0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3];
+    const REVERT_42: [u8; 10] = [0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xfd];
+
+    #[test]
+    fn test_call_copies_output() {
+        let callee = Address::random();
+        let state = State::new().with_account(callee, U256::zero(), RETURN_42.to_vec());
+        let mut vm = run_call(call_code(0xf1, callee, 0), Address::random(), state);
+        assert_eq!(vm.stack().peek(0), Ok(M256::one()));
+        assert_eq!(vm.memory_mut().read(0.into()), Ok(M256::from(0x2a)));
+        assert_eq!(vm.frame().return_data.len(), 32);
+        assert_eq!(vm.depth(), 0);
+    }
+
+    #[test]
+    fn test_calls_share_analysis_cache() {
+        let callee = Address::random();
+        let state = State::new().with_account(callee, U256::zero(), RETURN_42.to_vec());
+        let cache = AnalysisCache::new();
+        for _ in 0..2 {
+            let mut vm = VM::new(call_code(0xf1, callee, 0))
+                .with_host(state.clone())
+                .with_analysis_cache(cache.clone());
+            assert!(vm.execute().is_success());
+            assert_eq!(vm.memory_mut().read(0.into()), Ok(M256::from(0x2a)));
+        }
+        // Only the callee is cached, once for both VMs
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_root_code_of_an_account_is_cached() {
+        let address = Address::random();
+        let state = State::new().with_account(address, U256::zero(), RETURN_42.to_vec());
+        let cache = AnalysisCache::new();
+        for _ in 0..2 {
+            let mut vm = VM::new(RETURN_42.to_vec())
+                .with_address(address)
+                .with_host(state.clone())
+                .with_analysis_cache(cache.clone());
+            assert!(vm.execute().is_success());
+        }
+        assert_eq!(cache.len(), 1);
+        // Code that is not that of the account, such as init code, is not cached
+        let mut vm = VM::new(RETURN_42.to_vec()).with_analysis_cache(cache.clone());
+        assert!(vm.execute().is_success());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_call_reverted() {
+        let callee = Address::random();
+        let state = State::new().with_account(callee, U256::zero(), REVERT_42.to_vec());
+        let mut vm = run_call(call_code(0xf1, callee, 0), Address::random(), state);
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        // The revert data is still handed back to the caller
+        assert_eq!(vm.memory_mut().read(0.into()), Ok(M256::from(0x2a)));
+    }
+
+    #[test]
+    fn test_call_transfers_value() {
+        let caller = Address::random();
+        let callee = Address::random();
+        let state = State::new()
+            .with_account(caller, 100.into(), vec![])
+            .with_account(callee, U256::zero(), vec![]);
+        let vm = run_call(call_code(0xf1, callee, 10), caller, state);
+        assert_eq!(vm.stack().peek(0), Ok(M256::one()));
+        assert_eq!(vm.host().balance(caller), U256::from(90));
+        assert_eq!(vm.host().balance(callee), U256::from(10));
+    }
+
+    #[test]
+    fn test_call_without_funds_fails() {
+        let caller = Address::random();
+        let callee = Address::random();
+        let state = State::new().with_account(caller, 5.into(), vec![]);
+        let vm = run_call(call_code(0xf1, callee, 10), caller, state);
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        assert_eq!(vm.host().balance(caller), U256::from(5));
+        assert_eq!(vm.host().balance(callee), U256::zero());
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        let callee = Address::random();
+        let state = State::new().with_account(callee, U256::zero(), RETURN_42.to_vec());
+        let mut vm = VM::new(vec![]).with_host(state);
+        for _ in 0..CALL_DEPTH_LIMIT {
+            vm.frames.push(Frame::new(FrameKind::Root, vec![], DEFAULT_GAS_LIMIT));
+        }
+        let code = call_code(0xf1, callee, 0);
+        *vm.frame_mut() = Frame::new(FrameKind::Root, code, DEFAULT_GAS_LIMIT);
+        // Five pushes, PUSH20, GAS and the CALL itself
+        for _ in 0..8 {
+            assert!(vm.execute_one().is_ok());
+        }
+        assert_eq!(vm.depth(), CALL_DEPTH_LIMIT);
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+    }
+
+    #[test]
+    fn test_early_failures_keep_gas() {
+        let failure = early_failure(CALL_DEPTH_LIMIT + 1, U256::one(), U256::zero());
+        assert_eq!(failure, Some(VMError::CallDepthExceeded));
+        let failure = early_failure(1, U256::one(), U256::from(2));
+        assert_eq!(failure, Some(VMError::InsufficientBalance));
+        assert!(!failure.unwrap().consumes_all_gas());
+        assert_eq!(early_failure(1, U256::one(), U256::one()), None);
+    }
+
+    #[test]
+    fn test_call_without_balance_returns_stipend() {
+        let callee = Address::random();
+        let state = State::new().with_account(callee, U256::zero(), RETURN_42.to_vec());
+        let mut vm = VM::new(call_code(0xf1, callee, 1))
+            .with_gas_limit(100_000)
+            .with_host(state);
+        let result = vm.execute();
+        assert!(result.is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        // Five pushes, PUSH20 and GAS, then the cold access, the value transfer and one word of
+        // memory, less the stipend the failed call hands back
+        assert_eq!(result.gas_used, 20 + 2600 + 9000 + 3 - 2300);
+    }
+
+    #[test]
+    fn test_call_forwards_all_but_one_64th() {
+        let callee = Address::random();
+        // Returns the gas it has left after GAS itself
+        let code = vec![0x5a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3];
+        let state = State::new().with_account(callee, U256::zero(), code);
+        let mut vm = VM::new(call_code(0xfa, callee, 0))
+            .with_gas_limit(100_000)
+            .with_host(state);
+        assert!(vm.execute().is_success());
+        // Four pushes, PUSH20 and GAS, then the cold access to the callee and one word of memory
+        let available = 100_000 - 17 - 2600 - 3;
+        assert_eq!(
+            vm.memory_mut().read(0.into()),
+            Ok(M256::from(available - available / 64 - 2))
+        );
+    }
+
+    #[test]
+    fn test_call_precompile() {
+        let mut vm = VM::new(call_code(0xfa, Address::from(2), 0));
+        let result = vm.execute();
+        assert_eq!(vm.stack().peek(0), Ok(M256::one()));
+        let hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(
+            vm.memory_mut().read(0.into()),
+            Ok(M256::from(U256::from_str(hash).unwrap()))
+        );
+        // Five pushes and GAS, a call to a warm account, a word of memory and SHA256 of nothing
+        assert_eq!(result.gas_used, 17 + 100 + 3 + 60);
+    }
+
+    #[test]
+    fn test_failed_precompile_consumes_its_gas() {
+        // Writes a point that is not on the curve, then passes it to the bn128 addition with 1000 gas
+        let code = vec![
+            0x60, 0x01, 0x60, 0x00, 0x53, 0x60, 0x20, 0x60, 0x40, 0x60, 0x40, 0x60, 0x00, 0x60, 0x06, 0x61, 0x03, 0xe8,
+            0xfa, 0x00,
+        ];
+        let mut vm = VM::new(code);
+        let result = vm.execute();
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        assert_eq!(result.gas_used, 12 + 18 + 100 + 6 + 1000);
+    }
+
+    #[test]
+    fn test_precompile_missing_before_its_fork() {
+        // Before Istanbul there is no contract at address 9, so calling it succeeds with no output
+        let mut vm = VM::new(call_code(0xfa, Address::from(9), 0)).with_fork(Fork::Constantinople);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::one()));
+        assert!(vm.frame().return_data.is_empty());
+    }
+
+    #[test]
+    fn test_assembled_loop() {
+        // Adds 3 to the counter five times, then returns it
+        let code = assemble(
+            "
+                PUSH1 5
+                PUSH1 0
+            loop:
+                JUMPDEST
+                PUSH1 3
+                ADD
+                SWAP1
+                PUSH1 1
+                SWAP1
+                SUB
+                SWAP1
+                DUP2
+                PUSH loop
+                JUMPI
+                PUSH1 0
+                MSTORE
+                PUSH1 0x20
+                PUSH1 0
+                RETURN
+            ",
+        )
+        .unwrap();
+        let result = VM::new(code).execute();
+        assert_eq!(result.output(), &H256::from(15)[..]);
+    }
+
+    #[test]
+    fn test_fast_memory_matches_simple_memory() {
+        // Copies calldata and code into memory, overwrites a byte, then returns all of it along
+        // with its hash
+        let code = assemble(
+            "
+                PUSH1 64
+                PUSH1 0
+                PUSH1 0
+                CALLDATACOPY
+                PUSH1 10
+                PUSH1 2
+                PUSH1 100
+                CODECOPY
+                PUSH1 0xaa
+                PUSH2 300
+                MSTORE8
+                PUSH2 320
+                PUSH1 0
+                SHA3
+                PUSH2 320
+                MSTORE
+                PUSH2 352
+                PUSH1 0
+                RETURN
+            ",
+        )
+        .unwrap();
+        let calldata: Vec<u8> = (1..=48).collect();
+        let mut simple = VM::new(code.clone())
+            .with_calldata(calldata.clone())
+            .with_simple_memory();
+        let mut fast = VM::new(code).with_calldata(calldata).with_fast_memory();
+        let expected = simple.execute();
+        let result = fast.execute();
+        assert!(result.is_success());
+        assert_eq!(result, expected);
+        assert_eq!(&result.output()[..48], &(1..=48).collect::<Vec<u8>>()[..]);
+        assert_eq!(fast.active_memory(), simple.active_memory());
+    }
+
+    #[test]
+    fn test_tracer_sees_every_step() {
+        let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x56];
+        let mut logger = StructLogger::new();
+        let result = VM::new(code).execute_with_tracer(&mut logger);
+        let logs = logger.logs();
+        let ops: Vec<&str> = logs.iter().map(|log| log.op.as_str()).collect();
+        assert_eq!(ops, vec!["PUSH1", "PUSH1", "SSTORE", "PUSH1", "JUMP"]);
+        assert_eq!(logs[2].stack.len(), 2);
+        assert_eq!(logs[2].gas, logs[0].gas - 6);
+        assert_eq!(logs[3].storage.len(), 1);
+        assert_eq!(logs[4].error, Some(VMError::InvalidJump.to_string()));
+        // Like geth, the failing JUMP reports its own cost, though the call goes on to use all its gas
+        assert_eq!(logs[4].gas_cost, 8);
+        assert_eq!(result.gas_used, DEFAULT_GAS_LIMIT);
+        assert!(logs.iter().all(|log| log.depth == 1));
+    }
+
+    /// Builds code that destroys the contract running it in favour of `beneficiary`
+    fn selfdestruct_code(beneficiary: Address) -> Vec<u8> {
+        let mut code = vec![0x73];
+        code.extend_from_slice(&beneficiary);
+        code.push(0xff);
+        code
+    }
+
+    /// Runs SELFDESTRUCT from a contract holding 100 wei, against `state`
+    fn run_selfdestruct(beneficiary: Address, state: State, fork: Fork) -> (VM, Address) {
+        let address = Address::random();
+        let state = state.with_account(address, 100.into(), selfdestruct_code(beneficiary));
+        let mut vm = VM::new(selfdestruct_code(beneficiary))
+            .with_fork(fork)
+            .with_address(address)
+            .with_host(state);
+        assert!(vm.execute().is_success());
+        (vm, address)
+    }
+
+    #[test]
+    fn test_selfdestruct() {
+        let beneficiary = Address::random();
+        let (vm, address) = run_selfdestruct(beneficiary, State::new(), Fork::London);
+        assert_eq!(vm.host().balance(beneficiary), U256::from(100));
+        assert!(!vm.host().exists(address));
+        // PUSH20, SELFDESTRUCT, a cold beneficiary that has to be created, and no refund
+        let result = vm.result();
+        assert_eq!(result.gas_used, 3 + 5000 + 2600 + 25000);
+        assert_eq!(result.gas_refunded, 0);
+    }
+
+    #[test]
+    fn test_selfdestruct_to_itself_burns_balance() {
+        let address = Address::random();
+        let state = State::new().with_account(address, 100.into(), vec![]);
+        let mut vm = VM::new(selfdestruct_code(address))
+            .with_address(address)
+            .with_host(state);
+        assert!(vm.execute().is_success());
+        assert!(!vm.host().exists(address));
+        assert_eq!(vm.host().balance(address), U256::zero());
+    }
+
+    #[test]
+    fn test_selfdestruct_gas_per_fork() {
+        let beneficiary = Address::random();
+        let (vm, _) = run_selfdestruct(beneficiary, State::new(), Fork::Frontier);
+        // Free on Frontier, but the refund is still capped at half of the gas used
+        assert_eq!(vm.result().gas_used, 2);
+        let (vm, _) = run_selfdestruct(beneficiary, State::new(), Fork::TangerineWhistle);
+        assert_eq!(vm.result().gas_used, (3 + 5000 + 25000) / 2 + 1);
+        let existing = State::new().with_account(beneficiary, 1.into(), vec![]);
+        let (vm, _) = run_selfdestruct(beneficiary, existing, Fork::Istanbul);
+        assert_eq!(vm.result().gas_used, (3 + 5000) / 2 + 1);
+    }
+
+    #[test]
+    fn test_selfdestruct_without_balance_creates_nothing() {
+        let beneficiary = Address::random();
+        let address = Address::random();
+        let state = State::new().with_account(address, U256::zero(), vec![]);
+        let mut vm = VM::new(selfdestruct_code(beneficiary))
+            .with_address(address)
+            .with_host(state);
+        let result = vm.execute();
+        assert_eq!(result.gas_used, 3 + 5000 + 2600);
+        assert!(!vm.host().exists(beneficiary));
+    }
+
+    #[test]
+    fn test_selfdestruct_forbidden_in_staticcall() {
+        let callee = Address::random();
+        let state = State::new().with_account(callee, 100.into(), selfdestruct_code(Address::random()));
+        let vm = run_call(call_code(0xfa, callee, 0), Address::random(), state);
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        assert!(vm.host().exists(callee));
+        assert_eq!(vm.host().balance(callee), U256::from(100));
+    }
+
+    #[test]
+    fn test_selfdestruct_undone_by_revert() {
+        let callee = Address::random();
+        let beneficiary = Address::random();
+        let state = State::new().with_account(callee, 100.into(), selfdestruct_code(beneficiary));
+        // Calls the contract, which destroys itself, then reverts
+        let mut code = call_code(0xf1, callee, 0);
+        code.pop();
+        code.extend_from_slice(&[0x60, 0x00, 0x80, 0xfd]);
+        let mut vm = VM::new(code).with_host(state);
+        assert_eq!(vm.execute().outcome, Outcome::Revert(vec![]));
+        assert!(vm.host().exists(callee));
+        assert_eq!(vm.host().balance(callee), U256::from(100));
+        assert!(!vm.host().exists(beneficiary));
+    }
+
+    #[test]
+    fn test_staticcall_forbids_sstore() {
+        let callee = Address::random();
+        let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+        let state = State::new().with_account(callee, U256::zero(), code);
+        let vm = run_call(call_code(0xfa, callee, 0), Address::random(), state);
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        assert_eq!(vm.host().storage(callee, 0.into()), M256::zero());
+    }
+
+    #[test]
+    fn test_delegatecall_runs_in_caller_context() {
+        let caller = Address::random();
+        let callee = Address::random();
+        // Stores CALLER in slot 0
+        let code = vec![0x33, 0x60, 0x00, 0x55, 0x00];
+        let state = State::new().with_account(callee, U256::zero(), code);
+        let mut vm = VM::new(call_code(0xf4, callee, 0))
+            .with_address(caller)
+            .with_environment(test_environment())
+            .with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::one()));
+        assert_eq!(vm.host().storage(caller, 0.into()), M256::from(0xbb));
+        assert_eq!(vm.host().storage(callee, 0.into()), M256::zero());
+    }
+
+    #[test]
+    fn test_returndatacopy_out_of_bounds() {
+        let default_code = vec![0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0x3e];
+        let mut vm = VM::new(default_code).with_gas_limit(1000);
+        let result = vm.execute();
+        assert_eq!(result.outcome, halt(VMError::ReturnDataOutOfBounds, 6, 0x3e));
+        assert_eq!(result.gas_used, 1000);
+    }
+
+    fn address_from_hex(hex: &str) -> Address {
+        hex.parse().unwrap()
+    }
+
+    #[test]
+    fn test_create_address() {
+        let sender = address_from_hex("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0");
+        assert_eq!(
+            create_address(sender, U256::zero()),
+            address_from_hex("cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d")
+        );
+        assert_eq!(
+            create_address(sender, U256::one()),
+            address_from_hex("343c43a37d37dff08ae8c4a11544c718abb4fcf8")
+        );
+    }
+
+    #[test]
+    fn test_create2_address() {
+        // Examples from EIP-1014
+        assert_eq!(
+            create2_address(Address::default(), H256::default(), &[0x00]),
+            address_from_hex("4d1a2e2bb4f88f0250f26ffff098b0b30b26bf38")
+        );
+        let sender = address_from_hex("deadbeef00000000000000000000000000000000");
+        assert_eq!(
+            create2_address(sender, H256::default(), &[0x00]),
+            address_from_hex("b928f69bb1d91cd65274e3c79d8986362984fda3")
+        );
+    }
+
+    // Init code that deploys the single byte 0x2a
+    const DEPLOY_2A: [u8; 10] = [0x60, 0x2a, 0x60, 0x00, 0x53, 0x60, 0x01, 0x60, 0x00, 0xf3];
+
+    /// Builds code that stores `init_code` in memory and runs it with CREATE, or with CREATE2 if a
+    /// salt is given. The result is left on the stack.
+    fn create_code(init_code: &[u8], salt: Option<u8>) -> Vec<u8> {
+        let len = init_code.len() as u8;
+        let mut code = vec![0x60 + len - 1];
+        code.extend_from_slice(init_code);
+        code.extend_from_slice(&[0x60, 0x00, 0x52]);
+        if let Some(salt) = salt {
+            code.extend_from_slice(&[0x60, salt]);
+        }
+        code.extend_from_slice(&[0x60, len, 0x60, 32 - len, 0x60, 0x00]);
+        code.push(if salt.is_some() { 0xf5 } else { 0xf0 });
+        code.push(0x00);
+        code
+    }
+
+    #[test]
+    fn test_create_opcode() {
+        let creator = Address::random();
+        let vm = run_call(create_code(&DEPLOY_2A, None), creator, State::new());
+        let created = create_address(creator, U256::zero());
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(created)));
+        assert_eq!(vm.host().code(created), &[0x2a]);
+        assert_eq!(vm.host().nonce(created), U256::one());
+        assert_eq!(vm.host().nonce(creator), U256::one());
+    }
+
+    #[test]
+    fn test_create2_opcode() {
+        let creator = Address::random();
+        let vm = run_call(create_code(&DEPLOY_2A, Some(7)), creator, State::new());
+        let created = create2_address(creator, H256::from(M256::from(7)), &DEPLOY_2A);
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(created)));
+        assert_eq!(vm.host().code(created), &[0x2a]);
+    }
+
+    #[test]
+    fn test_create_collision_fails() {
+        let creator = Address::random();
+        let created = create_address(creator, U256::zero());
+        let state = State::new().with_account(created, U256::zero(), vec![0x00]);
+        let vm = run_call(create_code(&DEPLOY_2A, None), creator, state);
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        assert_eq!(vm.host().code(created), &[0x00]);
+    }
+
+    #[test]
+    fn test_create_reverted() {
+        let creator = Address::random();
+        let vm = run_call(create_code(&REVERT_42, None), creator, State::new());
+        let created = create_address(creator, U256::zero());
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        assert!(vm.host().code(created).is_empty());
+        assert_eq!(vm.frame().return_data.len(), 32);
+        // The account is removed again, but the creator keeps its incremented nonce
+        assert!(!vm.host().exists(created));
+        assert_eq!(vm.host().nonce(creator), U256::one());
+    }
+
+    #[test]
+    fn test_create_without_gas_for_deposit() {
+        let creator = Address::random();
+        // Deploys 32 bytes, which needs 6400 gas for the deposit alone
+        let mut vm = VM::new(create_code(&RETURN_42, None))
+            .with_address(creator)
+            .with_gas_limit(38_000);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+        assert!(vm.host().code(create_address(creator, U256::zero())).is_empty());
+    }
+
+    /// Builds code that pushes `address` and runs `opcode` on it
+    fn address_code(opcode: u8, address: Address) -> Vec<u8> {
+        let mut code = vec![0x73];
+        code.extend_from_slice(&address);
+        code.push(opcode);
+        code
+    }
+
+    #[test]
+    fn test_balance_opcode() {
+        let address = Address::random();
+        let state = State::new().with_account(address, 1234.into(), vec![]);
+        let mut vm = VM::new(address_code(0x31, address)).with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(1234)));
+    }
+
+    #[test]
+    fn test_extcodesize_opcode() {
+        let address = Address::random();
+        let state = State::new().with_account(address, U256::zero(), RETURN_42.to_vec());
+        let mut vm = VM::new(address_code(0x3b, address)).with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(RETURN_42.len())));
+    }
+
+    #[test]
+    fn test_extcodecopy_opcode() {
+        let address = Address::random();
+        let state = State::new().with_account(address, U256::zero(), RETURN_42.to_vec());
+        // Copies 4 bytes of code starting at offset 8 to memory offset 0
+        let mut code = vec![0x60, 0x04, 0x60, 0x08, 0x60, 0x00];
+        code.extend_from_slice(&address_code(0x3c, address));
+        let mut vm = VM::new(code).with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(
+            vm.memory_mut().copy_from_memory(0.into(), 4.into()),
+            Ok(vec![0x00, 0xf3, 0x00, 0x00])
+        );
+    }
+
+    #[test]
+    fn test_blockhash_opcode() {
+        let hash = H256::from(M256::from(0xabcdef));
+        let state = State::new()
+            .with_block_hash(100.into(), hash)
+            .with_block_hash(300.into(), hash);
+        let mut environment = test_environment();
+        environment.block.number = 357.into();
+        // BLOCKHASH of 100, which is too old, and of 300
+        let code = vec![0x60, 0x64, 0x40, 0x61, 0x01, 0x2c, 0x40];
+        let mut vm = VM::new(code).with_environment(environment).with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().items(), &[M256::zero(), M256::from(0xabcdef)]);
+    }
+
+    #[test]
+    fn test_logs_are_sent_to_host_on_success() {
+        let default_code = vec![0x60, 0x00, 0x60, 0x00, 0xa0, 0x00];
+        let mut vm = VM::new(default_code);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.host().logs().len(), 1);
+
+        let default_code = vec![0x60, 0x00, 0x60, 0x00, 0xa0, 0x60, 0x00, 0x60, 0x00, 0xfd];
+        let mut vm = VM::new(default_code);
+        assert!(!vm.execute().is_success());
+        assert!(vm.host().logs().is_empty());
+    }
+
+    #[test]
+    fn test_shl_opcode() {
+        assert_eq!(run_and_peek(vec![0x60, 0x01, 0x60, 0x01, 0x1b]), M256::from(2));
+        assert_eq!(run_and_peek(vec![0x60, 0x01, 0x60, 0xff, 0x1b]), M256::one() << 255);
+        assert_eq!(run_and_peek(vec![0x60, 0x01, 0x61, 0x01, 0x00, 0x1b]), M256::zero());
+    }
+
+    #[test]
+    fn test_shr_opcode() {
+        assert_eq!(run_and_peek(vec![0x60, 0x04, 0x60, 0x01, 0x1c]), M256::from(2));
+        let mut code = vec![0x7f, 0x80];
+        code.extend_from_slice(&[0; 31]);
+        code.extend_from_slice(&[0x60, 0xff, 0x1c]);
+        assert_eq!(run_and_peek(code), M256::one());
+    }
+
+    #[test]
+    fn test_sar_opcode() {
+        // Examples from EIP-145
+        let min = M256::one() << 255;
+        assert_eq!(sar(M256::one(), min), M256::from(0xc0) << 248);
+        assert_eq!(sar(M256::from(0xff), min), !M256::zero());
+        assert_eq!(sar(M256::from(0x100), min), !M256::zero());
+        assert_eq!(sar(M256::from(0x100), M256::max_value() >> 1), M256::zero());
+        assert_eq!(sar(M256::from(0xfe), M256::one() << 254), M256::one());
+        assert_eq!(sar(M256::zero(), !M256::zero()), !M256::zero());
+        assert_eq!(run_and_peek(vec![0x60, 0x10, 0x60, 0x02, 0x1d]), M256::from(4));
+    }
+
+    #[test]
+    fn test_extcodehash_opcode() {
+        let address = Address::random();
+        let state = State::new().with_account(address, U256::zero(), RETURN_42.to_vec());
+        let mut vm = VM::new(address_code(0x3f, address)).with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(&*keccak(&RETURN_42[..]))));
+
+        let mut vm = VM::new(address_code(0x3f, Address::random()));
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+
+        // An account without balance, nonce or code is empty even though it exists
+        let state = State::new().with_account(address, U256::zero(), vec![]);
+        let mut vm = VM::new(address_code(0x3f, address)).with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+    }
+
+    #[test]
+    fn test_opcode_invalid_before_its_fork() {
+        let default_code = vec![0x60, 0x00, 0x60, 0x00, 0xfd];
+        let mut vm = VM::new(default_code.clone()).with_fork(Fork::Homestead);
+        assert_eq!(vm.execute().outcome, halt(VMError::InvalidOpcode(0xfd), 4, 0xfd));
+        let mut vm = VM::new(default_code).with_fork(Fork::Byzantium);
+        assert_eq!(vm.execute().outcome, Outcome::Revert(vec![]));
     }
 
+    #[test]
+    fn test_fork_follows_block_number() {
+        // The environment is that of block 42
+        let default_code = vec![0x60, 0x00, 0x60, 0x00, 0xfd];
+        let schedule = ForkSchedule::new().with_fork(Fork::Byzantium, 42.into());
+        let mut vm = VM::new(default_code.clone())
+            .with_fork_schedule(schedule)
+            .with_environment(test_environment());
+        assert_eq!(vm.execute().outcome, Outcome::Revert(vec![]));
+        let schedule = ForkSchedule::new().with_fork(Fork::Byzantium, 43.into());
+        let mut vm = VM::new(default_code.clone())
+            .with_environment(test_environment())
+            .with_fork_schedule(schedule.clone());
+        assert_eq!(vm.execute().outcome, halt(VMError::InvalidOpcode(0xfd), 4, 0xfd));
+        // A fork that is set explicitly wins over the schedule
+        let mut vm = VM::new(default_code)
+            .with_fork(Fork::Byzantium)
+            .with_fork_schedule(schedule)
+            .with_environment(test_environment());
+        assert_eq!(vm.execute().outcome, Outcome::Revert(vec![]));
+    }
+
+    #[test]
+    fn test_cold_and_warm_account_access() {
+        let address = Address::random();
+        let mut default_code = address_code(0x31, address);
+        default_code.extend_from_slice(&address_code(0x31, address));
+        let mut vm = VM::new(default_code.clone());
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.gas_used(), 3 + 2600 + 3 + 100);
+        let mut vm = VM::new(default_code).with_fork(Fork::Istanbul);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.gas_used(), 3 + 700 + 3 + 700);
+    }
+
+    #[test]
+    fn test_cold_and_warm_sload() {
+        let default_code = vec![0x60, 0x01, 0x54, 0x60, 0x01, 0x54];
+        let mut vm = VM::new(default_code.clone());
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.gas_used(), 3 + 2100 + 3 + 100);
+        let mut vm = VM::new(default_code).with_fork(Fork::Frontier);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.gas_used(), 3 + 50 + 3 + 50);
+    }
+
+    #[test]
+    fn test_call_before_tangerine_whistle_needs_requested_gas() {
+        let callee = Address::random();
+        let state = State::new().with_account(callee, U256::zero(), RETURN_42.to_vec());
+        // Requests all the gas left, which no longer covers the call itself
+        let mut vm = VM::new(call_code(0xf1, callee, 0))
+            .with_fork(Fork::Homestead)
+            .with_host(state.clone());
+        assert_eq!(vm.execute().outcome, halt(VMError::OutOfGas, 32, 0xf1));
+        let mut vm = VM::new(call_code(0xf1, callee, 0))
+            .with_fork(Fork::TangerineWhistle)
+            .with_host(state);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.stack().peek(0), Ok(M256::one()));
+    }
+
+    #[test]
+    fn test_call_creates_account_before_spurious_dragon() {
+        let to = Address::random();
+        let mut vm = VM::new(call_code(0xf1, to, 0)).with_fork(Fork::TangerineWhistle);
+        assert!(vm.execute().is_success());
+        assert!(vm.host().exists(to));
+        assert_eq!(vm.gas_used(), 6 * 3 + 2 + 3 + 700 + 25000);
+        let mut vm = VM::new(call_code(0xf1, to, 0)).with_fork(Fork::SpuriousDragon);
+        assert!(vm.execute().is_success());
+        assert!(!vm.host().exists(to));
+    }
+
+    #[test]
+    fn test_touched_empty_accounts_are_cleared_from_spurious_dragon() {
+        let to = Address::random();
+        let state = State::new().with_account(to, U256::zero(), vec![]);
+        let mut vm = VM::new(call_code(0xf1, to, 0))
+            .with_host(state.clone())
+            .with_fork(Fork::TangerineWhistle);
+        assert!(vm.execute().is_success());
+        assert!(vm.host().exists(to));
+        let mut vm = VM::new(call_code(0xf1, to, 0))
+            .with_host(state)
+            .with_fork(Fork::SpuriousDragon);
+        assert!(vm.execute().is_success());
+        assert!(!vm.host().exists(to));
+    }
+
+    #[test]
+    fn test_create_without_gas_for_deposit_on_frontier() {
+        let creator = Address::random();
+        let mut vm = VM::new(create_code(&RETURN_42, None))
+            .with_address(creator)
+            .with_gas_limit(38_000)
+            .with_fork(Fork::Frontier);
+        assert!(vm.execute().is_success());
+        // The contract is still created, only without code
+        let created = create_address(creator, U256::zero());
+        assert_eq!(vm.stack().peek(0), Ok(M256::from(created)));
+        assert!(vm.host().code(created).is_empty());
+        assert_eq!(vm.host().nonce(created), U256::zero());
+    }
+
+    #[test]
+    fn test_create_rejects_ef_code_from_london() {
+        // Deploys the single byte 0xef
+        let init_code = [0x60, 0xef, 0x60, 0x00, 0x53, 0x60, 0x01, 0x60, 0x00, 0xf3];
+        let creator = Address::random();
+        let vm = run_call(create_code(&init_code, None), creator, State::new());
+        assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+
+        let mut vm = VM::new(create_code(&init_code, None))
+            .with_address(creator)
+            .with_fork(Fork::Berlin);
+        assert!(vm.execute().is_success());
+        assert_eq!(vm.host().code(create_address(creator, U256::zero())), &[0xef]);
+    }
+
+    // Stores 1 in slot 0 and emits a log, then reverts or hits an invalid opcode
+    const WRITE_THEN_REVERT: [u8; 15] = [
+        0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xa0, 0x60, 0x00, 0x60, 0x00, 0xfd,
+    ];
+    const WRITE_THEN_FAIL: [u8; 11] = [0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xa0, 0xfe];
+
+    #[test]
+    fn test_failed_call_is_rolled_back() {
+        for callee_code in [&WRITE_THEN_REVERT[..], &WRITE_THEN_FAIL[..]].iter() {
+            let caller = Address::random();
+            let callee = Address::random();
+            let state = State::new().with_account(caller, 100.into(), vec![]).with_account(
+                callee,
+                U256::zero(),
+                callee_code.to_vec(),
+            );
+            let mut vm = VM::new(call_code(0xf1, callee, 10))
+                .with_address(caller)
+                .with_host(state);
+            let result = vm.execute();
+            assert!(result.is_success());
+            assert!(result.logs.is_empty());
+            assert_eq!(vm.stack().peek(0), Ok(M256::zero()));
+            assert_eq!(vm.host().storage(callee, 0.into()), M256::zero());
+            assert_eq!(vm.host().balance(caller), U256::from(100));
+            assert_eq!(vm.host().balance(callee), U256::zero());
+            assert!(vm.host().logs().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_root_revert_is_rolled_back() {
+        let address = Address::random();
+        let mut vm = VM::new(WRITE_THEN_REVERT.to_vec()).with_address(address);
+        let result = vm.execute();
+        assert_eq!(result.outcome, Outcome::Revert(vec![]));
+        assert_eq!(vm.host().storage(address, 0.into()), M256::zero());
+        assert!(!vm.host().exists(address));
+    }
+
+    #[test]
+    fn test_refund_of_failed_call_is_dropped() {
+        let callee = Address::random();
+        // Clears slot 0 and reverts
+        let code = vec![0x60, 0x00, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xfd];
+        let state = State::new()
+            .with_account(callee, U256::zero(), code)
+            .with_storage(callee, 0.into(), M256::one());
+        let vm = run_call(call_code(0xf1, callee, 0), Address::random(), state);
+        assert_eq!(vm.gas_refund(), 0);
+        assert_eq!(vm.host().storage(callee, 0.into()), M256::one());
+    }
 }
@@ -0,0 +1,474 @@
+//! Runs the VMTests and GeneralStateTests of the Ethereum consensus tests
+//! (https://github.com/ethereum/tests) through fvm, and prints how many pass for each fork.
+//!
+//! The fixtures kept under `tests/fixtures` are not copies of the official ones, but small tests
+//! written by hand in the format of those suites. Their expectations do not come from fvm: the
+//! state roots and logs hashes of the GeneralStateTests pass `revme statetest` (revm 43.0.3), and
+//! the gas and storage of the VMTests follow from the Homestead prices and the instructions they
+//! run. Every one of them must pass. Conformance is measured by running a checkout of the suites,
+//! by pointing `ETHEREUM_TESTS` at it:
+//!
+//! ```text
+//! ETHEREUM_TESTS=/path/to/ethereum/tests cargo test -p fvm --test ethereum_tests -- --nocapture
+//! ```
+//!
+//! Failures in a full checkout are reported in the summary rather than failing the run, as fvm does
+//! not support everything the suites cover. Tests that need something the runners do not do, such
+//! as contract creation transactions, are counted as skipped.
+
+extern crate bigint;
+extern crate fvm;
+extern crate keccak_hash;
+extern crate rlp;
+extern crate rustc_hex;
+extern crate secp256k1;
+extern crate serde_json;
+extern crate trie;
+extern crate world;
+
+use bigint::{Address, H256, M256, U256};
+use fvm::environment::{BlockContext, Environment, TransactionContext};
+use fvm::eth_log::Log;
+use fvm::fork::Fork;
+use fvm::host::Host;
+use fvm::outcome::ExecutionResult;
+use fvm::precompiles;
+use fvm::vm::VM;
+use rustc_hex::FromHex;
+use secp256k1::{PublicKey, SecretKey, SECP256K1};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use trie::MemoryTrieMut;
+use world::state::WorldState;
+
+const FIXTURES: &str = "tests/fixtures";
+
+// VMTests do not name a fork. They were written for the rules of Homestead.
+const VM_TESTS_FORK: Fork = Fork::Homestead;
+
+/// How a single test, or a single fork of a state test, went
+enum Verdict {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+/// Counts of passes, failures and skips per fork, along with why tests failed or were skipped
+#[derive(Default)]
+struct Summary {
+    counts: BTreeMap<String, (usize, usize, usize)>,
+    failures: Vec<String>,
+    skips: BTreeMap<String, usize>,
+}
+
+impl Summary {
+    fn record(&mut self, fork: &str, name: &str, verdict: Verdict) {
+        let counts = self.counts.entry(fork.to_string()).or_insert((0, 0, 0));
+        match verdict {
+            Verdict::Pass => counts.0 += 1,
+            Verdict::Fail(reason) => {
+                counts.1 += 1;
+                self.failures.push(format!("{} ({}): {}", name, fork, reason));
+            }
+            Verdict::Skip(reason) => {
+                counts.2 += 1;
+                *self.skips.entry(reason).or_default() += 1;
+            }
+        }
+    }
+
+    fn print(&self, suite: &str) {
+        for failure in &self.failures {
+            println!("FAIL {}", failure);
+        }
+        for (reason, count) in &self.skips {
+            println!("SKIP {} tests: {}", count, reason);
+        }
+        println!("{}", suite);
+        println!("  {:<16} {:>8} {:>8} {:>8}", "fork", "passed", "failed", "skipped");
+        for (fork, &(passed, failed, skipped)) in &self.counts {
+            println!("  {:<16} {:>8} {:>8} {:>8}", fork, passed, failed, skipped);
+        }
+    }
+}
+
+#[test]
+fn vm_tests() {
+    run_suite("VMTests", |name, test, summary| {
+        summary.record(VM_TESTS_FORK.name(), name, run_vm_test(test))
+    });
+}
+
+#[test]
+fn general_state_tests() {
+    run_suite("GeneralStateTests", |name, test, summary| {
+        for (fork_name, entries) in object(&test["post"]) {
+            let fork = match Fork::from_str(fork_name) {
+                Ok(fork) => fork,
+                Err(e) => {
+                    summary.record(fork_name, name, Verdict::Skip(e));
+                    continue;
+                }
+            };
+            for entry in entries.as_array().expect("post entries are a list") {
+                summary.record(fork_name, name, run_state_test(test, fork, entry));
+            }
+        }
+    });
+}
+
+/// Runs every test in every JSON file under the directory of `suite`, then prints the summary. The
+/// vendored fixtures must all pass, none of them skipped.
+fn run_suite<F>(suite: &str, mut run: F)
+where
+    F: FnMut(&str, &Value, &mut Summary),
+{
+    let (root, vendored) = match env::var("ETHEREUM_TESTS") {
+        Ok(path) => (PathBuf::from(path), false),
+        Err(_) => (PathBuf::from(FIXTURES), true),
+    };
+    let mut files = vec![];
+    collect_json_files(&root.join(suite), &mut files);
+    files.sort();
+
+    let mut summary = Summary::default();
+    for file in files {
+        let contents = fs::read_to_string(&file).expect("fixture files are readable");
+        let tests: Value = match serde_json::from_str(&contents) {
+            Ok(tests) => tests,
+            Err(e) => {
+                summary.record("-", &file.display().to_string(), Verdict::Fail(e.to_string()));
+                continue;
+            }
+        };
+        for (name, test) in object(&tests) {
+            run(name, test, &mut summary);
+        }
+    }
+    summary.print(suite);
+    if vendored {
+        assert!(
+            summary.failures.is_empty(),
+            "{} of the vendored fixtures failed",
+            summary.failures.len()
+        );
+        assert!(summary.skips.is_empty(), "vendored fixtures were skipped");
+        assert!(!summary.counts.is_empty(), "no fixtures found under {}", root.display());
+    }
+}
+
+fn collect_json_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_json_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "json") {
+            files.push(path);
+        }
+    }
+}
+
+/// Runs a VMTest: the code of a single account, executed with the given gas and environment
+fn run_vm_test(test: &Value) -> Verdict {
+    if test["callcreates"].as_array().is_some_and(|calls| !calls.is_empty()) {
+        return Verdict::Skip("expects calls and creates to be recorded rather than executed".to_string());
+    }
+    let exec = &test["exec"];
+    let env = &test["env"];
+    let transaction = TransactionContext {
+        origin: address(&exec["origin"]),
+        caller: address(&exec["caller"]),
+        value: u256(&exec["value"]),
+        gas_price: u256(&exec["gasPrice"]),
+    };
+    let mut vm = VM::new(bytes(&exec["code"]))
+        .with_fork(VM_TESTS_FORK)
+        .with_address(address(&exec["address"]))
+        .with_host(pre_state(&test["pre"]))
+        .with_calldata(bytes(&exec["data"]))
+        .with_gas_limit(u256(&exec["gas"]).low_u64())
+        .with_environment(Environment::new(transaction, block_context(env, VM_TESTS_FORK)));
+    let result = vm.execute();
+
+    let post = match test.get("post") {
+        Some(post) => post,
+        None if result.is_success() => return Verdict::Fail("expected execution to fail".to_string()),
+        None => return Verdict::Pass,
+    };
+    if !result.is_success() {
+        return Verdict::Fail(format!("execution failed: {:?}", result.outcome));
+    }
+    let checks = [
+        check("output", bytes(&test["out"]), result.output().to_vec()),
+        check("gas left", u256(&test["gas"]).low_u64(), vm.gas_remaining()),
+        check("logs hash", h256(&test["logs"]), logs_hash(&result.logs)),
+        check_post_state(post, vm.host()),
+    ];
+    match checks.iter().find(|check| check.is_err()) {
+        Some(Err(reason)) => Verdict::Fail(reason.clone()),
+        _ => Verdict::Pass,
+    }
+}
+
+/// Runs one entry of a GeneralStateTest: a transaction with the data, gas and value it indexes,
+/// checked against the state root and logs hash it should lead to
+fn run_state_test(test: &Value, fork: Fork, entry: &Value) -> Verdict {
+    let transaction = &test["transaction"];
+    let indexes = &entry["indexes"];
+    let index = |field: &str| indexes[field].as_u64().expect("indexes are numbers") as usize;
+    let data = bytes(&transaction["data"][index("data")]);
+    let gas_limit = u256(&transaction["gasLimit"][index("gas")]);
+    let value = u256(&transaction["value"][index("value")]);
+    let to = match transaction["to"].as_str() {
+        Some(to) if !to.is_empty() => address(&transaction["to"]),
+        _ => return Verdict::Skip("contract creation transactions are not supported".to_string()),
+    };
+    if transaction.get("gasPrice").is_none() || entry.get("expectException").is_some() {
+        return Verdict::Skip("only valid legacy transactions are supported".to_string());
+    }
+    if precompiles::is_precompile(to, fork) {
+        return Verdict::Skip("transactions to precompiled contracts are not supported".to_string());
+    }
+
+    let (state, result) = match execute_transaction(test, fork, to, data, gas_limit, value) {
+        Ok(executed) => executed,
+        Err(reason) => return Verdict::Fail(reason),
+    };
+    let checks = [
+        check("state root", h256(&entry["hash"]), state.root()),
+        check("logs hash", h256(&entry["logs"]), logs_hash(&result.logs)),
+    ];
+    match checks.iter().find(|check| check.is_err()) {
+        Some(Err(reason)) => Verdict::Fail(format!("{} (outcome {:?})", reason, result.outcome)),
+        _ => Verdict::Pass,
+    }
+}
+
+/// Buys the gas for a transaction, executes it, then pays back unused gas and pays the coinbase.
+/// Returns the state afterwards and the result of execution.
+fn execute_transaction(
+    test: &Value,
+    fork: Fork,
+    to: Address,
+    data: Vec<u8>,
+    gas_limit: U256,
+    value: U256,
+) -> Result<(WorldState<MemoryTrieMut>, ExecutionResult), String> {
+    let env = &test["env"];
+    let transaction = &test["transaction"];
+    let mut state = pre_state(&test["pre"]);
+    let sender = sender(&bytes(&transaction["secretKey"]));
+    let coinbase = address(&env["currentCoinbase"]);
+    let gas_price = u256(&transaction["gasPrice"]);
+    let base_fee = if fork >= Fork::London {
+        u256(&env["currentBaseFee"])
+    } else {
+        U256::zero()
+    };
+
+    let intrinsic_gas = intrinsic_gas(&data, fork);
+    let upfront = gas_limit * gas_price;
+    if u256(&transaction["nonce"]) != state.nonce(sender)
+        || U256::from(intrinsic_gas) > gas_limit
+        || gas_price < base_fee
+        || state.balance(sender) < upfront + value
+    {
+        return Err("the transaction is invalid, but the test does not expect an exception".to_string());
+    }
+    let nonce = state.nonce(sender);
+    state.set_nonce(sender, nonce + U256::one());
+    let balance = state.balance(sender);
+    state.set_balance(sender, balance - upfront);
+    state.transfer(sender, to, value);
+
+    let transaction_context = TransactionContext {
+        origin: sender,
+        caller: sender,
+        value,
+        gas_price,
+    };
+    let mut vm = VM::new(state.code(to))
+        .with_fork(fork)
+        .with_address(to)
+        .with_host(state)
+        .with_calldata(data)
+        .with_gas_limit(gas_limit.low_u64() - intrinsic_gas)
+        .with_environment(Environment::new(transaction_context, block_context(env, fork)));
+    let result = vm.execute();
+    let mut state = vm.host().clone();
+    if !result.is_success() {
+        // The VM only undoes what it did itself, so the transfer made before it ran is undone here
+        state.transfer(to, sender, value);
+    }
+
+    // The VM caps the refund by the gas execution used, but it is capped by that of the whole
+    // transaction
+    let total_gas = intrinsic_gas + result.gas_used + result.gas_refunded;
+    let refund = if result.is_success() {
+        let quotient = if fork >= Fork::London { 5 } else { 2 };
+        (vm.gas_refund().max(0) as u64).min(total_gas / quotient)
+    } else {
+        0
+    };
+    let gas_used = U256::from(total_gas - refund);
+    let balance = state.balance(sender);
+    state.set_balance(sender, balance + (gas_limit - gas_used) * gas_price);
+    let balance = state.balance(coinbase);
+    state.set_balance(coinbase, balance + gas_used * (gas_price - base_fee));
+
+    // EIP-161: the VM removes the accounts it touched and left empty. The value and gas moved here
+    // touch the sender, the recipient and the coinbase outside of it, so those are removed here.
+    if fork >= Fork::SpuriousDragon {
+        for address in &[sender, to, coinbase] {
+            if state.is_empty(*address) {
+                state.remove_account(*address);
+            }
+        }
+    }
+    Ok((state, result))
+}
+
+/// Returns the gas a transaction costs before any of its code runs
+fn intrinsic_gas(data: &[u8], fork: Fork) -> u64 {
+    let nonzero_cost = if fork >= Fork::Istanbul { 16 } else { 68 };
+    data.iter()
+        .fold(21000, |gas, &byte| gas + if byte == 0 { 4 } else { nonzero_cost })
+}
+
+/// Returns the address of the account that signs with `secret_key`
+fn sender(secret_key: &[u8]) -> Address {
+    let secret_key = SecretKey::from_slice(secret_key).expect("test secret keys are valid");
+    let public_key = PublicKey::from_secret_key(SECP256K1, &secret_key).serialize_uncompressed();
+    let hash = keccak_hash::keccak(&public_key[1..]);
+    Address::from(&hash.0[12..])
+}
+
+fn block_context(env: &Value, fork: Fork) -> BlockContext {
+    BlockContext {
+        coinbase: address(&env["currentCoinbase"]),
+        timestamp: u256(&env["currentTimestamp"]),
+        number: u256(&env["currentNumber"]),
+        difficulty: u256(&env["currentDifficulty"]),
+        gas_limit: u256(&env["currentGasLimit"]),
+        chain_id: U256::one(),
+        base_fee: if fork >= Fork::London {
+            u256(&env["currentBaseFee"])
+        } else {
+            U256::zero()
+        },
+    }
+}
+
+/// Builds the state described by the `pre` section of a test
+fn pre_state(pre: &Value) -> WorldState<MemoryTrieMut> {
+    let mut state = WorldState::new(MemoryTrieMut::default());
+    for (address_hex, account) in object(pre) {
+        let address = address(&Value::String(address_hex.clone()));
+        state.set_balance(address, u256(&account["balance"]));
+        state.set_nonce(address, u256(&account["nonce"]));
+        state.set_code(address, bytes(&account["code"]));
+        for (index, value) in object(&account["storage"]) {
+            let index = u256(&Value::String(index.clone()));
+            state
+                .set_storage(address, index, M256::from(u256(value)))
+                .expect("the pre-state can be stored");
+        }
+    }
+    state.commit_storage();
+    state
+}
+
+/// Compares every account of the `post` section of a VMTest with `state`
+fn check_post_state(post: &Value, state: &WorldState<MemoryTrieMut>) -> Result<(), String> {
+    for (address_hex, expected) in object(post) {
+        let address = address(&Value::String(address_hex.clone()));
+        check(
+            &format!("balance of {}", address_hex),
+            u256(&expected["balance"]),
+            state.balance(address),
+        )?;
+        check(
+            &format!("nonce of {}", address_hex),
+            u256(&expected["nonce"]),
+            state.nonce(address),
+        )?;
+        check(
+            &format!("code of {}", address_hex),
+            bytes(&expected["code"]),
+            state.code(address),
+        )?;
+        check(
+            &format!("storage root of {}", address_hex),
+            storage_root(&expected["storage"]),
+            state.storage_root(address),
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the root of a storage trie holding the slots of a `storage` section, which is how the
+/// storage of an account is compared with what a test expects
+fn storage_root(storage: &Value) -> H256 {
+    let mut slots = HashMap::new();
+    for (index, value) in object(storage) {
+        let index = u256(&Value::String(index.clone()));
+        let value = u256(value);
+        if !value.is_zero() {
+            slots.insert(keccak(&H256::from(index)).to_vec(), rlp::encode(&value).to_vec());
+        }
+    }
+    trie::build(&slots).0
+}
+
+/// Returns the hash of the RLP encoded list of logs, as the tests record them
+fn logs_hash(logs: &[Log]) -> H256 {
+    keccak(&rlp::encode_list::<Log, Log>(logs))
+}
+
+fn keccak(data: &[u8]) -> H256 {
+    H256::from(&*keccak_hash::keccak(data))
+}
+
+fn check<T: PartialEq + std::fmt::Debug>(what: &str, expected: T, actual: T) -> Result<(), String> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!("{} is {:?}, expected {:?}", what, actual, expected))
+    }
+}
+
+fn object(value: &Value) -> Vec<(&String, &Value)> {
+    value.as_object().map(|map| map.iter().collect()).unwrap_or_default()
+}
+
+/// Parses a number given as 0x prefixed hex or as decimal
+fn u256(value: &Value) -> U256 {
+    let text = value.as_str().expect("numbers are given as strings");
+    match text.strip_prefix("0x") {
+        Some("") => U256::zero(),
+        Some(hex) => U256::from_str(hex).expect("hex numbers are valid"),
+        None => U256::from_dec_str(text).expect("decimal numbers are valid"),
+    }
+}
+
+fn bytes(value: &Value) -> Vec<u8> {
+    let text = value.as_str().expect("bytes are given as strings");
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+    hex.from_hex().expect("bytes are given as hex")
+}
+
+fn address(value: &Value) -> Address {
+    Address::from(&bytes(value)[..])
+}
+
+fn h256(value: &Value) -> H256 {
+    H256::from(&bytes(value)[..])
+}
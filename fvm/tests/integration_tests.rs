@@ -1,4 +1,117 @@
+extern crate bigint;
 extern crate fvm;
+extern crate rustc_hex;
+
+use bigint::Address;
+use fvm::disassembler;
+use fvm::host::Host;
+use fvm::outcome::Outcome;
+use fvm::state::State;
+use fvm::vm::VM;
+use rustc_hex::FromHex;
+use std::fs;
+
+const GREETER_BIN: &str = "../docs/solc/contracts/outputDirectory/Greeter.bin";
+
+/// ABI encodes a single string argument
+fn encode_string(value: &str) -> Vec<u8> {
+    let mut encoded = vec![0u8; 64];
+    encoded[31] = 0x20;
+    encoded[63] = value.len() as u8;
+    encoded.extend_from_slice(value.as_bytes());
+    encoded.resize(64 + value.len().div_ceil(32) * 32, 0);
+    encoded
+}
+
+/// Runs the Greeter constructor and returns the state with the runtime code it deploys installed
+/// at `address`
+fn deploy_greeter(address: Address) -> State {
+    let mut code: Vec<u8> = fs::read_to_string(GREETER_BIN).unwrap().from_hex().unwrap();
+    code.extend_from_slice(&encode_string("Hello"));
+    let mut vm = VM::new(code).with_simple_memory().with_address(address);
+    match vm.execute().outcome {
+        Outcome::Return(runtime) => {
+            let mut state = vm.host().clone();
+            state.set_code(address, runtime);
+            state
+        }
+        outcome => panic!("Greeter constructor did not return: {:?}", outcome),
+    }
+}
 
 #[test]
 fn runs_hello_world() {}
+
+#[test]
+fn deploys_greeter() {
+    let address = Address::random();
+    let state = deploy_greeter(address);
+    let runtime = state.code(address);
+    assert_eq!(&runtime[..5], &[0x60, 0x80, 0x60, 0x40, 0x52]);
+}
+
+#[test]
+fn dispatches_greeter_selector() {
+    let address = Address::random();
+    let state = deploy_greeter(address);
+    // greet()
+    let mut vm = VM::new(state.code(address))
+        .with_simple_memory()
+        .with_address(address)
+        .with_host(state)
+        .with_calldata(vec![0xcf, 0xae, 0x32, 0x17]);
+    let result = vm.execute();
+    assert_eq!(result.outcome, Outcome::Return(encode_string("Hello")));
+}
+
+#[test]
+fn calls_greeter_from_another_contract() {
+    let greeter = Address::random();
+    let state = deploy_greeter(greeter);
+    // Stores the greet() selector at memory 0
+    let mut code = vec![0x7f, 0xcf, 0xae, 0x32, 0x17];
+    code.extend_from_slice(&[0; 28]);
+    code.extend_from_slice(&[0x60, 0x00, 0x52]);
+    // CALL(gas, greeter, 0, 0, 4, 0, 0)
+    code.extend_from_slice(&[0x60, 0x00, 0x60, 0x00, 0x60, 0x04, 0x60, 0x00, 0x60, 0x00, 0x73]);
+    code.extend_from_slice(&greeter);
+    code.extend_from_slice(&[0x5a, 0xf1, 0x50]);
+    // Returns whatever the greeter returned
+    code.extend_from_slice(&[0x3d, 0x60, 0x00, 0x60, 0x00, 0x3e, 0x3d, 0x60, 0x00, 0xf3]);
+    let mut vm = VM::new(code).with_random_address().with_host(state);
+    let result = vm.execute();
+    assert_eq!(result.outcome, Outcome::Return(encode_string("Hello")));
+}
+
+#[test]
+fn rejects_unknown_selector() {
+    let address = Address::random();
+    let state = deploy_greeter(address);
+    let mut vm = VM::new(state.code(address))
+        .with_simple_memory()
+        .with_address(address)
+        .with_host(state)
+        .with_calldata(vec![0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(vm.execute().outcome, Outcome::Revert(vec![]));
+}
+
+#[test]
+fn disassembles_greeter() {
+    let code: Vec<u8> = fs::read_to_string(GREETER_BIN).unwrap().from_hex().unwrap();
+    let listing = disassembler::listing(&code);
+    let lines: Vec<&str> = listing.lines().take(5).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "00000: PUSH1 0x80",
+            "00002: PUSH1 0x40",
+            "00004: MSTORE",
+            "00005: CALLVALUE",
+            "00006: DUP1"
+        ]
+    );
+    let instructions = disassembler::disassemble(&code);
+    let last = instructions.last().unwrap();
+    // Every byte is accounted for, though the metadata solc appends may end in a cut off PUSH
+    assert_eq!(last.offset + 1 + last.immediate.len(), code.len());
+}
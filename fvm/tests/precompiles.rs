@@ -0,0 +1,104 @@
+//! Runs the precompiled contracts against the test vectors under `tests/fixtures/precompiles`, which
+//! are kept in the format of go-ethereum's `core/vm/testdata/precompiles`. Files named `fail-*`
+//! hold inputs the contract must reject.
+//!
+//! The modexp, bn256 and ecRecover vectors are those of go-ethereum and of the Ethereum tests, as
+//! reproduced in the tests of revm-precompile 43.0.3 and aurora-engine-precompiles 2.2.0. The
+//! blake2F vectors are those of EIP-152. Gas is that of the fork each file is run under.
+
+extern crate bigint;
+extern crate fvm;
+extern crate rustc_hex;
+extern crate serde_json;
+
+use bigint::Address;
+use fvm::errors::VMError;
+use fvm::fork::Fork;
+use fvm::precompiles;
+use rustc_hex::FromHex;
+use serde_json::Value;
+use std::fs;
+
+const FIXTURES: &str = "tests/fixtures/precompiles";
+
+// Enough for every vector, so that only the price is checked
+const GAS: u64 = 100_000_000;
+
+#[test]
+fn ecrecover() {
+    run_vectors("ecRecover.json", 1, Fork::latest());
+}
+
+#[test]
+fn modexp() {
+    run_vectors("modexp.json", 5, Fork::Byzantium);
+}
+
+#[test]
+fn modexp_eip2565() {
+    run_vectors("modexp_eip2565.json", 5, Fork::Berlin);
+}
+
+#[test]
+fn bn256_add() {
+    run_vectors("bn256Add.json", 6, Fork::Istanbul);
+    run_failures("fail-bn256Add.json", 6, Fork::Istanbul);
+}
+
+#[test]
+fn bn256_scalar_mul() {
+    run_vectors("bn256ScalarMul.json", 7, Fork::Istanbul);
+    run_failures("fail-bn256ScalarMul.json", 7, Fork::Istanbul);
+}
+
+#[test]
+fn bn256_pairing() {
+    run_vectors("bn256Pairing.json", 8, Fork::Istanbul);
+    run_failures("fail-bn256Pairing.json", 8, Fork::Istanbul);
+}
+
+#[test]
+fn blake2f() {
+    run_vectors("blake2F.json", 9, Fork::Istanbul);
+    run_failures("fail-blake2f.json", 9, Fork::Istanbul);
+}
+
+/// Checks the output and gas of the precompiled contract `number` for every vector in `file`
+fn run_vectors(file: &str, number: u64, fork: Fork) {
+    for vector in load(file) {
+        let name = vector["Name"].as_str().expect("vectors are named");
+        let (gas, output) = precompiles::execute(Address::from(number), &bytes(&vector["Input"]), GAS, fork)
+            .unwrap_or_else(|e| panic!("{} in {} failed: {}", name, file, e));
+        assert_eq!(output, bytes(&vector["Expected"]), "output of {} in {}", name, file);
+        assert_eq!(Some(gas), vector["Gas"].as_u64(), "gas of {} in {}", name, file);
+    }
+}
+
+/// Checks that the precompiled contract `number` fails on every vector in `file`
+fn run_failures(file: &str, number: u64, fork: Fork) {
+    for vector in load(file) {
+        let name = vector["Name"].as_str().expect("vectors are named");
+        let result = precompiles::execute(Address::from(number), &bytes(&vector["Input"]), GAS, fork);
+        assert_eq!(
+            result,
+            Err(VMError::PrecompileFailure),
+            "{} in {} should fail: {}",
+            name,
+            file,
+            vector["ExpectedError"]
+        );
+    }
+}
+
+fn load(file: &str) -> Vec<Value> {
+    let contents = fs::read_to_string(format!("{}/{}", FIXTURES, file)).expect("vector files are readable");
+    let vectors: Value = serde_json::from_str(&contents).expect("vector files are valid JSON");
+    let vectors = vectors.as_array().expect("vector files hold a list").clone();
+    assert!(!vectors.is_empty(), "no vectors in {}", file);
+    vectors
+}
+
+fn bytes(value: &Value) -> Vec<u8> {
+    let hex = value.as_str().expect("bytes are given as hex strings");
+    hex.from_hex().expect("bytes are given as hex")
+}
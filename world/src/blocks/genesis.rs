@@ -4,8 +4,9 @@ use std::io::prelude::*;
 use std::path::PathBuf;
 use std::str::FromStr;
 
-use bigint::{B256, H256, H64};
+use bigint::{B256, H256, H64, U256};
 use bloom;
+use fvm::fork::{Fork, ForkSchedule};
 
 /// Genesis block data structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -34,19 +35,68 @@ pub struct GenesisAlloc {
     balance: String,
 }
 
+/// Chain parameters, including the block numbers at which each fork activates. Forks after Spurious
+/// Dragon are optional, and never activate if they are left out.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GenesisConfig {
     #[serde(rename = "chainId")]
     chain_id: u64,
     #[serde(rename = "homesteadBlock")]
     homestead_block: u64,
+    #[serde(rename = "eip150Block", default)]
+    eip150_block: Option<u64>,
     #[serde(rename = "eip155Block")]
     eip155_block: u64,
     #[serde(rename = "eip158Block")]
     eip158_block: u64,
+    #[serde(rename = "byzantiumBlock", default)]
+    byzantium_block: Option<u64>,
+    #[serde(rename = "constantinopleBlock", default)]
+    constantinople_block: Option<u64>,
+    #[serde(rename = "istanbulBlock", default)]
+    istanbul_block: Option<u64>,
+    #[serde(rename = "berlinBlock", default)]
+    berlin_block: Option<u64>,
+    #[serde(rename = "londonBlock", default)]
+    london_block: Option<u64>,
+}
+
+impl GenesisConfig {
+    /// Returns the identifier of the chain
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Returns the schedule of forks the chain follows. Tangerine Whistle activates at the EIP-158
+    /// block if no EIP-150 block is given, since Spurious Dragon builds on it.
+    pub fn fork_schedule(&self) -> ForkSchedule {
+        let later_forks = [
+            (Fork::Byzantium, self.byzantium_block),
+            (Fork::Constantinople, self.constantinople_block),
+            (Fork::Istanbul, self.istanbul_block),
+            (Fork::Berlin, self.berlin_block),
+            (Fork::London, self.london_block),
+        ];
+        let tangerine_whistle_block = self.eip150_block.unwrap_or(self.eip158_block);
+        let mut schedule = ForkSchedule::new()
+            .with_fork(Fork::Homestead, U256::from(self.homestead_block))
+            .with_fork(Fork::TangerineWhistle, U256::from(tangerine_whistle_block))
+            .with_fork(Fork::SpuriousDragon, U256::from(self.eip158_block));
+        for &(fork, block) in later_forks.iter() {
+            if let Some(block) = block {
+                schedule = schedule.with_fork(fork, U256::from(block));
+            }
+        }
+        schedule
+    }
 }
 
 impl Genesis {
+    /// Returns the chain parameters of the genesis block
+    pub fn config(&self) -> &GenesisConfig {
+        &self.config
+    }
+
     /// Attempts to load a Genesis block from a json file
     pub fn load(path: PathBuf) -> Result<Box<Genesis>, serde_json::Error> {
         match File::open(path) {
@@ -88,3 +138,55 @@ impl Into<block::Header> for Genesis {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fvm::environment::{BlockContext, Environment};
+    use fvm::vm::VM;
+
+    #[test]
+    fn fork_schedule_from_template() {
+        let genesis = Genesis::load(PathBuf::from("../templates/genesis.json")).unwrap();
+        let schedule = genesis.config().fork_schedule();
+        assert_eq!(genesis.config().chain_id(), 1983);
+        assert_eq!(schedule.fork_at(U256::zero()), Fork::Byzantium);
+    }
+
+    #[test]
+    fn later_forks_are_optional() {
+        let config: GenesisConfig = serde_json::from_str(
+            r#"{"chainId": 1, "homesteadBlock": 5, "eip155Block": 10, "eip158Block": 10, "londonBlock": 20}"#,
+        )
+        .unwrap();
+        let schedule = config.fork_schedule();
+        assert_eq!(schedule.fork_at(4.into()), Fork::Frontier);
+        assert_eq!(schedule.fork_at(5.into()), Fork::Homestead);
+        assert_eq!(schedule.fork_at(19.into()), Fork::SpuriousDragon);
+        assert_eq!(schedule.fork_at(20.into()), Fork::London);
+    }
+
+    #[test]
+    fn vm_follows_fork_schedule() {
+        let config: GenesisConfig = serde_json::from_str(
+            r#"{"chainId": 1, "homesteadBlock": 0, "eip155Block": 0, "eip158Block": 0, "londonBlock": 20}"#,
+        )
+        .unwrap();
+        // BASEFEE, which London adds
+        let execute_at = |number: u64| {
+            let environment = Environment {
+                block: BlockContext {
+                    number: number.into(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            VM::new(vec![0x48, 0x00])
+                .with_fork_schedule(config.fork_schedule())
+                .with_environment(environment)
+                .execute()
+        };
+        assert!(!execute_at(19).is_success());
+        assert!(execute_at(20).is_success());
+    }
+}
@@ -19,4 +19,5 @@ pub mod blocks;
 pub mod chain;
 pub mod consensus;
 pub mod db;
+pub mod state;
 pub mod transactions;
\ No newline at end of file
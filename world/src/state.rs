@@ -0,0 +1,311 @@
+//! Contains the world state the VM executes against when it runs on a node
+
+use bigint::{Address, H256, M256, U256};
+use fvm::errors::StorageError;
+use fvm::eth_log::Log;
+use fvm::host::Host;
+use rlp::{self, Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+use sha3::{Digest, Keccak256};
+use std::collections::HashMap;
+use trie::{empty_trie_hash, TrieMut};
+
+/// An account as it is stored in the world state, which is how Ethereum stores it. The code and
+/// storage slots are stored separately, under the code hash and in a storage trie of its own.
+#[derive(Default, Debug, Clone, PartialEq)]
+struct AccountRecord {
+    nonce: U256,
+    balance: U256,
+    storage_root: H256,
+    code_hash: H256,
+}
+
+impl Encodable for AccountRecord {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(4);
+        s.append(&self.nonce);
+        s.append(&self.balance);
+        s.append(&self.storage_root);
+        s.append(&self.code_hash);
+    }
+}
+
+impl Decodable for AccountRecord {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(Self {
+            nonce: rlp.val_at(0)?,
+            balance: rlp.val_at(1)?,
+            storage_root: rlp.val_at(2)?,
+            code_hash: rlp.val_at(3)?,
+        })
+    }
+}
+
+/// A Host backed by tries, laid out as Ethereum lays out its state so that `root` can be compared
+/// with the state root of other clients. Accounts are stored in the state trie under the hash of
+/// their address, and the storage slots of each account in a trie of its own under the hash of
+/// the slot index. Code is stored apart from both, under its hash.
+#[derive(Clone)]
+pub struct WorldState<T: TrieMut> {
+    trie: T,
+    storage: HashMap<Address, T>,
+    code: T,
+    block_hashes: HashMap<U256, H256>,
+    logs: Vec<Log>,
+    /// Values that slots written in the current transaction held before their first write
+    original_storage: HashMap<(Address, U256), M256>,
+}
+
+impl<T: TrieMut + Default> WorldState<T> {
+    /// Creates and returns a new WorldState with `trie` as its state trie
+    pub fn new(trie: T) -> WorldState<T> {
+        WorldState {
+            trie,
+            storage: HashMap::new(),
+            code: T::default(),
+            block_hashes: HashMap::new(),
+            logs: vec![],
+            original_storage: HashMap::new(),
+        }
+    }
+
+    /// Records `hash` as the hash of the block at height `number`
+    pub fn insert_block_hash(&mut self, number: U256, hash: H256) {
+        self.block_hashes.insert(number, hash);
+    }
+
+    /// Returns the logs of every successful execution against this state, in order
+    pub fn logs(&self) -> &[Log] {
+        &self.logs
+    }
+
+    /// Returns the state trie
+    pub fn trie(&self) -> &T {
+        &self.trie
+    }
+
+    /// Returns the root of the state trie
+    pub fn root(&self) -> H256 {
+        self.trie.root()
+    }
+
+    /// Returns the root of the storage trie of the account at `address`, which is that of an
+    /// empty trie if the account has no storage or does not exist
+    pub fn storage_root(&self, address: Address) -> H256 {
+        self.account(address)
+            .map(|a| a.storage_root)
+            .unwrap_or_else(empty_trie_hash)
+    }
+
+    fn account(&self, address: Address) -> Option<AccountRecord> {
+        self.trie.get(&keccak(&address)).map(|bytes| rlp::decode(&bytes))
+    }
+
+    fn update_account<F>(&mut self, address: Address, update: F)
+    where
+        F: FnOnce(&mut AccountRecord),
+    {
+        let mut account = self.account(address).unwrap_or_else(|| AccountRecord {
+            storage_root: empty_trie_hash(),
+            code_hash: keccak(&[]),
+            ..Default::default()
+        });
+        update(&mut account);
+        self.trie.insert(&keccak(&address), &rlp::encode(&account));
+    }
+}
+
+/// Returns the Keccak-256 hash of `data`
+fn keccak(data: &[u8]) -> H256 {
+    let mut hasher = Keccak256::default();
+    hasher.input(data);
+    H256::from(hasher.result().as_slice())
+}
+
+/// Returns the key a storage slot is stored under in the storage trie of its account
+fn storage_key(index: U256) -> H256 {
+    keccak(&H256::from(index))
+}
+
+impl<T: TrieMut + Default> Host for WorldState<T> {
+    fn exists(&self, address: Address) -> bool {
+        self.account(address).is_some()
+    }
+
+    fn balance(&self, address: Address) -> U256 {
+        self.account(address).map(|a| a.balance).unwrap_or_default()
+    }
+
+    fn nonce(&self, address: Address) -> U256 {
+        self.account(address).map(|a| a.nonce).unwrap_or_default()
+    }
+
+    fn code(&self, address: Address) -> Vec<u8> {
+        self.account(address)
+            .and_then(|a| self.code.get(&a.code_hash))
+            .unwrap_or_default()
+    }
+
+    fn code_hash(&self, address: Address) -> H256 {
+        self.account(address).map(|a| a.code_hash).unwrap_or_default()
+    }
+
+    fn storage(&self, address: Address, index: U256) -> M256 {
+        let value = self
+            .storage
+            .get(&address)
+            .and_then(|trie| trie.get(&storage_key(index)));
+        match value {
+            Some(bytes) => M256::from(rlp::decode::<U256>(&bytes)),
+            None => M256::zero(),
+        }
+    }
+
+    fn original_storage(&self, address: Address, index: U256) -> M256 {
+        match self.original_storage.get(&(address, index)) {
+            Some(&value) => value,
+            None => self.storage(address, index),
+        }
+    }
+
+    fn set_storage(&mut self, address: Address, index: U256, value: M256) -> Result<(), StorageError> {
+        if !self.original_storage.contains_key(&(address, index)) {
+            let current = self.storage(address, index);
+            self.original_storage.insert((address, index), current);
+        }
+        let storage_root = {
+            let trie = self.storage.entry(address).or_default();
+            let key = storage_key(index);
+            if value == M256::zero() {
+                trie.delete(&key);
+            } else {
+                let value: U256 = value.into();
+                trie.insert(&key, &rlp::encode(&value));
+            }
+            trie.root()
+        };
+        self.update_account(address, |a| a.storage_root = storage_root);
+        Ok(())
+    }
+
+    fn commit_storage(&mut self) {
+        self.original_storage.clear();
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.update_account(address, |a| a.balance = balance);
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: U256) {
+        self.update_account(address, |a| a.nonce = nonce);
+    }
+
+    fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        let code_hash = keccak(&code);
+        if !code.is_empty() {
+            self.code.insert(&code_hash, &code);
+        }
+        self.update_account(address, |a| a.code_hash = code_hash);
+    }
+
+    fn remove_account(&mut self, address: Address) {
+        self.trie.delete(&keccak(&address));
+        self.storage.remove(&address);
+    }
+
+    fn block_hash(&self, number: U256) -> H256 {
+        self.block_hashes.get(&number).cloned().unwrap_or_default()
+    }
+
+    fn log(&mut self, log: Log) {
+        self.logs.push(log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fvm::outcome::Outcome;
+    use fvm::vm::VM;
+    use trie::MemoryTrieMut;
+
+    #[test]
+    fn stores_accounts_in_trie() {
+        let mut state = WorldState::new(MemoryTrieMut::default());
+        let address = Address::random();
+        assert!(!state.exists(address));
+        state.set_balance(address, 100.into());
+        state.set_code(address, vec![0x00]);
+        assert!(state.exists(address));
+        assert_eq!(state.balance(address), U256::from(100));
+        assert_eq!(state.code(address), vec![0x00]);
+        assert_eq!(state.code_hash(address), keccak(&[0x00]));
+    }
+
+    #[test]
+    fn clearing_storage_deletes_slot() {
+        let mut state = WorldState::new(MemoryTrieMut::default());
+        let address = Address::random();
+        assert!(state.set_storage(address, 1.into(), 5.into()).is_ok());
+        assert_eq!(state.storage(address, 1.into()), M256::from(5));
+        assert_ne!(state.storage_root(address), empty_trie_hash());
+        assert!(state.set_storage(address, 1.into(), M256::zero()).is_ok());
+        assert_eq!(state.storage(address, 1.into()), M256::zero());
+        assert_eq!(state.storage_root(address), empty_trie_hash());
+    }
+
+    #[test]
+    fn accounts_are_keyed_by_address_hash() {
+        let mut state = WorldState::new(MemoryTrieMut::default());
+        let address = Address::random();
+        state.set_nonce(address, 1.into());
+        assert!(state.set_storage(address, 2.into(), 3.into()).is_ok());
+        assert!(state.trie().get(&address).is_none());
+        let account: AccountRecord = rlp::decode(&state.trie().get(&keccak(&address)).unwrap());
+        assert_eq!(account.nonce, U256::from(1));
+        assert_eq!(account.storage_root, state.storage_root(address));
+        assert_eq!(account.code_hash, keccak(&[]));
+    }
+
+    #[test]
+    fn removing_account_removes_its_storage() {
+        let mut state = WorldState::new(MemoryTrieMut::default());
+        let address = Address::random();
+        assert!(state.set_storage(address, 1.into(), 5.into()).is_ok());
+        state.remove_account(address);
+        assert!(!state.exists(address));
+        // An account created again at the same address, as CREATE2 can, starts out empty
+        state.set_nonce(address, 1.into());
+        assert_eq!(state.storage(address, 1.into()), M256::zero());
+        assert_eq!(state.storage_root(address), empty_trie_hash());
+    }
+
+    #[test]
+    fn original_storage_survives_until_commit() {
+        let mut state = WorldState::new(MemoryTrieMut::default());
+        let address = Address::random();
+        assert!(state.set_storage(address, 1.into(), 5.into()).is_ok());
+        state.commit_storage();
+        assert!(state.set_storage(address, 1.into(), M256::zero()).is_ok());
+        assert_eq!(state.original_storage(address, 1.into()), M256::from(5));
+        state.commit_storage();
+        assert_eq!(state.original_storage(address, 1.into()), M256::zero());
+    }
+
+    #[test]
+    fn vm_executes_against_world_state() {
+        let address = Address::random();
+        let mut state = WorldState::new(MemoryTrieMut::default());
+        // Stores 0x2a in slot 0 and returns the balance of the contract
+        let code = vec![
+            0x60, 0x2a, 0x60, 0x00, 0x55, 0x30, 0x31, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+        ];
+        state.set_code(address, code.clone());
+        state.set_balance(address, 7.into());
+        let mut vm = VM::new(code).with_address(address).with_host(state);
+        let result = vm.execute();
+        let mut expected = vec![0u8; 31];
+        expected.push(7);
+        assert_eq!(result.outcome, Outcome::Return(expected));
+        assert_eq!(vm.host().storage(address, 0.into()), M256::from(0x2a));
+    }
+}